@@ -0,0 +1,212 @@
+//! Provides parsing of the ACPI tables the firmware describes the
+//! platform with.
+//!
+//! Everything here works on tables that have already been read into
+//! memory, so that it can be built and tested on the host. Finding the
+//! tables in physical memory is left to the boot stub and kernel.
+//!
+//! For more details see the ACPI specification, section 5.2.
+#![cfg_attr(not(test), no_std)]
+
+use core::convert::TryInto;
+
+mod madt;
+pub use madt::*;
+
+/// The size of the header every system description table starts with.
+pub const TABLE_HEADER_SIZE: usize = 36;
+
+/// The ways in which a table can be invalid.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TableError {
+    /// The table claims to be smaller than its header, or larger than
+    /// the bytes it was read from, or too small for its kind of table.
+    InvalidLength,
+
+    /// The bytes of the table with the given signature don't sum to
+    /// zero.
+    InvalidChecksum([u8; 4]),
+
+    /// The table has the given signature, rather than the one expected.
+    UnexpectedSignature([u8; 4]),
+}
+
+/// A system description table, whose length and checksum have been
+/// checked.
+///
+/// The header is laid out as follows:
+///
+/// | Bytes | Meaning          |
+/// |-------|------------------|
+/// | 0-3   | Signature        |
+/// | 4-7   | Length           |
+/// | 8     | Revision         |
+/// | 9     | Checksum         |
+/// | 10-15 | OEM ID           |
+/// | 16-23 | OEM table ID     |
+/// | 24-27 | OEM revision     |
+/// | 28-31 | Creator ID       |
+/// | 32-35 | Creator revision |
+#[derive(Copy, Clone)]
+pub struct SystemDescriptionTable<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> SystemDescriptionTable<'a> {
+    /// Checks the table at the start of the given bytes, which may go
+    /// on beyond the end of the table.
+    pub fn new(bytes: &'a [u8]) -> Result<Self, TableError> {
+        if bytes.len() < TABLE_HEADER_SIZE {
+            return Err(TableError::InvalidLength);
+        }
+
+        let length = read_u32(bytes, 4) as usize;
+
+        if length < TABLE_HEADER_SIZE || length > bytes.len() {
+            return Err(TableError::InvalidLength);
+        }
+
+        let table = Self {
+            bytes: &bytes[..length],
+        };
+
+        if checksum(table.bytes) != 0 {
+            return Err(TableError::InvalidChecksum(table.signature()));
+        }
+
+        Ok(table)
+    }
+
+    pub fn signature(&self) -> [u8; 4] {
+        self.bytes[0..4].try_into().unwrap()
+    }
+
+    pub fn revision(&self) -> u8 {
+        self.bytes[8]
+    }
+
+    pub fn oem_id(&self) -> &'a [u8] {
+        &self.bytes[10..16]
+    }
+
+    /// Gets the whole table, including the header.
+    pub fn bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    /// Gets the part of the table after the header.
+    pub fn body(&self) -> &'a [u8] {
+        &self.bytes[TABLE_HEADER_SIZE..]
+    }
+}
+
+impl core::fmt::Debug for SystemDescriptionTable<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("SystemDescriptionTable")
+            .field(
+                "signature",
+                &core::str::from_utf8(&self.bytes[0..4]).unwrap_or("????"),
+            )
+            .field("length", &self.bytes.len())
+            .field("revision", &self.revision())
+            .finish()
+    }
+}
+
+/// Sums the bytes, which should come to zero for a valid table.
+pub fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// Builds tables for tests.
+#[cfg(test)]
+mod test_tables {
+    use super::{checksum, TABLE_HEADER_SIZE};
+
+    /// Builds a table with the given signature and body, with its
+    /// length and checksum filled in.
+    pub fn table(signature: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0; TABLE_HEADER_SIZE];
+        bytes[0..4].copy_from_slice(signature);
+        bytes[4..8].copy_from_slice(&((TABLE_HEADER_SIZE + body.len()) as u32).to_le_bytes());
+        bytes[8] = 1;
+        bytes[10..16].copy_from_slice(b"OSCOS ");
+        bytes.extend_from_slice(body);
+
+        bytes[9] = 0u8.wrapping_sub(checksum(&bytes));
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_tables::table;
+    use super::*;
+
+    #[test]
+    fn reads_the_header() {
+        let bytes = table(b"TEST", &[1, 2, 3]);
+        let table = SystemDescriptionTable::new(&bytes).unwrap();
+
+        assert_eq!(&table.signature(), b"TEST");
+        assert_eq!(table.revision(), 1);
+        assert_eq!(table.oem_id(), b"OSCOS ");
+        assert_eq!(table.body(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn stops_at_the_length_in_the_header() {
+        let mut bytes = table(b"TEST", &[1, 2, 3]);
+        bytes.extend_from_slice(&[4, 5]);
+
+        let table = SystemDescriptionTable::new(&bytes).unwrap();
+
+        assert_eq!(table.bytes().len(), TABLE_HEADER_SIZE + 3);
+        assert_eq!(table.body(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn rejects_invalid_lengths() {
+        let bytes = table(b"TEST", &[1, 2, 3]);
+
+        assert_eq!(
+            SystemDescriptionTable::new(&bytes[..TABLE_HEADER_SIZE + 2]).unwrap_err(),
+            TableError::InvalidLength
+        );
+        assert_eq!(
+            SystemDescriptionTable::new(&bytes[..TABLE_HEADER_SIZE - 1]).unwrap_err(),
+            TableError::InvalidLength
+        );
+
+        let mut short = table(b"TEST", &[]);
+        short[4] = (TABLE_HEADER_SIZE - 1) as u8;
+
+        assert_eq!(
+            SystemDescriptionTable::new(&short).unwrap_err(),
+            TableError::InvalidLength
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_checksums() {
+        let mut bytes = table(b"TEST", &[1, 2, 3]);
+        bytes[TABLE_HEADER_SIZE] ^= 0xFF;
+
+        assert_eq!(
+            SystemDescriptionTable::new(&bytes).unwrap_err(),
+            TableError::InvalidChecksum(*b"TEST")
+        );
+    }
+}
@@ -0,0 +1,577 @@
+//! Provides access to the multiple APIC description table, which lists
+//! the interrupt controllers in the system and how ISA interrupts are
+//! wired to them.
+//!
+//! For more details see the ACPI specification, section 5.2.12.
+
+use super::{read_u16, read_u32, read_u64, SystemDescriptionTable, TableError};
+
+/// The signature of the MADT.
+pub const MADT_SIGNATURE: &[u8; 4] = b"APIC";
+
+/// The bus number that interrupt source overrides use for ISA.
+pub const ISA_BUS: u8 = 0;
+
+/// Flags: The system also has a pair of 8259 PICs, which have to be
+/// disabled before the I/O APICs are used.
+const PCAT_COMPAT: u32 = 1 << 0;
+
+/// Local APIC flags: The processor is enabled.
+const LOCAL_APIC_ENABLED: u32 = 1 << 0;
+
+/// Local APIC flags: The processor is disabled, but can be brought
+/// online later.
+const LOCAL_APIC_ONLINE_CAPABLE: u32 = 1 << 1;
+
+/// The multiple APIC description table.
+///
+/// After the header, the table is laid out as follows:
+///
+/// | Bytes | Meaning                               |
+/// |-------|---------------------------------------|
+/// | 0-3   | Physical address of the local APICs   |
+/// | 4-7   | Flags                                 |
+/// | 8-    | Interrupt controller structures       |
+///
+/// Each interrupt controller structure starts with its type, then its
+/// length in bytes, which includes those two bytes.
+#[derive(Debug, Copy, Clone)]
+pub struct Madt<'a> {
+    table: SystemDescriptionTable<'a>,
+}
+
+impl<'a> Madt<'a> {
+    /// Wraps a table, checking that it's the MADT.
+    pub fn new(table: SystemDescriptionTable<'a>) -> Result<Self, TableError> {
+        if &table.signature() != MADT_SIGNATURE {
+            return Err(TableError::UnexpectedSignature(table.signature()));
+        }
+
+        if table.body().len() < 8 {
+            return Err(TableError::InvalidLength);
+        }
+
+        Ok(Self { table })
+    }
+
+    /// Gets the physical address of the local APICs' registers, which
+    /// may be overridden by a `LocalApicAddressOverride` entry.
+    pub fn local_apic_address(&self) -> u64 {
+        self.entries()
+            .find_map(|entry| match entry {
+                MadtEntry::LocalApicAddressOverride { address } => Some(address),
+                _ => None,
+            })
+            .unwrap_or_else(|| u64::from(read_u32(self.table.body(), 0)))
+    }
+
+    /// Determines whether the system also has a pair of 8259 PICs.
+    pub fn has_legacy_pics(&self) -> bool {
+        read_u32(self.table.body(), 4) & PCAT_COMPAT != 0
+    }
+
+    pub fn entries(&self) -> MadtEntries<'a> {
+        MadtEntries {
+            bytes: &self.table.body()[8..],
+        }
+    }
+}
+
+/// The level of an interrupt pin that signals an interrupt.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Polarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
+/// Whether an interrupt pin signals an interrupt with an edge or a
+/// level.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TriggerMode {
+    Edge,
+    Level,
+}
+
+/// The polarity and trigger mode of an interrupt, as given by the MPS
+/// INTI flags, where either can be left to conform to the bus's
+/// specification.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct InterruptFlags(u16);
+
+impl InterruptFlags {
+    pub fn from_raw(raw: u16) -> Self {
+        Self(raw)
+    }
+
+    pub fn to_raw(&self) -> u16 {
+        self.0
+    }
+
+    /// Gets the polarity, or `None` if it conforms to the bus.
+    pub fn polarity(&self) -> Option<Polarity> {
+        match self.0 & 0b11 {
+            0b01 => Some(Polarity::ActiveHigh),
+            0b11 => Some(Polarity::ActiveLow),
+            _ => None,
+        }
+    }
+
+    /// Gets the trigger mode, or `None` if it conforms to the bus.
+    pub fn trigger_mode(&self) -> Option<TriggerMode> {
+        match (self.0 >> 2) & 0b11 {
+            0b01 => Some(TriggerMode::Edge),
+            0b11 => Some(TriggerMode::Level),
+            _ => None,
+        }
+    }
+}
+
+impl core::fmt::Debug for InterruptFlags {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("InterruptFlags")
+            .field("polarity", &self.polarity())
+            .field("trigger_mode", &self.trigger_mode())
+            .finish()
+    }
+}
+
+/// Redirects an interrupt from a bus to a different global system
+/// interrupt, or with a different polarity or trigger mode.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct InterruptSourceOverride {
+    pub bus: u8,
+    pub source: u8,
+    pub global_system_interrupt: u32,
+    pub flags: InterruptFlags,
+}
+
+/// An interrupt controller structure from the MADT.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MadtEntry {
+    /// A processor and its local APIC.
+    LocalApic {
+        processor_uid: u8,
+        apic_id: u8,
+        enabled: bool,
+        online_capable: bool,
+    },
+
+    /// An I/O APIC, whose inputs are the global system interrupts from
+    /// `global_system_interrupt_base` upwards.
+    IoApic {
+        id: u8,
+        address: u32,
+        global_system_interrupt_base: u32,
+    },
+
+    InterruptSourceOverride(InterruptSourceOverride),
+
+    /// A global system interrupt that is wired as an NMI.
+    NmiSource {
+        flags: InterruptFlags,
+        global_system_interrupt: u32,
+    },
+
+    /// A local APIC's LINT pin that is wired as an NMI, where a
+    /// processor UID of 0xFF means every processor.
+    LocalApicNmi {
+        processor_uid: u8,
+        flags: InterruptFlags,
+        lint: u8,
+    },
+
+    /// A 64-bit physical address of the local APICs' registers, which
+    /// replaces the 32-bit one in the table's header.
+    LocalApicAddressOverride {
+        address: u64,
+    },
+
+    /// A processor whose local APIC has an ID too large for
+    /// `LocalApic`.
+    LocalX2Apic {
+        processor_uid: u32,
+        x2apic_id: u32,
+        enabled: bool,
+        online_capable: bool,
+    },
+
+    /// A local x2APIC's LINT pin that is wired as an NMI, where a
+    /// processor UID of 0xFFFFFFFF means every processor.
+    LocalX2ApicNmi {
+        processor_uid: u32,
+        flags: InterruptFlags,
+        lint: u8,
+    },
+
+    /// A structure of a type this doesn't decode.
+    Unknown {
+        entry_type: u8,
+    },
+}
+
+/// Iterates over the interrupt controller structures in the MADT,
+/// stopping early at a structure that's truncated.
+pub struct MadtEntries<'a> {
+    bytes: &'a [u8],
+}
+
+impl Iterator for MadtEntries<'_> {
+    type Item = MadtEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.bytes.len() < 2 {
+            return None;
+        }
+
+        let entry_type = self.bytes[0];
+        let length = usize::from(self.bytes[1]);
+
+        if length < 2 || length > self.bytes.len() {
+            self.bytes = &[];
+            return None;
+        }
+
+        let bytes = &self.bytes[..length];
+        self.bytes = &self.bytes[length..];
+
+        // NOTE: Each structure has a minimum length, but later revisions
+        // may make them longer, so anything shorter is treated as a type
+        // this doesn't decode.
+        let entry = match (entry_type, length) {
+            (0, 8..=255) => {
+                let flags = read_u32(bytes, 4);
+
+                MadtEntry::LocalApic {
+                    processor_uid: bytes[2],
+                    apic_id: bytes[3],
+                    enabled: flags & LOCAL_APIC_ENABLED != 0,
+                    online_capable: flags & LOCAL_APIC_ONLINE_CAPABLE != 0,
+                }
+            }
+            (1, 12..=255) => MadtEntry::IoApic {
+                id: bytes[2],
+                address: read_u32(bytes, 4),
+                global_system_interrupt_base: read_u32(bytes, 8),
+            },
+            (2, 10..=255) => MadtEntry::InterruptSourceOverride(InterruptSourceOverride {
+                bus: bytes[2],
+                source: bytes[3],
+                global_system_interrupt: read_u32(bytes, 4),
+                flags: InterruptFlags::from_raw(read_u16(bytes, 8)),
+            }),
+            (3, 8..=255) => MadtEntry::NmiSource {
+                flags: InterruptFlags::from_raw(read_u16(bytes, 2)),
+                global_system_interrupt: read_u32(bytes, 4),
+            },
+            (4, 6..=255) => MadtEntry::LocalApicNmi {
+                processor_uid: bytes[2],
+                flags: InterruptFlags::from_raw(read_u16(bytes, 3)),
+                lint: bytes[5],
+            },
+            (5, 12..=255) => MadtEntry::LocalApicAddressOverride {
+                address: read_u64(bytes, 4),
+            },
+            (9, 16..=255) => {
+                let flags = read_u32(bytes, 8);
+
+                MadtEntry::LocalX2Apic {
+                    processor_uid: read_u32(bytes, 12),
+                    x2apic_id: read_u32(bytes, 4),
+                    enabled: flags & LOCAL_APIC_ENABLED != 0,
+                    online_capable: flags & LOCAL_APIC_ONLINE_CAPABLE != 0,
+                }
+            }
+            (10, 12..=255) => MadtEntry::LocalX2ApicNmi {
+                processor_uid: read_u32(bytes, 4),
+                flags: InterruptFlags::from_raw(read_u16(bytes, 2)),
+                lint: bytes[8],
+            },
+            _ => MadtEntry::Unknown { entry_type },
+        };
+
+        Some(entry)
+    }
+}
+
+/// Where an ISA IRQ arrives, after any interrupt source override.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct IsaIrqRoute {
+    pub gsi: u32,
+    pub polarity: Polarity,
+    pub trigger_mode: TriggerMode,
+}
+
+/// Gets where the given ISA IRQ arrives, given the MADT's interrupt
+/// source overrides, ignoring any for other buses.
+///
+/// Without an override, ISA IRQs are identity mapped, edge triggered
+/// and active high, and an override that leaves the polarity or trigger
+/// mode to conform to the bus gets those of ISA.
+pub fn isa_irq_route<'a>(
+    isa_irq: u8,
+    overrides: impl IntoIterator<Item = &'a InterruptSourceOverride>,
+) -> IsaIrqRoute {
+    let source_override = overrides.into_iter().find(|source_override| {
+        source_override.bus == ISA_BUS && source_override.source == isa_irq
+    });
+
+    match source_override {
+        Some(source_override) => IsaIrqRoute {
+            gsi: source_override.global_system_interrupt,
+            polarity: source_override
+                .flags
+                .polarity()
+                .unwrap_or(Polarity::ActiveHigh),
+            trigger_mode: source_override
+                .flags
+                .trigger_mode()
+                .unwrap_or(TriggerMode::Edge),
+        },
+        None => IsaIrqRoute {
+            gsi: u32::from(isa_irq),
+            polarity: Polarity::ActiveHigh,
+            trigger_mode: TriggerMode::Edge,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_tables::table;
+
+    const LOCAL_APIC_ADDRESS: u32 = 0xFEE0_0000;
+    const IO_APIC_ADDRESS: u32 = 0xFEC0_0000;
+
+    /// Builds an interrupt controller structure, filling in its length.
+    fn entry(entry_type: u8, fields: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![entry_type, (fields.len() + 2) as u8];
+        bytes.extend_from_slice(fields);
+        bytes
+    }
+
+    fn source_override(source: u8, gsi: u32, flags: u16) -> Vec<u8> {
+        let mut fields = vec![ISA_BUS, source];
+        fields.extend_from_slice(&gsi.to_le_bytes());
+        fields.extend_from_slice(&flags.to_le_bytes());
+        entry(2, &fields)
+    }
+
+    /// Builds a MADT with the given interrupt controller structures.
+    fn madt_bytes(entries: &[Vec<u8>]) -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&LOCAL_APIC_ADDRESS.to_le_bytes());
+        body.extend_from_slice(&PCAT_COMPAT.to_le_bytes());
+
+        for entry in entries {
+            body.extend_from_slice(entry);
+        }
+
+        table(MADT_SIGNATURE, &body)
+    }
+
+    fn parse(bytes: &[u8]) -> Vec<MadtEntry> {
+        let table = SystemDescriptionTable::new(bytes).unwrap();
+        Madt::new(table).unwrap().entries().collect()
+    }
+
+    /// A MADT like QEMU's, with one processor, one I/O APIC, and the
+    /// usual overrides for the timer and the ACPI SCI.
+    fn qemu_madt() -> Vec<u8> {
+        let mut io_apic = vec![0, 0];
+        io_apic.extend_from_slice(&IO_APIC_ADDRESS.to_le_bytes());
+        io_apic.extend_from_slice(&0u32.to_le_bytes());
+
+        madt_bytes(&[
+            entry(0, &[0, 0, 1, 0, 0, 0]),
+            entry(1, &io_apic),
+            source_override(0, 2, 0x0000),
+            source_override(9, 9, 0x000D),
+            entry(4, &[0xFF, 0x05, 0x00, 1]),
+        ])
+    }
+
+    #[test]
+    fn reads_the_fixed_fields() {
+        let bytes = qemu_madt();
+        let madt = Madt::new(SystemDescriptionTable::new(&bytes).unwrap()).unwrap();
+
+        assert_eq!(madt.local_apic_address(), u64::from(LOCAL_APIC_ADDRESS));
+        assert!(madt.has_legacy_pics());
+    }
+
+    #[test]
+    fn decodes_each_entry() {
+        assert_eq!(
+            parse(&qemu_madt()),
+            [
+                MadtEntry::LocalApic {
+                    processor_uid: 0,
+                    apic_id: 0,
+                    enabled: true,
+                    online_capable: false,
+                },
+                MadtEntry::IoApic {
+                    id: 0,
+                    address: IO_APIC_ADDRESS,
+                    global_system_interrupt_base: 0,
+                },
+                MadtEntry::InterruptSourceOverride(InterruptSourceOverride {
+                    bus: ISA_BUS,
+                    source: 0,
+                    global_system_interrupt: 2,
+                    flags: InterruptFlags::from_raw(0x0000),
+                }),
+                MadtEntry::InterruptSourceOverride(InterruptSourceOverride {
+                    bus: ISA_BUS,
+                    source: 9,
+                    global_system_interrupt: 9,
+                    flags: InterruptFlags::from_raw(0x000D),
+                }),
+                MadtEntry::LocalApicNmi {
+                    processor_uid: 0xFF,
+                    flags: InterruptFlags::from_raw(0x0005),
+                    lint: 1,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn prefers_the_local_apic_address_override() {
+        let mut fields = vec![0, 0];
+        fields.extend_from_slice(&0x1_FEE0_0000u64.to_le_bytes());
+
+        let bytes = madt_bytes(&[entry(5, &fields)]);
+        let madt = Madt::new(SystemDescriptionTable::new(&bytes).unwrap()).unwrap();
+
+        assert_eq!(madt.local_apic_address(), 0x1_FEE0_0000);
+    }
+
+    #[test]
+    fn stops_at_a_truncated_entry() {
+        // NOTE: The I/O APIC entry claims 12 bytes, but the table ends
+        // after 6 of them.
+        let entries = parse(&madt_bytes(&[
+            entry(0, &[0, 0, 1, 0, 0, 0]),
+            vec![1, 12, 0, 0, 0, 0],
+        ]));
+
+        assert_eq!(entries.len(), 1);
+    }
+
+    #[test]
+    fn stops_at_an_entry_too_short_for_its_own_header() {
+        for length in 0..2 {
+            let entries = parse(&madt_bytes(&[
+                vec![0, length],
+                entry(0, &[0, 0, 1, 0, 0, 0]),
+            ]));
+
+            assert_eq!(entries, []);
+        }
+    }
+
+    #[test]
+    fn skips_entries_shorter_than_their_type_needs() {
+        // NOTE: An interrupt source override needs 10 bytes, so this one
+        // can't be decoded, but the entries after it still can.
+        let entries = parse(&madt_bytes(&[
+            entry(2, &[ISA_BUS, 0, 2, 0, 0, 0]),
+            source_override(9, 9, 0x000D),
+        ]));
+
+        assert_eq!(entries[0], MadtEntry::Unknown { entry_type: 2 });
+        assert!(matches!(entries[1], MadtEntry::InterruptSourceOverride(_)));
+    }
+
+    #[test]
+    fn decodes_mps_inti_flags() {
+        let cases = [
+            (0b0000, None, None),
+            (0b0001, Some(Polarity::ActiveHigh), None),
+            (0b0010, None, None),
+            (0b0011, Some(Polarity::ActiveLow), None),
+            (0b0100, None, Some(TriggerMode::Edge)),
+            (0b1000, None, None),
+            (0b1100, None, Some(TriggerMode::Level)),
+            (0b1111, Some(Polarity::ActiveLow), Some(TriggerMode::Level)),
+        ];
+
+        for &(raw, polarity, trigger_mode) in cases.iter() {
+            let flags = InterruptFlags::from_raw(raw);
+
+            assert_eq!(flags.polarity(), polarity, "flags {:#06b}", raw);
+            assert_eq!(flags.trigger_mode(), trigger_mode, "flags {:#06b}", raw);
+        }
+    }
+
+    #[test]
+    fn rejects_other_tables() {
+        let bytes = table(b"FACP", &[0; 8]);
+        let table = SystemDescriptionTable::new(&bytes).unwrap();
+
+        assert_eq!(
+            Madt::new(table).unwrap_err(),
+            TableError::UnexpectedSignature(*b"FACP")
+        );
+    }
+
+    #[test]
+    fn rejects_a_body_too_short_for_the_fixed_fields() {
+        let bytes = table(MADT_SIGNATURE, &[0; 4]);
+        let table = SystemDescriptionTable::new(&bytes).unwrap();
+
+        assert_eq!(Madt::new(table).unwrap_err(), TableError::InvalidLength);
+    }
+
+    #[test]
+    fn routes_isa_irqs_through_overrides() {
+        let overrides: Vec<_> = parse(&qemu_madt())
+            .into_iter()
+            .filter_map(|entry| match entry {
+                MadtEntry::InterruptSourceOverride(source_override) => Some(source_override),
+                _ => None,
+            })
+            .collect();
+
+        // NOTE: The timer's override conforms to the bus, so it keeps
+        // ISA's polarity and trigger mode.
+        assert_eq!(
+            isa_irq_route(0, &overrides),
+            IsaIrqRoute {
+                gsi: 2,
+                polarity: Polarity::ActiveHigh,
+                trigger_mode: TriggerMode::Edge,
+            }
+        );
+        assert_eq!(
+            isa_irq_route(9, &overrides),
+            IsaIrqRoute {
+                gsi: 9,
+                polarity: Polarity::ActiveHigh,
+                trigger_mode: TriggerMode::Level,
+            }
+        );
+    }
+
+    #[test]
+    fn identity_maps_isa_irqs_without_an_override() {
+        let overrides = [InterruptSourceOverride {
+            bus: 1,
+            source: 4,
+            global_system_interrupt: 20,
+            flags: InterruptFlags::from_raw(0x000F),
+        }];
+
+        // NOTE: The override is for another bus, so it doesn't apply.
+        assert_eq!(
+            isa_irq_route(4, &overrides),
+            IsaIrqRoute {
+                gsi: 4,
+                polarity: Polarity::ActiveHigh,
+                trigger_mode: TriggerMode::Edge,
+            }
+        );
+    }
+}
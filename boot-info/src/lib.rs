@@ -0,0 +1,558 @@
+//! Provides the information handed from the boot stub to the kernel.
+//!
+//! Everything here is `#[repr(C)]` so that the layout doesn't depend on
+//! the compiler, and the structure carries a magic number and version
+//! so that a kernel can check it understands what a particular build of
+//! the boot stub has given it.
+//!
+//! Any change to the layout of these types must be accompanied by an
+//! increment of `BOOT_INFO_VERSION`.
+#![cfg_attr(not(test), no_std)]
+
+use core::fmt;
+use core::marker::PhantomData;
+
+mod memory_map;
+pub use memory_map::*;
+
+/// The magic number found at the start of every `BootInfo`.
+pub const BOOT_INFO_MAGIC: u64 = u64::from_le_bytes(*b"OSCOSBI\0");
+
+/// The version of the `BootInfo` layout described by this crate.
+pub const BOOT_INFO_VERSION: u32 = 3;
+
+/// The information the boot stub passes to the kernel's entry point.
+///
+/// All addresses held in here (other than those explicitly described as
+/// physical) are linear addresses in the address space the kernel is
+/// started in.
+///
+/// The kernel is started with interrupts disabled and an IDT loaded
+/// that isn't mapped in its address space, so it must not fault before
+/// it has loaded its own IDT.
+#[repr(C)]
+pub struct BootInfo {
+    /// Always `BOOT_INFO_MAGIC`.
+    pub magic: u64,
+
+    /// The version of the layout, see `BOOT_INFO_VERSION`.
+    pub version: u32,
+
+    /// The size of this structure in bytes.
+    pub size: u32,
+
+    /// The regions making up physical memory, sorted by address.
+    pub memory_map: BootSlice<MemoryRegion>,
+
+    /// The framebuffer set up by the firmware, if there is one.
+    pub framebuffer: FramebufferInfo,
+
+    /// The physical address of the ACPI RSDP, or zero if the firmware
+    /// didn't provide one.
+    pub rsdp_address: u64,
+
+    /// The kernel's command line.
+    pub command_line: BootStr,
+
+    /// The modules loaded alongside the kernel.
+    pub modules: BootSlice<Module>,
+
+    /// How the boot stub laid out memory for the kernel.
+    pub layout: AddressSpaceLayout,
+
+    /// The GDT and TSS the kernel is started with.
+    pub cpu_tables: CpuTablesInfo,
+}
+
+impl BootInfo {
+    /// Constructs boot information with the magic number and version
+    /// filled in, and everything else empty.
+    pub const fn new() -> Self {
+        Self {
+            magic: BOOT_INFO_MAGIC,
+            version: BOOT_INFO_VERSION,
+            size: core::mem::size_of::<Self>() as u32,
+            memory_map: BootSlice::empty(),
+            framebuffer: FramebufferInfo::none(),
+            rsdp_address: 0,
+            command_line: BootStr::empty(),
+            modules: BootSlice::empty(),
+            layout: AddressSpaceLayout::empty(),
+            cpu_tables: CpuTablesInfo::empty(),
+        }
+    }
+
+    /// Checks that this structure was produced by a boot stub using the
+    /// same layout as this crate.
+    pub fn is_compatible(&self) -> bool {
+        self.magic == BOOT_INFO_MAGIC
+            && self.version == BOOT_INFO_VERSION
+            && self.size as usize == core::mem::size_of::<Self>()
+    }
+
+    /// Gets the framebuffer, if there is one.
+    pub fn framebuffer(&self) -> Option<&FramebufferInfo> {
+        if self.framebuffer.address == 0 {
+            None
+        } else {
+            Some(&self.framebuffer)
+        }
+    }
+
+    /// Gets the physical address of the ACPI RSDP, if there is one.
+    pub fn rsdp_address(&self) -> Option<u64> {
+        if self.rsdp_address == 0 {
+            None
+        } else {
+            Some(self.rsdp_address)
+        }
+    }
+}
+
+impl Default for BootInfo {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A slice whose contents were placed in memory by the boot stub.
+#[repr(C)]
+pub struct BootSlice<T> {
+    address: u64,
+    len: u64,
+    _phantom: PhantomData<*const T>,
+}
+
+impl<T> BootSlice<T> {
+    /// Constructs an empty slice.
+    pub const fn empty() -> Self {
+        Self {
+            address: 0,
+            len: 0,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Constructs a slice from the linear address of its first element
+    /// and the number of elements.
+    pub const fn from_raw_parts(address: u64, len: u64) -> Self {
+        Self {
+            address,
+            len,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Gets the linear address of the first element.
+    pub fn address(&self) -> u64 {
+        self.address
+    }
+
+    /// Gets the number of elements.
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    /// Determines whether the slice is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Gets the contents of the slice.
+    ///
+    /// # Safety
+    /// The slice must be in the current address space, i.e. the boot
+    /// stub's mappings must still be in place.
+    pub unsafe fn as_slice(&self) -> &[T] {
+        if self.len == 0 {
+            &[]
+        } else {
+            core::slice::from_raw_parts(self.address as *const T, self.len as usize)
+        }
+    }
+}
+
+/// A UTF-8 string placed in memory by the boot stub.
+#[repr(C)]
+pub struct BootStr {
+    bytes: BootSlice<u8>,
+}
+
+impl BootStr {
+    /// Constructs an empty string.
+    pub const fn empty() -> Self {
+        Self {
+            bytes: BootSlice::empty(),
+        }
+    }
+
+    /// Constructs a string from the linear address of its first byte
+    /// and its length in bytes.
+    pub const fn from_raw_parts(address: u64, len: u64) -> Self {
+        Self {
+            bytes: BootSlice::from_raw_parts(address, len),
+        }
+    }
+
+    /// Gets the contents of the string, or `None` if it isn't valid
+    /// UTF-8.
+    ///
+    /// # Safety
+    /// The string must be in the current address space, i.e. the boot
+    /// stub's mappings must still be in place.
+    pub unsafe fn as_str(&self) -> Option<&str> {
+        core::str::from_utf8(self.bytes.as_slice()).ok()
+    }
+}
+
+/// The kind of a region of physical memory.
+///
+/// This is a plain integer rather than an enum so that a kernel can
+/// cope with kinds added by newer boot stubs.
+#[repr(transparent)]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MemoryRegionKind(pub u32);
+
+impl MemoryRegionKind {
+    /// Free memory that the kernel can use however it likes.
+    pub const USABLE: Self = Self(1);
+
+    /// Memory that must never be used.
+    pub const RESERVED: Self = Self(2);
+
+    /// Memory holding ACPI tables, which can be reused once the tables
+    /// have been read.
+    pub const ACPI_RECLAIMABLE: Self = Self(3);
+
+    /// Memory that the firmware needs preserved across sleep states.
+    pub const ACPI_NVS: Self = Self(4);
+
+    /// The boot stub's code, which can be reused once the kernel no
+    /// longer needs anything from the boot stub.
+    pub const BOOT_STUB_CODE: Self = Self(5);
+
+    /// The boot stub's data, which includes this boot information.
+    pub const BOOT_STUB_DATA: Self = Self(6);
+
+    /// The loaded segments of the kernel.
+    pub const KERNEL_IMAGE: Self = Self(7);
+
+    /// The page tables set up by the boot stub.
+    pub const PAGE_TABLES: Self = Self(8);
+
+    /// The modules loaded alongside the kernel.
+    pub const MODULES: Self = Self(9);
+
+    /// The stacks set up by the boot stub, including the one the kernel
+    /// is started on.
+    pub const KERNEL_STACKS: Self = Self(10);
+
+    fn name(&self) -> Option<&'static str> {
+        match *self {
+            Self::USABLE => Some("Usable"),
+            Self::RESERVED => Some("Reserved"),
+            Self::ACPI_RECLAIMABLE => Some("AcpiReclaimable"),
+            Self::ACPI_NVS => Some("AcpiNvs"),
+            Self::BOOT_STUB_CODE => Some("BootStubCode"),
+            Self::BOOT_STUB_DATA => Some("BootStubData"),
+            Self::KERNEL_IMAGE => Some("KernelImage"),
+            Self::PAGE_TABLES => Some("PageTables"),
+            Self::MODULES => Some("Modules"),
+            Self::KERNEL_STACKS => Some("KernelStacks"),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Debug for MemoryRegionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => f.write_str(name),
+            None => f.debug_tuple("MemoryRegionKind").field(&self.0).finish(),
+        }
+    }
+}
+
+/// A region of physical memory.
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct MemoryRegion {
+    /// The physical address of the start of the region.
+    pub start: u64,
+
+    /// The physical address just beyond the end of the region.
+    pub end: u64,
+
+    /// What the region is used for.
+    pub kind: MemoryRegionKind,
+}
+
+impl MemoryRegion {
+    /// Gets the length of the region in bytes.
+    pub fn len(&self) -> u64 {
+        self.end - self.start
+    }
+
+    /// Determines whether the region is empty.
+    pub fn is_empty(&self) -> bool {
+        self.end == self.start
+    }
+}
+
+impl fmt::Debug for MemoryRegion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("MemoryRegion")
+            .field(&format_args!("{:#018X}", self.start))
+            .field(&format_args!("{:#018X}", self.end))
+            .field(&self.kind)
+            .finish()
+    }
+}
+
+/// The layout of pixels in a framebuffer.
+#[repr(transparent)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PixelFormat(pub u32);
+
+impl PixelFormat {
+    /// There is no framebuffer.
+    pub const NONE: Self = Self(0);
+
+    /// Each pixel is 32-bits, with the bytes in red, green, blue,
+    /// reserved order.
+    pub const RGB: Self = Self(1);
+
+    /// Each pixel is 32-bits, with the bytes in blue, green, red,
+    /// reserved order.
+    pub const BGR: Self = Self(2);
+
+    /// Each pixel is 32-bits, with the channels described by the masks
+    /// in the `FramebufferInfo`.
+    pub const BITMASK: Self = Self(3);
+}
+
+/// Describes a linear framebuffer.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct FramebufferInfo {
+    /// The physical address of the framebuffer.
+    pub address: u64,
+
+    /// The size of the framebuffer in bytes.
+    pub size: u64,
+
+    /// The width of the display in pixels.
+    pub width: u32,
+
+    /// The height of the display in pixels.
+    pub height: u32,
+
+    /// The number of pixels in each scan line, which may be larger
+    /// than the width.
+    pub stride: u32,
+
+    /// The layout of the pixels.
+    pub format: PixelFormat,
+
+    /// The red channel's bits, for `PixelFormat::BITMASK`.
+    pub red_mask: u32,
+
+    /// The green channel's bits, for `PixelFormat::BITMASK`.
+    pub green_mask: u32,
+
+    /// The blue channel's bits, for `PixelFormat::BITMASK`.
+    pub blue_mask: u32,
+
+    /// The reserved bits, for `PixelFormat::BITMASK`.
+    pub reserved_mask: u32,
+}
+
+impl FramebufferInfo {
+    /// Constructs a description of a missing framebuffer.
+    pub const fn none() -> Self {
+        Self {
+            address: 0,
+            size: 0,
+            width: 0,
+            height: 0,
+            stride: 0,
+            format: PixelFormat::NONE,
+            red_mask: 0,
+            green_mask: 0,
+            blue_mask: 0,
+            reserved_mask: 0,
+        }
+    }
+}
+
+/// A file loaded into memory alongside the kernel.
+#[repr(C)]
+pub struct Module {
+    /// The path the module was loaded from.
+    pub name: BootStr,
+
+    /// The physical address the module was loaded at, this is always
+    /// page aligned.
+    pub physical_address: u64,
+
+    /// The size of the module in bytes.
+    pub size: u64,
+}
+
+/// Describes how the boot stub arranged memory for the kernel.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct AddressSpaceLayout {
+    /// The physical address of the root page table the kernel is
+    /// started with.
+    pub page_table_root: u64,
+
+    /// The linear address at which all of physical memory is mapped,
+    /// this is zero when physical memory is identity mapped.
+    pub physical_memory_offset: u64,
+
+    /// The lowest physical address occupied by the kernel image.
+    pub kernel_physical_start: u64,
+
+    /// The lowest linear address occupied by the kernel image.
+    pub kernel_virtual_start: u64,
+
+    /// The number of bytes spanned by the kernel image.
+    pub kernel_size: u64,
+
+    /// The linear address of the top of the stack the kernel is
+    /// started on.
+    pub stack_top: u64,
+
+    /// The size of the stack the kernel is started on in bytes.
+    pub stack_size: u64,
+
+    /// The size in bytes of the unmapped guard below the stack the
+    /// kernel is started on.
+    pub stack_guard_size: u64,
+}
+
+impl AddressSpaceLayout {
+    /// Constructs an empty layout.
+    pub const fn empty() -> Self {
+        Self {
+            page_table_root: 0,
+            physical_memory_offset: 0,
+            kernel_physical_start: 0,
+            kernel_virtual_start: 0,
+            kernel_size: 0,
+            stack_top: 0,
+            stack_size: 0,
+            stack_guard_size: 0,
+        }
+    }
+}
+
+/// The interrupt stack table index the boot stub gives double faults a
+/// stack at.
+pub const DOUBLE_FAULT_IST_INDEX: u8 = 1;
+
+/// The interrupt stack table index the boot stub gives NMIs a stack at.
+pub const NMI_IST_INDEX: u8 = 2;
+
+/// The interrupt stack table index the boot stub gives machine checks a
+/// stack at.
+pub const MACHINE_CHECK_IST_INDEX: u8 = 3;
+
+/// Describes the GDT and TSS loaded by the boot stub, so that the kernel
+/// can point its IDT at the right code segment and interrupt stacks
+/// before it replaces them with its own.
+///
+/// The GDT holds kernel code, kernel data, user data, then user code
+/// segments, in the order `syscall` and `sysret` expect, followed by the
+/// TSS.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct CpuTablesInfo {
+    /// The linear address of the GDT.
+    pub gdt_address: u64,
+
+    /// The size of the GDT in bytes.
+    pub gdt_size: u64,
+
+    /// The linear address of the TSS.
+    pub tss_address: u64,
+
+    /// The selector for the kernel's 64-bit code segment.
+    ///
+    /// This and the next three selectors are in the order `syscall` and
+    /// `sysret` expect, which the kernel relies on when it programs the
+    /// STAR MSR.
+    pub kernel_code_selector: u16,
+
+    /// The selector for the kernel's data segment, directly after its
+    /// code segment.
+    pub kernel_data_selector: u16,
+
+    /// The selector for the user data segment, directly after the
+    /// kernel's data segment.
+    pub user_data_selector: u16,
+
+    /// The selector for the user 64-bit code segment, directly after
+    /// the user data segment.
+    pub user_code_selector: u16,
+
+    /// The selector for the TSS, which is already loaded into the task
+    /// register.
+    pub tss_selector: u16,
+
+    /// The linear addresses of the tops of the stacks in the TSS's
+    /// interrupt stack table, where the first is for IST index 1, and
+    /// zero means there is no stack. See `DOUBLE_FAULT_IST_INDEX`,
+    /// `NMI_IST_INDEX` and `MACHINE_CHECK_IST_INDEX`.
+    pub interrupt_stack_tops: [u64; 7],
+}
+
+impl CpuTablesInfo {
+    /// Constructs a description of missing tables.
+    pub const fn empty() -> Self {
+        Self {
+            gdt_address: 0,
+            gdt_size: 0,
+            tss_address: 0,
+            kernel_code_selector: 0,
+            kernel_data_selector: 0,
+            user_data_selector: 0,
+            user_code_selector: 0,
+            tss_selector: 0,
+            interrupt_stack_tops: [0; 7],
+        }
+    }
+
+    /// Gets the top of the stack at the given IST index (1 to 7), if
+    /// there is one.
+    pub fn interrupt_stack_top(&self, index: u8) -> Option<u64> {
+        let slot = usize::from(index).checked_sub(1)?;
+
+        match self.interrupt_stack_tops.get(slot) {
+            Some(&top) if top != 0 => Some(top),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_interrupt_stacks_by_ist_index() {
+        let mut info = CpuTablesInfo::empty();
+        info.interrupt_stack_tops[0] = 0x1000;
+        info.interrupt_stack_tops[6] = 0x7000;
+
+        assert_eq!(
+            info.interrupt_stack_top(DOUBLE_FAULT_IST_INDEX),
+            Some(0x1000)
+        );
+        assert_eq!(info.interrupt_stack_top(7), Some(0x7000));
+        assert_eq!(info.interrupt_stack_top(NMI_IST_INDEX), None);
+        assert_eq!(info.interrupt_stack_top(0), None);
+        assert_eq!(info.interrupt_stack_top(8), None);
+    }
+}
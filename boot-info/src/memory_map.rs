@@ -0,0 +1,290 @@
+//! Provides conversion from the firmware's memory map into the regions
+//! handed to the kernel.
+//!
+//! This works on raw UEFI memory type values rather than the firmware
+//! crate's types, so that it can be built and tested on the host.
+
+use crate::{MemoryRegion, MemoryRegionKind};
+
+const UEFI_PAGE_SIZE: u64 = 4096;
+
+/// The type of a UEFI memory descriptor, as its raw value.
+#[repr(transparent)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct UefiMemoryType(pub u32);
+
+impl UefiMemoryType {
+    pub const RESERVED: Self = Self(0);
+    pub const LOADER_CODE: Self = Self(1);
+    pub const LOADER_DATA: Self = Self(2);
+    pub const BOOT_SERVICES_CODE: Self = Self(3);
+    pub const BOOT_SERVICES_DATA: Self = Self(4);
+    pub const RUNTIME_SERVICES_CODE: Self = Self(5);
+    pub const RUNTIME_SERVICES_DATA: Self = Self(6);
+    pub const CONVENTIONAL: Self = Self(7);
+    pub const UNUSABLE: Self = Self(8);
+    pub const ACPI_RECLAIM: Self = Self(9);
+    pub const ACPI_NON_VOLATILE: Self = Self(10);
+
+    // NOTE: UEFI reserves memory types from 0x80000000 upwards for use
+    // by OS loaders, which lets the boot stub find its allocations in
+    // the final memory map.
+
+    /// The type used for pages holding the kernel's segments.
+    pub const KERNEL_IMAGE: Self = Self(0x8000_0000);
+
+    /// The type used for pages holding page tables built for the
+    /// kernel.
+    pub const PAGE_TABLES: Self = Self(0x8000_0001);
+
+    /// The type used for pages holding modules.
+    pub const MODULES: Self = Self(0x8000_0002);
+
+    /// The type used for pages holding stacks built for the kernel.
+    pub const KERNEL_STACKS: Self = Self(0x8000_0003);
+}
+
+/// The parts of a UEFI memory descriptor that matter for conversion.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct UefiMemoryDescriptor {
+    pub memory_type: UefiMemoryType,
+    pub physical_start: u64,
+    pub page_count: u64,
+}
+
+/// Indicates that there wasn't enough room for the converted map.
+#[derive(Debug, PartialEq, Eq)]
+pub struct MemoryMapTooLarge;
+
+/// Converts UEFI memory descriptors into memory regions, writing them
+/// sorted by address with adjacent regions of the same kind merged.
+/// Returns the number of regions written.
+///
+/// This doesn't allocate, so it can be used after boot services have
+/// been exited. The output never needs more entries than there are
+/// descriptors.
+pub fn convert_memory_map(
+    descriptors: impl Iterator<Item = UefiMemoryDescriptor>,
+    regions: &mut [MemoryRegion],
+) -> Result<usize, MemoryMapTooLarge> {
+    let mut count = 0;
+
+    for descriptor in descriptors {
+        if descriptor.page_count == 0 {
+            continue;
+        }
+
+        let region = regions.get_mut(count).ok_or(MemoryMapTooLarge)?;
+
+        *region = MemoryRegion {
+            start: descriptor.physical_start,
+            end: descriptor.physical_start + (descriptor.page_count * UEFI_PAGE_SIZE),
+            kind: region_kind(descriptor.memory_type),
+        };
+
+        count += 1;
+    }
+
+    let regions = &mut regions[..count];
+    regions.sort_unstable_by_key(|region| region.start);
+
+    Ok(merge_adjacent(regions))
+}
+
+/// Determines what a region of memory of the given type can be used
+/// for once boot services have been exited.
+pub fn region_kind(memory_type: UefiMemoryType) -> MemoryRegionKind {
+    match memory_type {
+        // NOTE: Boot services memory is free for reuse once we've exited
+        // boot services.
+        UefiMemoryType::CONVENTIONAL
+        | UefiMemoryType::BOOT_SERVICES_CODE
+        | UefiMemoryType::BOOT_SERVICES_DATA => MemoryRegionKind::USABLE,
+
+        UefiMemoryType::LOADER_CODE => MemoryRegionKind::BOOT_STUB_CODE,
+        UefiMemoryType::LOADER_DATA => MemoryRegionKind::BOOT_STUB_DATA,
+        UefiMemoryType::ACPI_RECLAIM => MemoryRegionKind::ACPI_RECLAIMABLE,
+        UefiMemoryType::ACPI_NON_VOLATILE => MemoryRegionKind::ACPI_NVS,
+
+        UefiMemoryType::KERNEL_IMAGE => MemoryRegionKind::KERNEL_IMAGE,
+        UefiMemoryType::PAGE_TABLES => MemoryRegionKind::PAGE_TABLES,
+        UefiMemoryType::MODULES => MemoryRegionKind::MODULES,
+        UefiMemoryType::KERNEL_STACKS => MemoryRegionKind::KERNEL_STACKS,
+
+        // NOTE: This includes runtime services memory, which the
+        // firmware needs preserved for as long as we might call it.
+        _ => MemoryRegionKind::RESERVED,
+    }
+}
+
+/// Merges regions of the same kind that directly follow each other,
+/// moving the results to the front of the slice. The regions must
+/// already be sorted. Returns the number of regions after merging.
+fn merge_adjacent(regions: &mut [MemoryRegion]) -> usize {
+    if regions.is_empty() {
+        return 0;
+    }
+
+    let mut last = 0;
+
+    for index in 1..regions.len() {
+        let current = regions[index];
+        let previous = &mut regions[last];
+
+        if previous.kind == current.kind && previous.end == current.start {
+            previous.end = current.end;
+        } else {
+            last += 1;
+            regions[last] = current;
+        }
+    }
+
+    last + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EMPTY: MemoryRegion = MemoryRegion {
+        start: 0,
+        end: 0,
+        kind: MemoryRegionKind::RESERVED,
+    };
+
+    fn descriptor(memory_type: UefiMemoryType, start: u64, pages: u64) -> UefiMemoryDescriptor {
+        UefiMemoryDescriptor {
+            memory_type,
+            physical_start: start,
+            page_count: pages,
+        }
+    }
+
+    fn region(start: u64, end: u64, kind: MemoryRegionKind) -> MemoryRegion {
+        MemoryRegion { start, end, kind }
+    }
+
+    fn convert(
+        descriptors: &[UefiMemoryDescriptor],
+    ) -> Result<Vec<MemoryRegion>, MemoryMapTooLarge> {
+        let mut regions = vec![EMPTY; descriptors.len()];
+        let count = convert_memory_map(descriptors.iter().copied(), &mut regions)?;
+        regions.truncate(count);
+        Ok(regions)
+    }
+
+    #[test]
+    fn sorts_regions_by_address() {
+        let regions = convert(&[
+            descriptor(UefiMemoryType::ACPI_RECLAIM, 0x3000, 1),
+            descriptor(UefiMemoryType::CONVENTIONAL, 0x0000, 1),
+            descriptor(UefiMemoryType::LOADER_DATA, 0x1000, 1),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            regions,
+            [
+                region(0x0000, 0x1000, MemoryRegionKind::USABLE),
+                region(0x1000, 0x2000, MemoryRegionKind::BOOT_STUB_DATA),
+                region(0x3000, 0x4000, MemoryRegionKind::ACPI_RECLAIMABLE),
+            ]
+        );
+    }
+
+    #[test]
+    fn merges_adjacent_regions_of_the_same_kind() {
+        // NOTE: Boot services memory and conventional memory are both
+        // usable, so they merge even though their UEFI types differ.
+        let regions = convert(&[
+            descriptor(UefiMemoryType::CONVENTIONAL, 0x0000, 2),
+            descriptor(UefiMemoryType::BOOT_SERVICES_DATA, 0x2000, 1),
+            descriptor(UefiMemoryType::BOOT_SERVICES_CODE, 0x3000, 3),
+            descriptor(UefiMemoryType::KERNEL_IMAGE, 0x6000, 1),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            regions,
+            [
+                region(0x0000, 0x6000, MemoryRegionKind::USABLE),
+                region(0x6000, 0x7000, MemoryRegionKind::KERNEL_IMAGE),
+            ]
+        );
+    }
+
+    #[test]
+    fn does_not_merge_across_gaps() {
+        let regions = convert(&[
+            descriptor(UefiMemoryType::CONVENTIONAL, 0x0000, 1),
+            descriptor(UefiMemoryType::CONVENTIONAL, 0x2000, 1),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            regions,
+            [
+                region(0x0000, 0x1000, MemoryRegionKind::USABLE),
+                region(0x2000, 0x3000, MemoryRegionKind::USABLE),
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_zero_page_descriptors() {
+        let regions = convert(&[
+            descriptor(UefiMemoryType::CONVENTIONAL, 0x0000, 1),
+            descriptor(UefiMemoryType::ACPI_NON_VOLATILE, 0x1000, 0),
+            descriptor(UefiMemoryType::CONVENTIONAL, 0x1000, 1),
+        ])
+        .unwrap();
+
+        assert_eq!(regions, [region(0x0000, 0x2000, MemoryRegionKind::USABLE)]);
+    }
+
+    #[test]
+    fn zero_page_descriptors_need_no_room() {
+        let descriptors = [
+            descriptor(UefiMemoryType::CONVENTIONAL, 0x0000, 1),
+            descriptor(UefiMemoryType::RESERVED, 0x1000, 0),
+        ];
+        let mut regions = [EMPTY; 1];
+
+        assert_eq!(
+            convert_memory_map(descriptors.iter().copied(), &mut regions),
+            Ok(1)
+        );
+    }
+
+    #[test]
+    fn reports_when_the_output_is_too_small() {
+        let descriptors = [
+            descriptor(UefiMemoryType::CONVENTIONAL, 0x0000, 1),
+            descriptor(UefiMemoryType::CONVENTIONAL, 0x1000, 1),
+        ];
+        let mut regions = [EMPTY; 1];
+
+        // NOTE: The regions would merge into one, but there has to be
+        // room for every descriptor before they're merged.
+        assert_eq!(
+            convert_memory_map(descriptors.iter().copied(), &mut regions),
+            Err(MemoryMapTooLarge)
+        );
+    }
+
+    #[test]
+    fn maps_unknown_and_runtime_types_to_reserved() {
+        assert_eq!(
+            region_kind(UefiMemoryType::RUNTIME_SERVICES_DATA),
+            MemoryRegionKind::RESERVED
+        );
+        assert_eq!(
+            region_kind(UefiMemoryType(0x8000_00FF)),
+            MemoryRegionKind::RESERVED
+        );
+        assert_eq!(
+            region_kind(UefiMemoryType::KERNEL_STACKS),
+            MemoryRegionKind::KERNEL_STACKS
+        );
+    }
+}
@@ -0,0 +1,142 @@
+//! Provides access to the ACPI tables the firmware describes the
+//! platform with, starting from the RSDP.
+//!
+//! For more details see the ACPI specification, section 5.2.
+
+use core::convert::TryInto;
+
+use crate::arch::x86_64::paging::{PhysicalAddress, PhysicalToLinear};
+
+// NOTE: Parsing the tables themselves lives in its own crate, so that
+// it can be tested on the host.
+pub use osc_os_acpi::*;
+
+/// The signature at the start of the RSDP.
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+
+/// The size of the ACPI 1.0 RSDP, which is what its checksum covers.
+const RSDP_V1_SIZE: usize = 20;
+
+/// The size of the ACPI 2.0 RSDP.
+const RSDP_V2_SIZE: usize = 36;
+
+/// The ways in which finding or reading a table can fail.
+#[derive(Debug)]
+pub enum AcpiError {
+    /// The RSDP doesn't start with the right signature.
+    InvalidRsdpSignature,
+
+    /// The bytes of the RSDP don't sum to zero.
+    InvalidRsdpChecksum,
+
+    /// The table at the given address is invalid.
+    InvalidTable(PhysicalAddress, TableError),
+
+    /// The root table doesn't refer to a table with the given
+    /// signature.
+    TableNotFound([u8; 4]),
+
+    /// A table refers to a physical address beyond the processor's
+    /// physical address width.
+    InvalidAddress(u64),
+}
+
+/// Reads the table at the given physical address, checking its length
+/// and checksum.
+///
+/// # Safety
+/// This is unsafe because the address must refer to a table that is
+/// accessible through the given mapping for the lifetime `'a`.
+pub unsafe fn read_table<'a>(
+    physical_address: PhysicalAddress,
+    physical_to_linear: &impl PhysicalToLinear,
+) -> Result<SystemDescriptionTable<'a>, AcpiError> {
+    let ptr = physical_to_linear.to_linear(physical_address).to_raw() as *const u8;
+    let header = core::slice::from_raw_parts(ptr, TABLE_HEADER_SIZE);
+
+    // NOTE: A length shorter than the header is rejected when the table
+    // is checked, so only the header is read in that case.
+    let length = (read_u32(header, 4) as usize).max(TABLE_HEADER_SIZE);
+
+    SystemDescriptionTable::new(core::slice::from_raw_parts(ptr, length))
+        .map_err(|err| AcpiError::InvalidTable(physical_address, err))
+}
+
+/// Finds the table with the given signature, through the XSDT if the
+/// RSDP has one, or the RSDT otherwise.
+///
+/// # Safety
+/// This is unsafe because the address must refer to the RSDP, and it
+/// and every table must be accessible through the given mapping for the
+/// lifetime `'a`.
+pub unsafe fn find_table<'a>(
+    rsdp_address: PhysicalAddress,
+    signature: &[u8; 4],
+    physical_to_linear: &impl PhysicalToLinear,
+) -> Result<SystemDescriptionTable<'a>, AcpiError> {
+    let ptr = physical_to_linear.to_linear(rsdp_address).to_raw() as *const u8;
+    let rsdp = core::slice::from_raw_parts(ptr, RSDP_V1_SIZE);
+
+    if &rsdp[0..8] != RSDP_SIGNATURE {
+        return Err(AcpiError::InvalidRsdpSignature);
+    }
+
+    if checksum(rsdp) != 0 {
+        return Err(AcpiError::InvalidRsdpChecksum);
+    }
+
+    // NOTE: Revision 0 is ACPI 1.0, which only has the RSDT, while
+    // revision 2 onwards adds the XSDT with its 64-bit entries.
+    let revision = rsdp[15];
+
+    let xsdt_address = if revision >= 2 {
+        let rsdp = core::slice::from_raw_parts(ptr, RSDP_V2_SIZE);
+
+        if checksum(rsdp) != 0 {
+            return Err(AcpiError::InvalidRsdpChecksum);
+        }
+
+        read_u64(rsdp, 24)
+    } else {
+        0
+    };
+
+    let (root_address, entry_size) = if xsdt_address != 0 {
+        (xsdt_address, 8)
+    } else {
+        (u64::from(read_u32(rsdp, 16)), 4)
+    };
+
+    let root = read_table(physical_address(root_address)?, physical_to_linear)?;
+
+    for entry in root.body().chunks_exact(entry_size) {
+        let address = if entry_size == 8 {
+            read_u64(entry, 0)
+        } else {
+            u64::from(read_u32(entry, 0))
+        };
+
+        let address = physical_address(address)?;
+        let table_ptr = physical_to_linear.to_linear(address).to_raw() as *const [u8; 4];
+
+        // NOTE: Only the matching table is read in full, so that a bad
+        // checksum elsewhere doesn't hide the table being looked for.
+        if &*table_ptr == signature {
+            return read_table(address, physical_to_linear);
+        }
+    }
+
+    Err(AcpiError::TableNotFound(*signature))
+}
+
+fn physical_address(raw: u64) -> Result<PhysicalAddress, AcpiError> {
+    PhysicalAddress::new(raw).map_err(|_| AcpiError::InvalidAddress(raw))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
@@ -0,0 +1,515 @@
+//! Provides a driver for the I/O APICs, which route device interrupts
+//! to the local APICs through their redirection tables.
+//!
+//! Each I/O APIC has a contiguous range of global system interrupts as
+//! its inputs, starting at its GSI base. ISA IRQs are identity mapped
+//! onto global system interrupts, unless the MADT has an interrupt
+//! source override for them.
+//!
+//! For more details see the Intel 82093AA I/O APIC datasheet.
+
+use alloc::vec::Vec;
+
+use core::convert::TryFrom;
+
+use super::{DeliveryMode, Polarity, TriggerMode};
+use crate::acpi::{self, InterruptSourceOverride, IsaIrqRoute, Madt, MadtEntry, ISA_BUS};
+use crate::arch::x86_64::paging::{LinearAddress, PhysicalAddress, PhysicalToLinear};
+
+/// The offset of the register that selects which register the window
+/// accesses.
+const REGISTER_SELECT_OFFSET: u64 = 0x00;
+
+/// The offset of the window onto the selected register.
+const REGISTER_WINDOW_OFFSET: u64 = 0x10;
+
+/// The register holding the I/O APIC's ID, in bits 24 to 27.
+const ID_REGISTER: u8 = 0x00;
+
+/// The register holding the version and maximum redirection entry.
+const VERSION_REGISTER: u8 = 0x01;
+
+/// The register holding the low half of the first redirection entry,
+/// with each entry taking two registers.
+const REDIRECTION_TABLE_REGISTER: u8 = 0x10;
+
+/// The index of the last redirection entry whose registers can be
+/// selected, since the register select only takes 8 bits.
+const MAX_ADDRESSABLE_ENTRY: u8 = (0xFF - REDIRECTION_TABLE_REGISTER) / 2;
+
+/// The number of ISA IRQs, which are the only IRQs that can be routed
+/// by number rather than by global system interrupt.
+const ISA_IRQ_COUNT: u8 = 16;
+
+/// The ways in which the I/O APICs can fail to be set up or used.
+#[derive(Debug)]
+pub enum IoApicError {
+    /// The MADT lists an I/O APIC beyond the processor's physical
+    /// address width.
+    InvalidAddress(u32),
+
+    /// No I/O APIC has the given global system interrupt as an input.
+    NoIoApic(u32),
+
+    /// The destination APIC ID doesn't fit in the 8 bits of a
+    /// redirection entry.
+    DestinationOutOfRange(u32),
+
+    /// The IRQ isn't one of the 16 ISA IRQs.
+    NotIsaIrq(u8),
+}
+
+/// The value of a redirection table entry, which describes how an
+/// input is delivered.
+///
+/// The layout is as follows:
+///
+/// | Bits  | Meaning                                 |
+/// |-------|-----------------------------------------|
+/// | 0-7   | Vector                                  |
+/// | 8-10  | Delivery mode                           |
+/// | 11    | Destination mode (0 is physical)        |
+/// | 12    | Delivery status (read only)             |
+/// | 13    | Polarity                                |
+/// | 14    | Remote IRR (read only, level triggered) |
+/// | 15    | Trigger mode                            |
+/// | 16    | Masked                                  |
+/// | 56-63 | Destination APIC ID                     |
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct RedirectionEntry(u64);
+
+impl RedirectionEntry {
+    /// Constructs an unmasked entry that delivers fixed interrupts on
+    /// the given vector to the processor with APIC ID 0, edge triggered
+    /// and active high.
+    pub fn new(vector: u8) -> Self {
+        Self(u64::from(vector))
+    }
+
+    /// Constructs a masked entry.
+    pub fn masked() -> Self {
+        Self(1 << 16)
+    }
+
+    /// Constructs an entry from its raw 64-bit value.
+    pub fn from_raw(raw: u64) -> Self {
+        Self(raw)
+    }
+
+    /// Gets the raw 64-bit value.
+    pub fn to_raw(&self) -> u64 {
+        self.0
+    }
+
+    pub fn vector(&self) -> u8 {
+        self.0 as u8
+    }
+
+    pub fn delivery_mode(&self) -> Option<DeliveryMode> {
+        DeliveryMode::from_raw((self.0 >> 8) as u8 & 0b111)
+    }
+
+    /// Determines whether the destination is a logical destination,
+    /// rather than an APIC ID.
+    pub fn is_logical(&self) -> bool {
+        self.0 & (1 << 11) != 0
+    }
+
+    /// Determines whether an interrupt has been signalled but not yet
+    /// delivered.
+    pub fn is_pending(&self) -> bool {
+        self.0 & (1 << 12) != 0
+    }
+
+    pub fn polarity(&self) -> Polarity {
+        if self.0 & (1 << 13) != 0 {
+            Polarity::ActiveLow
+        } else {
+            Polarity::ActiveHigh
+        }
+    }
+
+    /// Determines whether a level triggered interrupt has been accepted
+    /// but not yet ended.
+    pub fn is_remote_irr(&self) -> bool {
+        self.0 & (1 << 14) != 0
+    }
+
+    pub fn trigger_mode(&self) -> TriggerMode {
+        if self.0 & (1 << 15) != 0 {
+            TriggerMode::Level
+        } else {
+            TriggerMode::Edge
+        }
+    }
+
+    pub fn is_masked(&self) -> bool {
+        self.0 & (1 << 16) != 0
+    }
+
+    pub fn destination(&self) -> u8 {
+        (self.0 >> 56) as u8
+    }
+
+    pub fn set_vector(&mut self, vector: u8) -> &mut Self {
+        self.0 = (self.0 & !0xFF) | u64::from(vector);
+        self
+    }
+
+    pub fn set_delivery_mode(&mut self, delivery_mode: DeliveryMode) -> &mut Self {
+        self.0 = (self.0 & !(0b111 << 8)) | (delivery_mode as u64) << 8;
+        self
+    }
+
+    pub fn set_polarity(&mut self, polarity: Polarity) -> &mut Self {
+        self.set_bit(13, polarity == Polarity::ActiveLow)
+    }
+
+    pub fn set_trigger_mode(&mut self, trigger_mode: TriggerMode) -> &mut Self {
+        self.set_bit(15, trigger_mode == TriggerMode::Level)
+    }
+
+    pub fn set_masked(&mut self, masked: bool) -> &mut Self {
+        self.set_bit(16, masked)
+    }
+
+    /// Sets the APIC ID of the processor the interrupt is delivered to,
+    /// in physical destination mode.
+    pub fn set_destination(&mut self, apic_id: u8) -> &mut Self {
+        self.set_bit(11, false);
+        self.0 = (self.0 & !(0xFF << 56)) | u64::from(apic_id) << 56;
+        self
+    }
+
+    fn set_bit(&mut self, bit: u32, value: bool) -> &mut Self {
+        if value {
+            self.0 |= 1 << bit;
+        } else {
+            self.0 &= !(1 << bit);
+        }
+
+        self
+    }
+}
+
+impl core::fmt::Debug for RedirectionEntry {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("RedirectionEntry")
+            .field("vector", &self.vector())
+            .field("delivery_mode", &self.delivery_mode())
+            .field("logical", &self.is_logical())
+            .field("pending", &self.is_pending())
+            .field("polarity", &self.polarity())
+            .field("remote_irr", &self.is_remote_irr())
+            .field("trigger_mode", &self.trigger_mode())
+            .field("masked", &self.is_masked())
+            .field("destination", &self.destination())
+            .finish()
+    }
+}
+
+/// A single I/O APIC.
+pub struct IoApic {
+    id: u8,
+    registers: LinearAddress,
+    gsi_base: u32,
+    max_redirection_entry: u8,
+}
+
+// NOTE: This is safe because the I/O APIC's registers can be accessed
+// from any processor.
+unsafe impl Send for IoApic {}
+
+impl IoApic {
+    /// Constructs a driver for the I/O APIC whose registers are at the
+    /// given physical address, accessed through the given mapping.
+    ///
+    /// # Safety
+    /// This is unsafe because the address must refer to an I/O APIC
+    /// that's mapped as uncacheable, and nothing else may be using it.
+    pub unsafe fn new(
+        id: u8,
+        physical_address: PhysicalAddress,
+        gsi_base: u32,
+        physical_to_linear: &impl PhysicalToLinear,
+    ) -> Self {
+        let mut io_apic = Self {
+            id,
+            registers: physical_to_linear.to_linear(physical_address),
+            gsi_base,
+            max_redirection_entry: 0,
+        };
+
+        // NOTE: The version register has room for more entries than
+        // the register select can reach, so any beyond that are left
+        // alone rather than aliasing onto other registers.
+        io_apic.max_redirection_entry =
+            ((io_apic.read(VERSION_REGISTER) >> 16) as u8).min(MAX_ADDRESSABLE_ENTRY);
+
+        io_apic
+    }
+
+    /// Gets the ID the MADT gives the I/O APIC.
+    pub fn id(&self) -> u8 {
+        self.id
+    }
+
+    /// Gets the ID the I/O APIC reports itself, which the firmware
+    /// should have set to match the MADT.
+    pub fn hardware_id(&self) -> u8 {
+        (self.read(ID_REGISTER) >> 24) as u8 & 0xF
+    }
+
+    pub fn version(&self) -> u8 {
+        self.read(VERSION_REGISTER) as u8
+    }
+
+    /// Gets the index of the last redirection entry, which is one less
+    /// than the number of inputs that can be used.
+    pub fn max_redirection_entry(&self) -> u8 {
+        self.max_redirection_entry
+    }
+
+    /// Gets the global system interrupt of the first input.
+    pub fn gsi_base(&self) -> u32 {
+        self.gsi_base
+    }
+
+    /// Gets the index of the redirection entry for the given global
+    /// system interrupt, if it's one of the I/O APIC's inputs.
+    pub fn entry_index(&self, gsi: u32) -> Option<u8> {
+        let index = gsi.checked_sub(self.gsi_base)?;
+
+        if index <= u32::from(self.max_redirection_entry) {
+            Some(index as u8)
+        } else {
+            None
+        }
+    }
+
+    /// Gets the redirection entry at the given index, panicking if it's
+    /// beyond `max_redirection_entry`.
+    pub fn redirection_entry(&self, index: u8) -> RedirectionEntry {
+        let register = self.entry_register(index);
+        let low = self.read(register);
+        let high = self.read(register + 1);
+
+        RedirectionEntry::from_raw(u64::from(high) << 32 | u64::from(low))
+    }
+
+    /// Sets the redirection entry at the given index, panicking if it's
+    /// beyond `max_redirection_entry`.
+    pub fn set_redirection_entry(&mut self, index: u8, entry: RedirectionEntry) {
+        let register = self.entry_register(index);
+        let raw = entry.to_raw();
+
+        // NOTE: The entry is masked while it's being changed, so that an
+        // interrupt can't be delivered using half of the old entry and
+        // half of the new one.
+        self.write(register, (raw as u32) | (1 << 16));
+        self.write(register + 1, (raw >> 32) as u32);
+        self.write(register, raw as u32);
+    }
+
+    /// Masks or unmasks the redirection entry at the given index.
+    pub fn set_masked(&mut self, index: u8, masked: bool) {
+        let mut entry = self.redirection_entry(index);
+        entry.set_masked(masked);
+        self.set_redirection_entry(index, entry);
+    }
+
+    /// Masks every redirection entry.
+    pub fn mask_all(&mut self) {
+        for index in 0..=self.max_redirection_entry {
+            self.set_masked(index, true);
+        }
+    }
+
+    fn entry_register(&self, index: u8) -> u8 {
+        assert!(
+            index <= self.max_redirection_entry,
+            "redirection entry {} is beyond the I/O APIC's {} inputs",
+            index,
+            u16::from(self.max_redirection_entry) + 1
+        );
+
+        let register = u32::from(REDIRECTION_TABLE_REGISTER) + u32::from(index) * 2;
+
+        // NOTE: This can't fail, since `new` limits the entries to those
+        // whose registers can be selected.
+        u8::try_from(register).expect("redirection entry register out of range")
+    }
+
+    fn read(&self, register: u8) -> u32 {
+        let base = self.registers.to_raw();
+
+        // NOTE: This is safe because the registers were given when the
+        // driver was constructed.
+        unsafe {
+            ((base + REGISTER_SELECT_OFFSET) as *mut u32).write_volatile(u32::from(register));
+            ((base + REGISTER_WINDOW_OFFSET) as *const u32).read_volatile()
+        }
+    }
+
+    fn write(&mut self, register: u8, value: u32) {
+        let base = self.registers.to_raw();
+
+        // NOTE: This is safe for the same reasons as reading.
+        unsafe {
+            ((base + REGISTER_SELECT_OFFSET) as *mut u32).write_volatile(u32::from(register));
+            ((base + REGISTER_WINDOW_OFFSET) as *mut u32).write_volatile(value);
+        }
+    }
+}
+
+impl core::fmt::Debug for IoApic {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("IoApic")
+            .field("id", &self.id)
+            .field("registers", &self.registers)
+            .field("gsi_base", &self.gsi_base)
+            .field("max_redirection_entry", &self.max_redirection_entry)
+            .finish()
+    }
+}
+
+/// Routes interrupts to processors through every I/O APIC in the
+/// system, as described by the MADT.
+#[derive(Debug)]
+pub struct InterruptRouter {
+    io_apics: Vec<IoApic>,
+    isa_overrides: Vec<InterruptSourceOverride>,
+}
+
+impl InterruptRouter {
+    /// Constructs drivers for the I/O APICs listed in the MADT, and
+    /// records its interrupt source overrides for ISA IRQs.
+    ///
+    /// # Safety
+    /// This is unsafe because the I/O APICs' registers must be mapped
+    /// as uncacheable through the given mapping, and nothing else may
+    /// be using them.
+    pub unsafe fn from_madt(
+        madt: &Madt<'_>,
+        physical_to_linear: &impl PhysicalToLinear,
+    ) -> Result<Self, IoApicError> {
+        let mut io_apics = Vec::new();
+        let mut isa_overrides = Vec::new();
+
+        for entry in madt.entries() {
+            match entry {
+                MadtEntry::IoApic {
+                    id,
+                    address,
+                    global_system_interrupt_base,
+                } => {
+                    let physical_address = PhysicalAddress::new(u64::from(address))
+                        .map_err(|_| IoApicError::InvalidAddress(address))?;
+
+                    io_apics.push(IoApic::new(
+                        id,
+                        physical_address,
+                        global_system_interrupt_base,
+                        physical_to_linear,
+                    ));
+                }
+                MadtEntry::InterruptSourceOverride(source_override)
+                    if source_override.bus == ISA_BUS =>
+                {
+                    isa_overrides.push(source_override);
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            io_apics,
+            isa_overrides,
+        })
+    }
+
+    pub fn io_apics(&self) -> &[IoApic] {
+        &self.io_apics
+    }
+
+    /// Gets where the given ISA IRQ arrives.
+    ///
+    /// Without an override, ISA IRQs are identity mapped, edge
+    /// triggered and active high.
+    pub fn isa_irq_route(&self, isa_irq: u8) -> IsaIrqRoute {
+        acpi::isa_irq_route(isa_irq, &self.isa_overrides)
+    }
+
+    /// Delivers the given ISA IRQ to the processor with the given APIC
+    /// ID on the given vector, and unmasks it, returning the global
+    /// system interrupt it arrives on.
+    ///
+    /// Only ISA IRQs 0 to 15 can be routed this way, other interrupts
+    /// are routed by global system interrupt with `set_gsi_entry`.
+    pub fn route_irq(&mut self, isa_irq: u8, vector: u8, cpu: u32) -> Result<u32, IoApicError> {
+        check_isa_irq(isa_irq)?;
+
+        if cpu > 0xFF {
+            return Err(IoApicError::DestinationOutOfRange(cpu));
+        }
+
+        let route = self.isa_irq_route(isa_irq);
+
+        let mut entry = RedirectionEntry::new(vector);
+        entry
+            .set_polarity(route.polarity)
+            .set_trigger_mode(route.trigger_mode)
+            .set_destination(cpu as u8);
+
+        self.set_gsi_entry(route.gsi, entry)?;
+
+        Ok(route.gsi)
+    }
+
+    /// Masks the given ISA IRQ.
+    pub fn mask_irq(&mut self, isa_irq: u8) -> Result<(), IoApicError> {
+        check_isa_irq(isa_irq)?;
+
+        let gsi = self.isa_irq_route(isa_irq).gsi;
+        let (io_apic, index) = self.io_apic_for(gsi)?;
+
+        io_apic.set_masked(index, true);
+
+        Ok(())
+    }
+
+    /// Sets the redirection entry for the given global system interrupt,
+    /// for interrupts that aren't ISA IRQs.
+    pub fn set_gsi_entry(&mut self, gsi: u32, entry: RedirectionEntry) -> Result<(), IoApicError> {
+        let (io_apic, index) = self.io_apic_for(gsi)?;
+
+        io_apic.set_redirection_entry(index, entry);
+
+        Ok(())
+    }
+
+    /// Masks every input of every I/O APIC.
+    pub fn mask_all(&mut self) {
+        for io_apic in &mut self.io_apics {
+            io_apic.mask_all();
+        }
+    }
+
+    fn io_apic_for(&mut self, gsi: u32) -> Result<(&mut IoApic, u8), IoApicError> {
+        self.io_apics
+            .iter_mut()
+            .find_map(|io_apic| {
+                let index = io_apic.entry_index(gsi)?;
+                Some((io_apic, index))
+            })
+            .ok_or(IoApicError::NoIoApic(gsi))
+    }
+}
+
+fn check_isa_irq(isa_irq: u8) -> Result<(), IoApicError> {
+    if isa_irq < ISA_IRQ_COUNT {
+        Ok(())
+    } else {
+        Err(IoApicError::NotIsaIrq(isa_irq))
+    }
+}
@@ -0,0 +1,635 @@
+//! Provides a driver for the local APIC, in either xAPIC mode (where
+//! its registers are memory mapped) or x2APIC mode (where they're
+//! model specific registers).
+//!
+//! In x2APIC mode, the register at offset `n` of the xAPIC's MMIO page
+//! is MSR `0x800 + n / 16`, with the exception of the interrupt command
+//! register, which becomes a single 64-bit MSR.
+
+use bitflags::bitflags;
+
+use super::{DeliveryMode, Polarity, TriggerMode};
+use crate::arch::x86_64::cpuid;
+use crate::arch::x86_64::msr::{ModelSpecificRegister, IA32_APIC_BASE};
+use crate::arch::x86_64::paging::{LinearAddress, PhysicalAddress, PhysicalToLinear};
+
+/// IA32_APIC_BASE: The processor is the bootstrap processor.
+const APIC_BASE_BSP: u64 = 1 << 8;
+
+/// IA32_APIC_BASE: x2APIC mode is enabled.
+const APIC_BASE_EXTD: u64 = 1 << 10;
+
+/// IA32_APIC_BASE: The local APIC is enabled.
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+
+/// IA32_APIC_BASE: The physical address of the xAPIC's registers.
+const APIC_BASE_ADDRESS_MASK: u64 = !0xFFF;
+
+/// The first of the MSRs used to access the registers in x2APIC mode.
+const X2APIC_MSR_BASE: u32 = 0x800;
+
+/// Spurious interrupt vector register: The APIC is software enabled.
+const SPURIOUS_APIC_ENABLE: u32 = 1 << 8;
+
+/// Interrupt command register: The interrupt hasn't been accepted yet
+/// (xAPIC mode only).
+const ICR_DELIVERY_PENDING: u32 = 1 << 12;
+
+/// Interrupt command register: The interrupt is asserted, which is
+/// required for everything other than the legacy INIT level de-assert.
+const ICR_LEVEL_ASSERT: u32 = 1 << 14;
+
+/// A register, identified by its offset in the xAPIC's MMIO page.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Register(u32);
+
+impl Register {
+    const ID: Self = Self(0x020);
+    const VERSION: Self = Self(0x030);
+    const TASK_PRIORITY: Self = Self(0x080);
+    const END_OF_INTERRUPT: Self = Self(0x0B0);
+    const SPURIOUS_VECTOR: Self = Self(0x0F0);
+    const ERROR_STATUS: Self = Self(0x280);
+    const LVT_CORRECTED_MACHINE_CHECK: Self = Self(0x2F0);
+    const INTERRUPT_COMMAND_LOW: Self = Self(0x300);
+    const INTERRUPT_COMMAND_HIGH: Self = Self(0x310);
+    const LVT_TIMER: Self = Self(0x320);
+    const LVT_THERMAL: Self = Self(0x330);
+    const LVT_PERFORMANCE_COUNTER: Self = Self(0x340);
+    const LVT_LINT0: Self = Self(0x350);
+    const LVT_LINT1: Self = Self(0x360);
+    const LVT_ERROR: Self = Self(0x370);
+
+    fn msr(self) -> ModelSpecificRegister {
+        ModelSpecificRegister::from_raw(X2APIC_MSR_BASE + (self.0 >> 4))
+    }
+}
+
+/// The ways in which the local APIC can fail to be set up or used.
+#[derive(Debug)]
+pub enum ApicError {
+    /// The processor doesn't have a local APIC.
+    Unsupported,
+
+    /// x2APIC mode was requested, but isn't supported.
+    X2ApicUnsupported,
+
+    /// The xAPIC's registers don't fit the physical address width.
+    InvalidBaseAddress(u64),
+
+    /// The destination APIC ID doesn't fit in xAPIC mode's 8 bits.
+    DestinationOutOfRange(u32),
+
+    /// The IPI can't be sent to the processor sending it.
+    InvalidSelfIpi(Ipi),
+}
+
+/// The modes the local APIC can be enabled in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ApicMode {
+    /// Registers are memory mapped, and APIC IDs are 8 bits.
+    XApic,
+
+    /// Registers are MSRs, and APIC IDs are 32 bits.
+    X2Apic,
+}
+
+impl ApicMode {
+    /// Gets the best mode the processor supports, if it has a local
+    /// APIC at all.
+    pub fn detect() -> Option<Self> {
+        if cpuid::has_x2apic() {
+            Some(Self::X2Apic)
+        } else if cpuid::has_apic() {
+            Some(Self::XApic)
+        } else {
+            None
+        }
+    }
+
+    /// Gets the mode the local APIC is currently in, or `None` if it's
+    /// globally disabled.
+    ///
+    /// # Safety
+    /// This is unsafe because the processor must have a local APIC.
+    pub unsafe fn current() -> Option<Self> {
+        let base = IA32_APIC_BASE.read();
+
+        if base & APIC_BASE_ENABLE == 0 {
+            None
+        } else if base & APIC_BASE_EXTD != 0 {
+            Some(Self::X2Apic)
+        } else {
+            Some(Self::XApic)
+        }
+    }
+}
+
+/// The local vector table entries, which each describe how a local
+/// interrupt source is delivered.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LocalVector {
+    Timer,
+    Thermal,
+    PerformanceCounter,
+    LInt0,
+    LInt1,
+    Error,
+    CorrectedMachineCheck,
+}
+
+impl LocalVector {
+    fn register(self) -> Register {
+        match self {
+            Self::Timer => Register::LVT_TIMER,
+            Self::Thermal => Register::LVT_THERMAL,
+            Self::PerformanceCounter => Register::LVT_PERFORMANCE_COUNTER,
+            Self::LInt0 => Register::LVT_LINT0,
+            Self::LInt1 => Register::LVT_LINT1,
+            Self::Error => Register::LVT_ERROR,
+            Self::CorrectedMachineCheck => Register::LVT_CORRECTED_MACHINE_CHECK,
+        }
+    }
+}
+
+/// The modes the local APIC timer can count in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TimerMode {
+    OneShot = 0b00,
+    Periodic = 0b01,
+    TscDeadline = 0b10,
+}
+
+/// The value of a local vector table entry.
+///
+/// The layout is as follows:
+///
+/// | Bits  | Meaning                                            |
+/// |-------|----------------------------------------------------|
+/// | 0-7   | Vector                                             |
+/// | 8-10  | Delivery mode (not for the timer or error entries) |
+/// | 12    | Delivery status (read only)                        |
+/// | 13    | Polarity (LINT0 and LINT1 only)                    |
+/// | 14    | Remote IRR (read only, LINT0 and LINT1 only)       |
+/// | 15    | Trigger mode (LINT0 and LINT1 only)                |
+/// | 16    | Masked                                             |
+/// | 17-18 | Timer mode (timer only)                            |
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct LocalVectorEntry(u32);
+
+impl LocalVectorEntry {
+    /// Constructs an unmasked entry that delivers fixed interrupts on
+    /// the given vector, edge triggered and active high.
+    pub fn new(vector: u8) -> Self {
+        Self(u32::from(vector))
+    }
+
+    /// Constructs a masked entry.
+    pub fn masked() -> Self {
+        Self(1 << 16)
+    }
+
+    /// Constructs an entry from its raw 32-bit value.
+    pub fn from_raw(raw: u32) -> Self {
+        Self(raw)
+    }
+
+    /// Gets the raw 32-bit value.
+    pub fn to_raw(&self) -> u32 {
+        self.0
+    }
+
+    pub fn vector(&self) -> u8 {
+        self.0 as u8
+    }
+
+    pub fn delivery_mode(&self) -> Option<DeliveryMode> {
+        DeliveryMode::from_raw((self.0 >> 8) as u8 & 0b111)
+    }
+
+    /// Determines whether an interrupt has been sent to the processor
+    /// but not yet accepted.
+    pub fn is_pending(&self) -> bool {
+        self.0 & (1 << 12) != 0
+    }
+
+    pub fn polarity(&self) -> Polarity {
+        if self.0 & (1 << 13) != 0 {
+            Polarity::ActiveLow
+        } else {
+            Polarity::ActiveHigh
+        }
+    }
+
+    pub fn trigger_mode(&self) -> TriggerMode {
+        if self.0 & (1 << 15) != 0 {
+            TriggerMode::Level
+        } else {
+            TriggerMode::Edge
+        }
+    }
+
+    pub fn is_masked(&self) -> bool {
+        self.0 & (1 << 16) != 0
+    }
+
+    pub fn timer_mode(&self) -> Option<TimerMode> {
+        match (self.0 >> 17) & 0b11 {
+            0b00 => Some(TimerMode::OneShot),
+            0b01 => Some(TimerMode::Periodic),
+            0b10 => Some(TimerMode::TscDeadline),
+            _ => None,
+        }
+    }
+
+    pub fn set_vector(&mut self, vector: u8) -> &mut Self {
+        self.0 = (self.0 & !0xFF) | u32::from(vector);
+        self
+    }
+
+    pub fn set_delivery_mode(&mut self, delivery_mode: DeliveryMode) -> &mut Self {
+        self.0 = (self.0 & !(0b111 << 8)) | (delivery_mode as u32) << 8;
+        self
+    }
+
+    pub fn set_polarity(&mut self, polarity: Polarity) -> &mut Self {
+        self.set_bit(13, polarity == Polarity::ActiveLow)
+    }
+
+    pub fn set_trigger_mode(&mut self, trigger_mode: TriggerMode) -> &mut Self {
+        self.set_bit(15, trigger_mode == TriggerMode::Level)
+    }
+
+    pub fn set_masked(&mut self, masked: bool) -> &mut Self {
+        self.set_bit(16, masked)
+    }
+
+    pub fn set_timer_mode(&mut self, timer_mode: TimerMode) -> &mut Self {
+        self.0 = (self.0 & !(0b11 << 17)) | (timer_mode as u32) << 17;
+        self
+    }
+
+    fn set_bit(&mut self, bit: u32, value: bool) -> &mut Self {
+        if value {
+            self.0 |= 1 << bit;
+        } else {
+            self.0 &= !(1 << bit);
+        }
+
+        self
+    }
+}
+
+impl core::fmt::Debug for LocalVectorEntry {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("LocalVectorEntry")
+            .field("vector", &self.vector())
+            .field("delivery_mode", &self.delivery_mode())
+            .field("pending", &self.is_pending())
+            .field("polarity", &self.polarity())
+            .field("trigger_mode", &self.trigger_mode())
+            .field("masked", &self.is_masked())
+            .finish()
+    }
+}
+
+bitflags! {
+    /// The errors the local APIC has detected, see Intel 3A - 10.5.3.
+    pub struct ErrorStatus: u32 {
+        /// A sent message failed its checksum (P6 and Pentium only).
+        const SEND_CHECKSUM = 1 << 0;
+
+        /// A received message failed its checksum (P6 and Pentium
+        /// only).
+        const RECEIVE_CHECKSUM = 1 << 1;
+
+        /// A sent message wasn't accepted by any APIC (P6 and Pentium
+        /// only).
+        const SEND_ACCEPT = 1 << 2;
+
+        /// A received message wasn't accepted by any APIC, including
+        /// this one (P6 and Pentium only).
+        const RECEIVE_ACCEPT = 1 << 3;
+
+        /// A lowest priority IPI was sent, which isn't supported.
+        const REDIRECTABLE_IPI = 1 << 4;
+
+        /// An IPI with a vector below 16 was sent.
+        const SEND_ILLEGAL_VECTOR = 1 << 5;
+
+        /// An interrupt with a vector below 16 was received.
+        const RECEIVE_ILLEGAL_VECTOR = 1 << 6;
+
+        /// A register that doesn't exist was accessed (xAPIC mode
+        /// only).
+        const ILLEGAL_REGISTER_ADDRESS = 1 << 7;
+    }
+}
+
+/// The inter-processor interrupts that can be sent.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Ipi {
+    /// An ordinary interrupt on the given vector.
+    Fixed(u8),
+
+    /// A non-maskable interrupt.
+    Nmi,
+
+    /// Resets the processor into the wait-for-SIPI state.
+    Init,
+
+    /// Starts a processor in the wait-for-SIPI state executing in real
+    /// mode at the start of the given page (so at `page * 0x1000`).
+    StartUp(u8),
+}
+
+impl Ipi {
+    fn command(self) -> u32 {
+        let (delivery_mode, vector) = match self {
+            Self::Fixed(vector) => (DeliveryMode::Fixed, vector),
+            Self::Nmi => (DeliveryMode::Nmi, 0),
+            Self::Init => (DeliveryMode::Init, 0),
+            Self::StartUp(page) => (DeliveryMode::StartUp, page),
+        };
+
+        u32::from(vector) | (delivery_mode as u32) << 8 | ICR_LEVEL_ASSERT
+    }
+}
+
+/// The processors an IPI can be sent to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IpiDestination {
+    /// The processor with the given APIC ID.
+    Apic(u32),
+
+    /// The processor sending the IPI, which is only valid for fixed
+    /// IPIs.
+    SendingProcessor,
+
+    /// Every processor, including the one sending the IPI.
+    All,
+
+    /// Every processor, other than the one sending the IPI.
+    AllOthers,
+}
+
+impl IpiDestination {
+    fn shorthand(self) -> u32 {
+        let shorthand = match self {
+            Self::Apic(_) => 0b00,
+            Self::SendingProcessor => 0b01,
+            Self::All => 0b10,
+            Self::AllOthers => 0b11,
+        };
+
+        shorthand << 18
+    }
+}
+
+/// How the registers are accessed.
+#[derive(Debug, Copy, Clone)]
+enum Registers {
+    Mmio(LinearAddress),
+    Msr,
+}
+
+/// The local APIC of the processor it's used on.
+///
+/// Every processor has its own local APIC at the same address (or
+/// MSRs), so a `LocalApic` always refers to the current processor's,
+/// and has to be constructed on each processor that uses one.
+pub struct LocalApic {
+    mode: ApicMode,
+    registers: Registers,
+}
+
+impl LocalApic {
+    /// Globally enables the current processor's local APIC in the given
+    /// mode, without software enabling it.
+    ///
+    /// In xAPIC mode, the registers are accessed through the given
+    /// mapping, which must map them as uncacheable.
+    ///
+    /// # Safety
+    /// This is unsafe because switching modes changes how every other
+    /// user of the local APIC has to access it, and any interrupts that
+    /// are in flight during the switch may be lost.
+    pub unsafe fn new(
+        mode: ApicMode,
+        physical_to_linear: &impl PhysicalToLinear,
+    ) -> Result<Self, ApicError> {
+        match ApicMode::detect() {
+            None => return Err(ApicError::Unsupported),
+            Some(ApicMode::XApic) if mode == ApicMode::X2Apic => {
+                return Err(ApicError::X2ApicUnsupported)
+            }
+            _ => {}
+        }
+
+        let base = IA32_APIC_BASE.read();
+        let xapic_base = (base & !APIC_BASE_EXTD) | APIC_BASE_ENABLE;
+
+        match mode {
+            ApicMode::XApic => {
+                // NOTE: Going from x2APIC mode straight to xAPIC mode
+                // isn't allowed, so the APIC has to be globally disabled
+                // in between.
+                if base & APIC_BASE_EXTD != 0 {
+                    IA32_APIC_BASE.write(base & !(APIC_BASE_ENABLE | APIC_BASE_EXTD));
+                }
+
+                if base != xapic_base {
+                    IA32_APIC_BASE.write(xapic_base);
+                }
+            }
+            ApicMode::X2Apic => {
+                // NOTE: Likewise, x2APIC mode can only be entered from
+                // xAPIC mode, so a disabled APIC has to be enabled first.
+                // An APIC that's already in x2APIC mode is left alone,
+                // since clearing the extended bit alone isn't allowed.
+                if base & APIC_BASE_EXTD == 0 {
+                    if base & APIC_BASE_ENABLE == 0 {
+                        IA32_APIC_BASE.write(xapic_base);
+                    }
+
+                    IA32_APIC_BASE.write(xapic_base | APIC_BASE_EXTD);
+                }
+            }
+        }
+
+        let registers = match mode {
+            ApicMode::XApic => {
+                let raw_address = xapic_base & APIC_BASE_ADDRESS_MASK;
+                let physical_address = PhysicalAddress::new(raw_address)
+                    .map_err(|_| ApicError::InvalidBaseAddress(raw_address))?;
+
+                Registers::Mmio(physical_to_linear.to_linear(physical_address))
+            }
+            ApicMode::X2Apic => Registers::Msr,
+        };
+
+        Ok(Self { mode, registers })
+    }
+
+    /// Determines whether the current processor is the bootstrap
+    /// processor.
+    ///
+    /// # Safety
+    /// This is unsafe because the processor must have a local APIC.
+    pub unsafe fn is_bootstrap_processor() -> bool {
+        IA32_APIC_BASE.read() & APIC_BASE_BSP != 0
+    }
+
+    pub fn mode(&self) -> ApicMode {
+        self.mode
+    }
+
+    /// Gets the APIC ID, which is 8 bits in xAPIC mode and 32 bits in
+    /// x2APIC mode.
+    pub fn id(&self) -> u32 {
+        let id = self.read(Register::ID);
+
+        match self.mode {
+            ApicMode::XApic => id >> 24,
+            ApicMode::X2Apic => id,
+        }
+    }
+
+    /// Gets the version of the local APIC, where 0x10 and above are
+    /// integrated APICs.
+    pub fn version(&self) -> u8 {
+        self.read(Register::VERSION) as u8
+    }
+
+    /// Gets the number of local vector table entries, less one.
+    pub fn max_lvt_entry(&self) -> u8 {
+        (self.read(Register::VERSION) >> 16) as u8
+    }
+
+    /// Software enables the local APIC, delivering spurious interrupts
+    /// on the given vector.
+    ///
+    /// The lowest 4 bits of the vector are fixed at 1 on some older
+    /// processors, so it should end in 0xF.
+    pub fn enable(&mut self, spurious_vector: u8) {
+        self.write(
+            Register::SPURIOUS_VECTOR,
+            u32::from(spurious_vector) | SPURIOUS_APIC_ENABLE,
+        );
+    }
+
+    /// Software disables the local APIC, which masks every local vector
+    /// table entry until it's enabled again.
+    pub fn disable(&mut self) {
+        let value = self.read(Register::SPURIOUS_VECTOR);
+        self.write(Register::SPURIOUS_VECTOR, value & !SPURIOUS_APIC_ENABLE);
+    }
+
+    pub fn spurious_vector(&self) -> u8 {
+        self.read(Register::SPURIOUS_VECTOR) as u8
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.read(Register::SPURIOUS_VECTOR) & SPURIOUS_APIC_ENABLE != 0
+    }
+
+    /// Sets the priority class below which interrupts aren't delivered,
+    /// where a class is the upper 4 bits of a vector.
+    pub fn set_task_priority(&mut self, class: u8) {
+        self.write(Register::TASK_PRIORITY, u32::from(class & 0xF) << 4);
+    }
+
+    /// Signals that the handler for the current interrupt has finished.
+    ///
+    /// This mustn't be done for spurious interrupts, or for NMI, SMI,
+    /// INIT, start-up or ExtInt interrupts.
+    pub fn end_of_interrupt(&mut self) {
+        self.write(Register::END_OF_INTERRUPT, 0);
+    }
+
+    pub fn local_vector(&self, vector: LocalVector) -> LocalVectorEntry {
+        LocalVectorEntry::from_raw(self.read(vector.register()))
+    }
+
+    pub fn set_local_vector(&mut self, vector: LocalVector, entry: LocalVectorEntry) {
+        self.write(vector.register(), entry.to_raw());
+    }
+
+    /// Gets the errors detected since this was last called.
+    pub fn error_status(&mut self) -> ErrorStatus {
+        // NOTE: The register only latches the errors when it's written,
+        // and has to be written as zero in x2APIC mode.
+        self.write(Register::ERROR_STATUS, 0);
+
+        ErrorStatus::from_bits_truncate(self.read(Register::ERROR_STATUS))
+    }
+
+    /// Sends an inter-processor interrupt, waiting until it has been
+    /// accepted in xAPIC mode.
+    ///
+    /// A sequence of INIT, then start-up IPIs (with the appropriate
+    /// delays in between) starts up an application processor.
+    pub fn send_ipi(&mut self, ipi: Ipi, destination: IpiDestination) -> Result<(), ApicError> {
+        if destination == IpiDestination::SendingProcessor && !matches!(ipi, Ipi::Fixed(_)) {
+            return Err(ApicError::InvalidSelfIpi(ipi));
+        }
+
+        let command = ipi.command() | destination.shorthand();
+        let apic_id = match destination {
+            IpiDestination::Apic(apic_id) => apic_id,
+            _ => 0,
+        };
+
+        match self.registers {
+            Registers::Mmio(_) => {
+                if apic_id > 0xFF {
+                    return Err(ApicError::DestinationOutOfRange(apic_id));
+                }
+
+                // NOTE: Writing the low half is what sends the IPI, so
+                // the destination has to be written first.
+                self.write(Register::INTERRUPT_COMMAND_HIGH, apic_id << 24);
+                self.write(Register::INTERRUPT_COMMAND_LOW, command);
+
+                while self.read(Register::INTERRUPT_COMMAND_LOW) & ICR_DELIVERY_PENDING != 0 {
+                    core::sync::atomic::spin_loop_hint();
+                }
+            }
+            Registers::Msr => unsafe {
+                Register::INTERRUPT_COMMAND_LOW
+                    .msr()
+                    .write(u64::from(apic_id) << 32 | u64::from(command));
+            },
+        }
+
+        Ok(())
+    }
+
+    fn read(&self, register: Register) -> u32 {
+        // NOTE: This is safe because the registers were set up when the
+        // APIC was enabled, and only registers that exist are accessed.
+        unsafe {
+            match self.registers {
+                Registers::Mmio(base) => {
+                    let ptr = (base.to_raw() + u64::from(register.0)) as *const u32;
+                    ptr.read_volatile()
+                }
+                Registers::Msr => register.msr().read() as u32,
+            }
+        }
+    }
+
+    fn write(&mut self, register: Register, value: u32) {
+        // NOTE: This is safe for the same reasons as reading.
+        unsafe {
+            match self.registers {
+                Registers::Mmio(base) => {
+                    let ptr = (base.to_raw() + u64::from(register.0)) as *mut u32;
+                    ptr.write_volatile(value)
+                }
+                Registers::Msr => register.msr().write(u64::from(value)),
+            }
+        }
+    }
+}
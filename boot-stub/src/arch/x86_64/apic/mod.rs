@@ -0,0 +1,64 @@
+//! Provides drivers for the advanced programmable interrupt
+//! controllers: the local APIC in each processor, and the I/O APICs
+//! that route device interrupts to them.
+//!
+//! For more details see Intel 3A - 10.
+
+mod io;
+pub use io::*;
+
+mod local;
+pub use local::*;
+
+// NOTE: The MADT describes interrupt pins in the same terms, so these
+// come from the ACPI crate.
+pub use osc_os_acpi::{Polarity, TriggerMode};
+
+/// How an interrupt is delivered to the processors it targets.
+///
+/// Not every mode is valid everywhere: LVT entries only accept `Fixed`,
+/// `Smi`, `Nmi`, `Init` and `ExtInt`, and `StartUp` is only valid in
+/// an IPI.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u8)]
+pub enum DeliveryMode {
+    /// Delivers the interrupt on its vector.
+    Fixed = 0b000,
+
+    /// Delivers the interrupt on its vector, to whichever of the
+    /// targeted processors is running at the lowest priority.
+    LowestPriority = 0b001,
+
+    /// Delivers a system management interrupt, ignoring the vector.
+    Smi = 0b010,
+
+    /// Delivers a non-maskable interrupt, ignoring the vector.
+    Nmi = 0b100,
+
+    /// Delivers an INIT request, ignoring the vector.
+    Init = 0b101,
+
+    /// Delivers a start-up request, with the vector giving the page
+    /// the processor starts executing at.
+    StartUp = 0b110,
+
+    /// Delivers the interrupt as if it came from an 8259 PIC, which
+    /// supplies the vector.
+    ExtInt = 0b111,
+}
+
+impl DeliveryMode {
+    /// Decodes a delivery mode from its 3-bit encoding, if it's valid.
+    pub fn from_raw(raw: u8) -> Option<Self> {
+        match raw {
+            0b000 => Some(Self::Fixed),
+            0b001 => Some(Self::LowestPriority),
+            0b010 => Some(Self::Smi),
+            0b100 => Some(Self::Nmi),
+            0b101 => Some(Self::Init),
+            0b110 => Some(Self::StartUp),
+            0b111 => Some(Self::ExtInt),
+            _ => None,
+        }
+    }
+}
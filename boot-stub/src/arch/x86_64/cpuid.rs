@@ -0,0 +1,122 @@
+//! Provides access to the processor identification and feature
+//! information reported by the CPUID instruction.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+/// The registers returned by a CPUID query.
+#[derive(Debug, Copy, Clone)]
+pub struct CpuidResult {
+    pub eax: u32,
+    pub ebx: u32,
+    pub ecx: u32,
+    pub edx: u32,
+}
+
+/// Queries the given leaf (and subleaf, for leaves that have them).
+///
+/// The leaf must be no greater than `max_leaf` (or `max_extended_leaf`
+/// for leaves from 0x80000000 upwards), otherwise the processor returns
+/// the contents of the highest basic leaf instead.
+pub fn cpuid(leaf: u32, subleaf: u32) -> CpuidResult {
+    let eax: u32;
+    let ebx: u64;
+    let ecx: u32;
+    let edx: u32;
+
+    // NOTE: RBX is used internally by LLVM, so it can't be named as an
+    // operand, and has to be preserved by hand.
+    unsafe {
+        asm!(
+            "mov {ebx}, rbx",
+            "cpuid",
+            "xchg {ebx}, rbx",
+            ebx = out(reg) ebx,
+            inout("eax") leaf => eax,
+            inout("ecx") subleaf => ecx,
+            out("edx") edx,
+            options(nomem, nostack, preserves_flags),
+        );
+    }
+
+    CpuidResult {
+        eax,
+        ebx: ebx as u32,
+        ecx,
+        edx,
+    }
+}
+
+/// Gets the highest basic leaf supported.
+pub fn max_leaf() -> u32 {
+    cpuid(0, 0).eax
+}
+
+/// Gets the highest extended leaf supported.
+pub fn max_extended_leaf() -> u32 {
+    cpuid(0x8000_0000, 0).eax
+}
+
+/// Determines whether 1GiB pages can be mapped by page directory
+/// pointer table entries (CPUID.80000001H:EDX.Page1GB[bit 26]).
+pub fn has_1gib_pages() -> bool {
+    max_extended_leaf() >= 0x8000_0001 && cpuid(0x8000_0001, 0).edx & (1 << 26) != 0
+}
+
+/// Determines whether the processor has a local APIC
+/// (CPUID.01H:EDX.APIC[bit 9]).
+pub fn has_apic() -> bool {
+    cpuid(0x01, 0).edx & (1 << 9) != 0
+}
+
+/// Determines whether the local APIC supports x2APIC mode
+/// (CPUID.01H:ECX.x2APIC[bit 21]).
+pub fn has_x2apic() -> bool {
+    cpuid(0x01, 0).ecx & (1 << 21) != 0
+}
+
+/// Determines whether process-context identifiers can be enabled
+/// (CPUID.01H:ECX.PCID[bit 17]).
+pub fn has_pcid() -> bool {
+    cpuid(0x01, 0).ecx & (1 << 17) != 0
+}
+
+/// Determines whether the INVPCID instruction is supported
+/// (CPUID.(EAX=07H,ECX=0H):EBX.INVPCID[bit 10]).
+pub fn has_invpcid() -> bool {
+    max_leaf() >= 0x07 && cpuid(0x07, 0).ebx & (1 << 10) != 0
+}
+
+/// Determines whether 5-level paging is supported
+/// (CPUID.(EAX=07H,ECX=0H):ECX.LA57[bit 16]).
+pub fn has_la57() -> bool {
+    max_leaf() >= 0x07 && cpuid(0x07, 0).ecx & (1 << 16) != 0
+}
+
+/// Gets the number of bits in a physical address (MAXPHYADDR, from
+/// CPUID.80000008H:EAX[7:0]).
+///
+/// This is cached after the first call, since CPUID can be slow, in
+/// particular under virtualisation.
+pub fn max_physical_address_bits() -> u8 {
+    // NOTE: 36 bits is the architectural default for processors that
+    // don't report the width.
+    const DEFAULT_BITS: u8 = 36;
+
+    static CACHED_BITS: AtomicU8 = AtomicU8::new(0);
+
+    let cached = CACHED_BITS.load(Ordering::Relaxed);
+
+    if cached != 0 {
+        return cached;
+    }
+
+    let bits = if max_extended_leaf() >= 0x8000_0008 {
+        cpuid(0x8000_0008, 0).eax as u8
+    } else {
+        DEFAULT_BITS
+    };
+
+    CACHED_BITS.store(bits, Ordering::Relaxed);
+
+    bits
+}
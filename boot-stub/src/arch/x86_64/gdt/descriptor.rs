@@ -0,0 +1,161 @@
+//! Provides iteration over the logical descriptors in a GDT.
+//!
+//! In IA-32e mode, system descriptors (LDTs, TSSs and call gates) take
+//! up two entries, the second holding the upper 32 bits of the base, so
+//! looking at the table an entry at a time misreads them.
+
+use super::{GDTEntry, GDTEntryType, GDTRValue};
+
+/// Whether a segment's limit is in bytes or 4KiB pages.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Granularity {
+    Byte,
+    Page,
+}
+
+/// A logical descriptor in a GDT, which is either a single entry, or a
+/// pair of entries for a system descriptor.
+pub struct GDTDescriptor<'a> {
+    index: usize,
+    low: &'a GDTEntry,
+    high: Option<&'a GDTEntry>,
+}
+
+impl GDTDescriptor<'_> {
+    /// Gets the index of the (first) entry of the descriptor, which is
+    /// what a selector for it refers to.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Determines whether this is a 16-byte system descriptor.
+    pub fn is_system(&self) -> bool {
+        self.high.is_some()
+    }
+
+    pub fn entry_type(&self) -> GDTEntryType {
+        self.low.entry_type()
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.low.is_present()
+    }
+
+    pub fn dpl(&self) -> u8 {
+        self.low.dpl()
+    }
+
+    /// Gets the base address, which includes the upper 32 bits for
+    /// system descriptors.
+    pub fn base(&self) -> u64 {
+        let upper = self.high.map(|high| high.upper_base()).unwrap_or(0);
+
+        u64::from(upper) << 32 | u64::from(self.low.base())
+    }
+
+    /// Gets the raw 20-bit limit, in units of the granularity.
+    pub fn limit(&self) -> u32 {
+        self.low.limit()
+    }
+
+    pub fn granularity(&self) -> Granularity {
+        self.low.granularity()
+    }
+
+    /// Gets the offset of the last byte of the segment, taking the
+    /// granularity into account.
+    pub fn byte_limit(&self) -> u64 {
+        match self.granularity() {
+            Granularity::Byte => u64::from(self.limit()),
+            Granularity::Page => u64::from(self.limit()) << 12 | 0xFFF,
+        }
+    }
+
+    /// Determines whether the L bit is set, which makes a code segment
+    /// a 64-bit segment.
+    pub fn is_long_mode(&self) -> bool {
+        self.low.is_long_mode()
+    }
+
+    /// Determines whether the D/B bit is set.
+    pub fn is_default_size(&self) -> bool {
+        self.low.is_default_size()
+    }
+
+    /// Determines whether the AVL bit (which is free for software to
+    /// use) is set.
+    pub fn is_available(&self) -> bool {
+        self.low.is_available()
+    }
+}
+
+impl core::fmt::Debug for GDTDescriptor<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if !self.is_present() {
+            return f
+                .debug_struct("GDTDescriptor")
+                .field("present", &false)
+                .finish();
+        }
+
+        f.debug_struct("GDTDescriptor")
+            .field("entry_type", &self.entry_type())
+            .field("present", &true)
+            .field("dpl", &self.dpl())
+            .field("base", &format_args!("{:#X}", self.base()))
+            .field("limit", &format_args!("{:#X}", self.limit()))
+            .field("granularity", &self.granularity())
+            .field("long_mode", &self.is_long_mode())
+            .field("default_size", &self.is_default_size())
+            .field("available", &self.is_available())
+            .finish()
+    }
+}
+
+/// Iterates over the logical descriptors in a GDT.
+pub struct GDTDescriptors<'a> {
+    entries: &'a [GDTEntry],
+    index: usize,
+}
+
+impl<'a> GDTDescriptors<'a> {
+    /// Iterates over the descriptors in the given entries.
+    pub fn new(entries: &'a [GDTEntry]) -> Self {
+        Self { entries, index: 0 }
+    }
+}
+
+impl<'a> Iterator for GDTDescriptors<'a> {
+    type Item = GDTDescriptor<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let index = self.index;
+        let low = self.entries.get(index)?;
+
+        // NOTE: A system descriptor that's cut short by the end of the
+        // table is reported as a single entry, rather than dropped.
+        let high = if low.is_wide_system_descriptor() {
+            self.entries.get(index + 1)
+        } else {
+            None
+        };
+
+        self.index += if high.is_some() { 2 } else { 1 };
+
+        Some(GDTDescriptor { index, low, high })
+    }
+}
+
+impl GDTRValue {
+    /// Iterates over the logical descriptors in the table.
+    ///
+    /// # Safety
+    /// The table must be accessible at its linear address.
+    pub unsafe fn descriptors(&self) -> GDTDescriptors<'static> {
+        let count = (usize::from(self.limit()) + 1) / core::mem::size_of::<GDTEntry>();
+        let entries =
+            core::slice::from_raw_parts(self.address().to_raw() as *const GDTEntry, count);
+
+        GDTDescriptors::new(entries)
+    }
+}
@@ -1,5 +1,14 @@
 use super::paging::LinearAddress;
 
+mod descriptor;
+pub use descriptor::*;
+
+mod table;
+pub use table::*;
+
+mod tss;
+pub use tss::*;
+
 #[repr(packed)]
 #[derive(Debug, Copy, Clone)]
 pub struct GDTRValue {
@@ -8,6 +17,12 @@ pub struct GDTRValue {
 }
 
 impl GDTRValue {
+    /// Constructs a value describing a table at the given address,
+    /// whose last byte is `limit` bytes beyond its start.
+    pub fn new(address: LinearAddress, limit: u16) -> Self {
+        Self { limit, address }
+    }
+
     /// Reads the current value of the GDT register.
     pub fn read() -> Self {
         unsafe {
@@ -20,6 +35,15 @@ impl GDTRValue {
         }
     }
 
+    /// Loads the value into the GDT register.
+    ///
+    /// # Safety
+    /// The value must describe a valid table, which must stay where it
+    /// is until another table is loaded.
+    pub unsafe fn write(&self) {
+        asm!("lgdt [{0}]", in(reg) self as *const Self);
+    }
+
     /// Gets the linear address.
     pub fn address(&self) -> LinearAddress {
         self.address
@@ -57,7 +81,9 @@ pub enum GDTEntryType {
 
     CallGate,
 
-    /// The upper eight bytes of a 16-byte descriptor.
+    /// The upper eight bytes of a 16-byte descriptor, which is only
+    /// seen when looking at entries one at a time, rather than through
+    /// `GDTDescriptors`.
     Upper,
 
     /// An unknown system descriptor.
@@ -106,6 +132,11 @@ impl GDTEntry {
     const RW_MASK: u8 = 0b0000_0010;
     const ACCESSED_MASK: u8 = 0b0000_0001;
     const TYPE_MASK: u8 = 0b0000_1111;
+    const LIMIT_HIGH_MASK: u8 = 0b0000_1111;
+    const AVL_MASK: u8 = 0b0001_0000;
+    const L_MASK: u8 = 0b0010_0000;
+    const DB_MASK: u8 = 0b0100_0000;
+    const G_MASK: u8 = 0b1000_0000;
 
     pub fn is_present(&self) -> bool {
         self.type_and_attributes & Self::PRESENT_MASK != 0
@@ -154,6 +185,55 @@ impl GDTEntry {
             }
         }
     }
+
+    /// Gets the 32 bits of the base held in the entry.
+    pub fn base(&self) -> u32 {
+        u32::from(self.base_high) << 24 | u32::from(self.base_mid) << 16 | u32::from(self.base_low)
+    }
+
+    /// Gets the raw 20-bit limit, in units of the granularity.
+    pub fn limit(&self) -> u32 {
+        u32::from(self.limit_high_and_attributes & Self::LIMIT_HIGH_MASK) << 16
+            | u32::from(self.limit_low)
+    }
+
+    pub fn granularity(&self) -> Granularity {
+        if self.limit_high_and_attributes & Self::G_MASK != 0 {
+            Granularity::Page
+        } else {
+            Granularity::Byte
+        }
+    }
+
+    pub fn is_long_mode(&self) -> bool {
+        self.limit_high_and_attributes & Self::L_MASK != 0
+    }
+
+    pub fn is_default_size(&self) -> bool {
+        self.limit_high_and_attributes & Self::DB_MASK != 0
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.limit_high_and_attributes & Self::AVL_MASK != 0
+    }
+
+    /// Determines whether the entry is the first half of a 16-byte
+    /// system descriptor.
+    fn is_wide_system_descriptor(&self) -> bool {
+        match self.entry_type() {
+            GDTEntryType::LDT
+            | GDTEntryType::AvailableTSS
+            | GDTEntryType::BusyTSS
+            | GDTEntryType::CallGate => true,
+            _ => false,
+        }
+    }
+
+    /// Interprets the entry as the second half of a 16-byte system
+    /// descriptor, getting the upper 32 bits of the base.
+    fn upper_base(&self) -> u32 {
+        u32::from(self.limit_low) | u32::from(self.base_low) << 16
+    }
 }
 
 impl core::fmt::Debug for GDTEntry {
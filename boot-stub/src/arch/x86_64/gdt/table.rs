@@ -0,0 +1,210 @@
+//! Provides a global descriptor table that we own, built up one
+//! descriptor at a time.
+
+use bitflags::bitflags;
+
+use super::GDTRValue;
+use crate::arch::x86_64::paging::{LinearAddress, SegmentSelector};
+
+/// The number of eight byte slots in a table.
+pub const GDT_CAPACITY: usize = 16;
+
+bitflags! {
+    /// The bits of a code or data segment descriptor, see Intel 3A -
+    /// 3.4.5.
+    pub struct DescriptorFlags: u64 {
+        /// The first 16 bits of the limit.
+        const LIMIT_0_15 = 0xFFFF;
+
+        /// Set by the processor when the segment is accessed, set up
+        /// front so that the processor never has to write the table.
+        const ACCESSED = 1 << 40;
+
+        /// For data segments, the segment is writable. For code
+        /// segments, the segment is readable.
+        const WRITABLE = 1 << 41;
+
+        /// The segment is conforming (for code segments), or expands
+        /// down (for data segments).
+        const CONFORMING = 1 << 42;
+
+        /// The segment is a code segment.
+        const EXECUTABLE = 1 << 43;
+
+        /// The descriptor is for a code or data segment, rather than a
+        /// system segment.
+        const USER_SEGMENT = 1 << 44;
+
+        /// The segment is for ring 3.
+        const DPL_RING_3 = 3 << 45;
+
+        /// The segment is present.
+        const PRESENT = 1 << 47;
+
+        /// The last four bits of the limit.
+        const LIMIT_16_19 = 0xF << 48;
+
+        /// The code segment is a 64-bit segment.
+        const LONG_MODE = 1 << 53;
+
+        /// The segment is a 32-bit segment (must be clear for 64-bit
+        /// code segments).
+        const DEFAULT_SIZE = 1 << 54;
+
+        /// The limit is in 4KiB units.
+        const GRANULARITY = 1 << 55;
+
+        /// The bits every flat segment has.
+        const COMMON = Self::USER_SEGMENT.bits
+            | Self::PRESENT.bits
+            | Self::WRITABLE.bits
+            | Self::ACCESSED.bits
+            | Self::LIMIT_0_15.bits
+            | Self::LIMIT_16_19.bits
+            | Self::GRANULARITY.bits;
+
+        const KERNEL_CODE = Self::COMMON.bits | Self::EXECUTABLE.bits | Self::LONG_MODE.bits;
+        const KERNEL_DATA = Self::COMMON.bits | Self::DEFAULT_SIZE.bits;
+        const USER_CODE = Self::KERNEL_CODE.bits | Self::DPL_RING_3.bits;
+        const USER_DATA = Self::KERNEL_DATA.bits | Self::DPL_RING_3.bits;
+    }
+}
+
+/// A descriptor that can be added to a table.
+#[derive(Debug, Copy, Clone)]
+pub enum Descriptor {
+    /// A code or data segment, which takes one slot.
+    UserSegment(u64),
+
+    /// A system segment (such as a TSS), which takes two slots.
+    SystemSegment(u64, u64),
+}
+
+impl Descriptor {
+    /// A 64-bit code segment for ring 0.
+    pub fn kernel_code_segment() -> Self {
+        Descriptor::UserSegment(DescriptorFlags::KERNEL_CODE.bits())
+    }
+
+    /// A data segment for ring 0.
+    pub fn kernel_data_segment() -> Self {
+        Descriptor::UserSegment(DescriptorFlags::KERNEL_DATA.bits())
+    }
+
+    /// A 64-bit code segment for ring 3.
+    pub fn user_code_segment() -> Self {
+        Descriptor::UserSegment(DescriptorFlags::USER_CODE.bits())
+    }
+
+    /// A data segment for ring 3.
+    pub fn user_data_segment() -> Self {
+        Descriptor::UserSegment(DescriptorFlags::USER_DATA.bits())
+    }
+
+    /// Gets the privilege level of the descriptor.
+    fn dpl(&self) -> u16 {
+        let low = match self {
+            Descriptor::UserSegment(low) => low,
+            Descriptor::SystemSegment(low, _) => low,
+        };
+
+        ((low & DescriptorFlags::DPL_RING_3.bits()) >> 45) as u16
+    }
+}
+
+/// A global descriptor table, which always starts with the null
+/// descriptor.
+#[repr(C, align(16))]
+pub struct GlobalDescriptorTable {
+    entries: [u64; GDT_CAPACITY],
+    len: usize,
+}
+
+impl GlobalDescriptorTable {
+    /// Constructs a table containing just the null descriptor.
+    pub const fn new() -> Self {
+        Self {
+            entries: [0; GDT_CAPACITY],
+            len: 1,
+        }
+    }
+
+    /// Appends a descriptor, returning a selector for it with an RPL
+    /// matching the descriptor's DPL. Panics if the table is full.
+    pub fn append(&mut self, descriptor: Descriptor) -> SegmentSelector {
+        let index = self.len;
+
+        match descriptor {
+            Descriptor::UserSegment(value) => self.push(value),
+
+            Descriptor::SystemSegment(low, high) => {
+                self.push(low);
+                self.push(high);
+            }
+        }
+
+        SegmentSelector::new(index as u16, descriptor.dpl())
+    }
+
+    /// Gets the linear address of the first descriptor.
+    pub fn address(&self) -> u64 {
+        self.entries.as_ptr() as u64
+    }
+
+    /// Gets the number of bytes taken up by the descriptors so far.
+    pub fn size(&self) -> u64 {
+        (self.len * core::mem::size_of::<u64>()) as u64
+    }
+
+    /// Loads the table into the GDT register.
+    ///
+    /// This doesn't change the segment registers, which keep using the
+    /// descriptors they were loaded from until they're reloaded, see
+    /// `reload_segments`.
+    pub fn load(&'static self) {
+        unsafe { self.load_unchecked() }
+    }
+
+    /// Loads the table into the GDT register.
+    ///
+    /// # Safety
+    /// The table must stay where it is, and stay alive, until another
+    /// table is loaded.
+    pub unsafe fn load_unchecked(&self) {
+        let address = LinearAddress::from_raw_unchecked(self.entries.as_ptr() as u64);
+        let limit = (self.len * core::mem::size_of::<u64>() - 1) as u16;
+
+        GDTRValue::new(address, limit).write();
+    }
+
+    fn push(&mut self, value: u64) {
+        assert!(self.len < GDT_CAPACITY, "the GDT is full");
+
+        self.entries[self.len] = value;
+        self.len += 1;
+    }
+}
+
+/// Reloads CS with the given code segment using a far return, and DS,
+/// ES, SS, FS and GS with the given data segment.
+///
+/// # Safety
+/// The selectors must refer to suitable descriptors in the current
+/// table, otherwise the processor faults.
+pub unsafe fn reload_segments(code: SegmentSelector, data: SegmentSelector) {
+    asm!(
+        "push {code}",
+        "lea {scratch}, [rip + 3f]",
+        "push {scratch}",
+        "retfq",
+        "3:",
+        "mov ds, {data:x}",
+        "mov es, {data:x}",
+        "mov ss, {data:x}",
+        "mov fs, {data:x}",
+        "mov gs, {data:x}",
+        code = in(reg) u64::from(code.to_raw()),
+        data = in(reg) data.to_raw(),
+        scratch = out(reg) _,
+    );
+}
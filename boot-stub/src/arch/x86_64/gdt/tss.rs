@@ -0,0 +1,121 @@
+//! Provides the 64-bit task state segment, which holds the stacks the
+//! processor switches to when handling interrupts.
+//!
+//! | Bytes     | Length | Purpose                                  |
+//! | ----------| -------| -----------------------------------------|
+//! |   0 - 3   | 4      | Reserved                                 |
+//! |   4 - 27  | 24     | RSP0 to RSP2                             |
+//! |  28 - 35  | 8      | Reserved                                 |
+//! |  36 - 91  | 56     | IST1 to IST7                             |
+//! |  92 - 101 | 10     | Reserved                                 |
+//! | 102 - 103 | 2      | I/O map base address                     |
+//!
+//! For more details see Intel 3A - 7.7.
+
+use super::Descriptor;
+use crate::arch::x86_64::paging::{LinearAddress, SegmentSelector};
+
+// NOTE: The kernel needs to know which stacks are which, so the
+// indices are shared through the boot information.
+pub use osc_os_boot_info::{DOUBLE_FAULT_IST_INDEX, MACHINE_CHECK_IST_INDEX, NMI_IST_INDEX};
+
+#[repr(C, packed(4))]
+pub struct TaskStateSegment {
+    reserved_1: u32,
+    privilege_stack_table: [u64; 3],
+    reserved_2: u64,
+    interrupt_stack_table: [u64; 7],
+    reserved_3: u64,
+    reserved_4: u16,
+    io_map_base: u16,
+}
+
+impl TaskStateSegment {
+    /// Constructs a segment with no stacks, and no I/O permission map.
+    pub const fn new() -> Self {
+        Self {
+            reserved_1: 0,
+            privilege_stack_table: [0; 3],
+            reserved_2: 0,
+            interrupt_stack_table: [0; 7],
+            reserved_3: 0,
+            reserved_4: 0,
+            io_map_base: core::mem::size_of::<Self>() as u16,
+        }
+    }
+
+    /// Gets the stack pointer loaded when switching to the given
+    /// privilege level (0 to 2).
+    pub fn privilege_stack(&self, level: usize) -> u64 {
+        let table = self.privilege_stack_table;
+        table[level]
+    }
+
+    /// Sets the stack pointer loaded when switching to the given
+    /// privilege level (0 to 2) from a less privileged one.
+    pub fn set_privilege_stack(&mut self, level: usize, top: LinearAddress) {
+        let mut table = self.privilege_stack_table;
+        table[level] = top.to_raw();
+        self.privilege_stack_table = table;
+    }
+
+    /// Gets the stack pointer loaded for handlers with the given IST
+    /// index (1 to 7).
+    pub fn interrupt_stack(&self, index: u8) -> u64 {
+        let table = self.interrupt_stack_table;
+        table[ist_slot(index)]
+    }
+
+    /// Sets the stack pointer loaded for handlers with the given IST
+    /// index (1 to 7), whatever privilege level they interrupt.
+    pub fn set_interrupt_stack(&mut self, index: u8, top: LinearAddress) {
+        let mut table = self.interrupt_stack_table;
+        table[ist_slot(index)] = top.to_raw();
+        self.interrupt_stack_table = table;
+    }
+}
+
+/// Converts an IST index, which counts from one, into a slot in the
+/// table.
+fn ist_slot(index: u8) -> usize {
+    assert!(
+        (1..=7).contains(&index),
+        "IST index {} is out of range",
+        index
+    );
+
+    usize::from(index - 1)
+}
+
+impl Descriptor {
+    /// A descriptor for an available 64-bit TSS.
+    ///
+    /// # Safety
+    /// The segment must stay where it is, and stay alive, for as long
+    /// as the descriptor is in use.
+    pub unsafe fn tss_segment(tss: *const TaskStateSegment) -> Self {
+        const AVAILABLE_TSS: u64 = 0b1001 << 40;
+        const PRESENT: u64 = 1 << 47;
+
+        let base = tss as u64;
+        let limit = (core::mem::size_of::<TaskStateSegment>() - 1) as u64;
+
+        let low = limit & 0xFFFF
+            | (base & 0x00FF_FFFF) << 16
+            | AVAILABLE_TSS
+            | PRESENT
+            | (base >> 24 & 0xFF) << 56;
+
+        Descriptor::SystemSegment(low, base >> 32)
+    }
+}
+
+/// Loads the task register with the given TSS selector, which marks
+/// the TSS as busy.
+///
+/// # Safety
+/// The selector must refer to an available TSS descriptor in the
+/// current GDT, which must be writable.
+pub unsafe fn load_task_register(selector: SegmentSelector) {
+    asm!("ltr {0:x}", in(reg) selector.to_raw(), options(nostack, preserves_flags));
+}
@@ -0,0 +1,405 @@
+//! Provides default handlers for the 32 architectural exceptions,
+//! which report everything they can about the exception over COM1.
+//!
+//! The handlers are entered through the stubs in `exceptions.s`, which
+//! save every general purpose register before calling into Rust, since
+//! an `x86-interrupt` handler has no way to see the registers as they
+//! were when the exception happened.
+//!
+//! Breakpoints are resumed after being reported, everything else halts
+//! the processor.
+
+use core::fmt::{self, Write};
+
+use bitflags::bitflags;
+
+use super::{IDTEntryType, InterruptDescriptorTable, InterruptStackFrame, EXCEPTION_VECTOR_COUNT};
+use crate::ansi;
+use crate::arch::x86_64::paging::SegmentSelector;
+use crate::arch::x86_64::registers::CR2Value;
+use crate::arch::x86_64::serial::{SerialPort, SerialPortDescriptor};
+
+global_asm!(include_str!("exceptions.s"));
+
+extern "C" {
+    fn exception_stub_0();
+    fn exception_stub_1();
+    fn exception_stub_2();
+    fn exception_stub_3();
+    fn exception_stub_4();
+    fn exception_stub_5();
+    fn exception_stub_6();
+    fn exception_stub_7();
+    fn exception_stub_8();
+    fn exception_stub_9();
+    fn exception_stub_10();
+    fn exception_stub_11();
+    fn exception_stub_12();
+    fn exception_stub_13();
+    fn exception_stub_14();
+    fn exception_stub_15();
+    fn exception_stub_16();
+    fn exception_stub_17();
+    fn exception_stub_18();
+    fn exception_stub_19();
+    fn exception_stub_20();
+    fn exception_stub_21();
+    fn exception_stub_22();
+    fn exception_stub_23();
+    fn exception_stub_24();
+    fn exception_stub_25();
+    fn exception_stub_26();
+    fn exception_stub_27();
+    fn exception_stub_28();
+    fn exception_stub_29();
+    fn exception_stub_30();
+    fn exception_stub_31();
+}
+
+/// The entry stub for each exception, indexed by vector.
+const EXCEPTION_STUBS: [unsafe extern "C" fn(); EXCEPTION_VECTOR_COUNT] = [
+    exception_stub_0,
+    exception_stub_1,
+    exception_stub_2,
+    exception_stub_3,
+    exception_stub_4,
+    exception_stub_5,
+    exception_stub_6,
+    exception_stub_7,
+    exception_stub_8,
+    exception_stub_9,
+    exception_stub_10,
+    exception_stub_11,
+    exception_stub_12,
+    exception_stub_13,
+    exception_stub_14,
+    exception_stub_15,
+    exception_stub_16,
+    exception_stub_17,
+    exception_stub_18,
+    exception_stub_19,
+    exception_stub_20,
+    exception_stub_21,
+    exception_stub_22,
+    exception_stub_23,
+    exception_stub_24,
+    exception_stub_25,
+    exception_stub_26,
+    exception_stub_27,
+    exception_stub_28,
+    exception_stub_29,
+    exception_stub_30,
+    exception_stub_31,
+];
+
+/// The mnemonic and name of each exception, indexed by vector.
+const EXCEPTION_NAMES: [(&str, &str); EXCEPTION_VECTOR_COUNT] = [
+    ("#DE", "Divide Error"),
+    ("#DB", "Debug"),
+    ("NMI", "Non-Maskable Interrupt"),
+    ("#BP", "Breakpoint"),
+    ("#OF", "Overflow"),
+    ("#BR", "Bound Range Exceeded"),
+    ("#UD", "Invalid Opcode"),
+    ("#NM", "Device Not Available"),
+    ("#DF", "Double Fault"),
+    ("---", "Coprocessor Segment Overrun"),
+    ("#TS", "Invalid TSS"),
+    ("#NP", "Segment Not Present"),
+    ("#SS", "Stack-Segment Fault"),
+    ("#GP", "General Protection"),
+    ("#PF", "Page Fault"),
+    ("---", "Reserved"),
+    ("#MF", "x87 Floating-Point Error"),
+    ("#AC", "Alignment Check"),
+    ("#MC", "Machine Check"),
+    ("#XM", "SIMD Floating-Point"),
+    ("#VE", "Virtualization"),
+    ("#CP", "Control Protection"),
+    ("---", "Reserved"),
+    ("---", "Reserved"),
+    ("---", "Reserved"),
+    ("---", "Reserved"),
+    ("---", "Reserved"),
+    ("---", "Reserved"),
+    ("#HV", "Hypervisor Injection"),
+    ("#VC", "VMM Communication"),
+    ("#SX", "Security"),
+    ("---", "Reserved"),
+];
+
+const BREAKPOINT_VECTOR: u64 = 3;
+const PAGE_FAULT_VECTOR: u64 = 14;
+
+/// The general purpose registers, as saved by the entry stubs.
+#[repr(C)]
+pub struct GeneralRegisters {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+}
+
+/// Everything on the stack when an entry stub calls into Rust.
+#[repr(C)]
+pub struct ExceptionContext {
+    pub registers: GeneralRegisters,
+    pub vector: u64,
+
+    /// The error code, which is zero for exceptions that don't push
+    /// one.
+    pub error_code: u64,
+
+    pub frame: InterruptStackFrame,
+}
+
+bitflags! {
+    /// The error code pushed by a page fault, see Intel 3A - 4.7.
+    pub struct PageFaultErrorCode: u64 {
+        /// The fault was caused by a protection violation, rather than
+        /// a non-present page.
+        const PROTECTION_VIOLATION = 1 << 0;
+
+        /// The access was a write.
+        const WRITE = 1 << 1;
+
+        /// The access was made in user mode.
+        const USER = 1 << 2;
+
+        /// A reserved bit was set in a paging structure.
+        const RESERVED_BIT = 1 << 3;
+
+        /// The access was an instruction fetch.
+        const INSTRUCTION_FETCH = 1 << 4;
+
+        /// The access violated protection keys.
+        const PROTECTION_KEY = 1 << 5;
+
+        /// The access was a shadow stack access.
+        const SHADOW_STACK = 1 << 6;
+
+        /// The fault was an SGX access-control violation.
+        const SGX = 1 << 15;
+    }
+}
+
+/// The error code pushed by exceptions relating to a segment or gate,
+/// see Intel 3A - 6.13.
+#[derive(Copy, Clone)]
+pub struct SelectorErrorCode(u64);
+
+impl SelectorErrorCode {
+    /// Determines whether the exception happened while delivering an
+    /// event external to the program.
+    pub fn is_external(&self) -> bool {
+        self.0 & 0b001 != 0
+    }
+
+    /// Gets the name of the table the index refers to.
+    pub fn table(&self) -> &'static str {
+        if self.0 & 0b010 != 0 {
+            "IDT"
+        } else if self.0 & 0b100 != 0 {
+            "LDT"
+        } else {
+            "GDT"
+        }
+    }
+
+    /// Gets the index into the table.
+    pub fn index(&self) -> u16 {
+        ((self.0 >> 3) & 0x1FFF) as u16
+    }
+}
+
+impl fmt::Debug for SelectorErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SelectorErrorCode")
+            .field("external", &self.is_external())
+            .field("table", &self.table())
+            .field("index", &self.index())
+            .finish()
+    }
+}
+
+/// Points the first 32 entries of the table at the default handlers.
+pub fn install_default_handlers(table: &mut InterruptDescriptorTable) {
+    let code_segment = SegmentSelector::from_cs_register();
+
+    for (vector, stub) in EXCEPTION_STUBS.iter().enumerate() {
+        table
+            .entry_mut(vector as u8)
+            .set_handler_address(code_segment, *stub as usize as u64)
+            .set_entry_type(IDTEntryType::InterruptGate)
+            .set_present(true);
+    }
+}
+
+/// Reports an exception, called from the common entry stub.
+#[no_mangle]
+extern "sysv64" fn handle_exception(context: &mut ExceptionContext) {
+    let mut com1 = unsafe { SerialPort::new(SerialPortDescriptor::StandardCom1) };
+
+    // NOTE: There's nothing useful to do if writing to the serial port
+    // fails.
+    let _ = report(&mut com1, context);
+
+    if context.vector == BREAKPOINT_VECTOR {
+        return;
+    }
+
+    loop {
+        unsafe {
+            asm!("cli", "hlt");
+        }
+    }
+}
+
+fn report(output: &mut impl Write, context: &ExceptionContext) -> fmt::Result {
+    let (mnemonic, name) = EXCEPTION_NAMES
+        .get(context.vector as usize)
+        .copied()
+        .unwrap_or(("???", "Unknown"));
+
+    writeln!(
+        output,
+        "{}Exception {} {} ({}){}",
+        ansi::Color::from_fg_and_bg(ansi::StandardColor::White, ansi::StandardColor::Red),
+        context.vector,
+        mnemonic,
+        name,
+        ansi::Reset
+    )?;
+
+    let heading =
+        ansi::Color::from_fg_and_bg(ansi::StandardColor::Yellow, ansi::StandardColor::Black);
+    let error_code = context.error_code;
+
+    match context.vector {
+        8 | 17 => writeln!(
+            output,
+            "{}Error code:{} {:#X}",
+            heading,
+            ansi::Reset,
+            error_code
+        )?,
+
+        10 | 11 | 12 | 13 => writeln!(
+            output,
+            "{}Error code:{} {:#X} {:?}",
+            heading,
+            ansi::Reset,
+            error_code,
+            SelectorErrorCode(error_code)
+        )?,
+
+        PAGE_FAULT_VECTOR => {
+            let address = CR2Value::read().to_raw();
+
+            writeln!(
+                output,
+                "{}Error code:{} {:#X} {:?}",
+                heading,
+                ansi::Reset,
+                error_code,
+                PageFaultErrorCode::from_bits_truncate(error_code)
+            )?;
+
+            writeln!(output, "{}CR2:{} {:#018X}", heading, ansi::Reset, address)?;
+        }
+
+        21 | 29 | 30 => writeln!(
+            output,
+            "{}Error code:{} {:#X}",
+            heading,
+            ansi::Reset,
+            error_code
+        )?,
+
+        _ => (),
+    }
+
+    writeln!(
+        output,
+        "{}Frame:{} {:?}",
+        heading,
+        ansi::Reset,
+        context.frame
+    )?;
+    writeln!(output, "{}Registers:{}", heading, ansi::Reset)?;
+
+    let registers = &context.registers;
+
+    write_registers(
+        output,
+        &[
+            ("RAX", registers.rax),
+            ("RBX", registers.rbx),
+            ("RCX", registers.rcx),
+            ("RDX", registers.rdx),
+        ],
+    )?;
+    write_registers(
+        output,
+        &[
+            ("RSI", registers.rsi),
+            ("RDI", registers.rdi),
+            ("RBP", registers.rbp),
+            ("RSP", context.frame.stack_pointer),
+        ],
+    )?;
+    write_registers(
+        output,
+        &[
+            ("R8", registers.r8),
+            ("R9", registers.r9),
+            ("R10", registers.r10),
+            ("R11", registers.r11),
+        ],
+    )?;
+    write_registers(
+        output,
+        &[
+            ("R12", registers.r12),
+            ("R13", registers.r13),
+            ("R14", registers.r14),
+            ("R15", registers.r15),
+        ],
+    )?;
+    write_registers(
+        output,
+        &[
+            ("RIP", context.frame.instruction_pointer),
+            ("RFLAGS", context.frame.cpu_flags),
+        ],
+    )
+}
+
+fn write_registers(output: &mut impl Write, registers: &[(&str, u64)]) -> fmt::Result {
+    let name_color =
+        ansi::Color::from_fg_and_bg(ansi::StandardColor::Cyan, ansi::StandardColor::Black);
+
+    for (name, value) in registers {
+        write!(
+            output,
+            "{}{:>6}{} {:#018X} ",
+            name_color,
+            name,
+            ansi::Reset,
+            value
+        )?;
+    }
+
+    writeln!(output)
+}
@@ -0,0 +1,101 @@
+# Entry points for the 32 architectural exceptions.
+#
+# Each stub makes the stack look the same whatever the exception (by
+# pushing a zero error code if the processor didn't push one), pushes
+# its vector, and then joins the common path, which saves every general
+# purpose register so that the handler can report them.
+
+.intel_syntax noprefix
+.section .text
+
+.macro exception_stub vector, has_error_code
+.global exception_stub_\vector
+.align 16
+exception_stub_\vector:
+.if \has_error_code == 0
+    push 0
+.endif
+    push \vector
+    jmp exception_common
+.endm
+
+exception_stub 0, 0
+exception_stub 1, 0
+exception_stub 2, 0
+exception_stub 3, 0
+exception_stub 4, 0
+exception_stub 5, 0
+exception_stub 6, 0
+exception_stub 7, 0
+exception_stub 8, 1
+exception_stub 9, 0
+exception_stub 10, 1
+exception_stub 11, 1
+exception_stub 12, 1
+exception_stub 13, 1
+exception_stub 14, 1
+exception_stub 15, 0
+exception_stub 16, 0
+exception_stub 17, 1
+exception_stub 18, 0
+exception_stub 19, 0
+exception_stub 20, 0
+exception_stub 21, 1
+exception_stub 22, 0
+exception_stub 23, 0
+exception_stub 24, 0
+exception_stub 25, 0
+exception_stub 26, 0
+exception_stub 27, 0
+exception_stub 28, 0
+exception_stub 29, 1
+exception_stub 30, 1
+exception_stub 31, 0
+
+# The registers are pushed in the reverse of the order of the fields of
+# `GeneralRegisters`. The processor aligns the stack to 16 bytes before
+# pushing the frame, and 22 quadwords have been pushed by the time of
+# the call, so the stack is still aligned as the System V ABI requires.
+.align 16
+exception_common:
+    push rax
+    push rbx
+    push rcx
+    push rdx
+    push rsi
+    push rdi
+    push rbp
+    push r8
+    push r9
+    push r10
+    push r11
+    push r12
+    push r13
+    push r14
+    push r15
+
+    mov rdi, rsp
+    cld
+    call handle_exception
+
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop r11
+    pop r10
+    pop r9
+    pop r8
+    pop rbp
+    pop rdi
+    pop rsi
+    pop rdx
+    pop rcx
+    pop rbx
+    pop rax
+
+    # Drop the vector and error code.
+    add rsp, 16
+    iretq
+
+.att_syntax
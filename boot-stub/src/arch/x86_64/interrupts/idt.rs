@@ -0,0 +1,328 @@
+//! Provides an interrupt descriptor table that we own, and can fill
+//! with handlers of our own.
+//!
+//! Each of the first 32 vectors is reserved for a particular exception,
+//! and the table gives each of those its own field, typed according to
+//! how the processor calls its handler:
+//!
+//! * Most exceptions push nothing beyond the stack frame.
+//! * Some also push an error code.
+//! * #DF and #MC can't be returned from, so their handlers diverge.
+//!
+//! The remaining 224 vectors are for interrupts, and are reached by
+//! indexing the table.
+
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut, Index, IndexMut};
+
+use super::{IDTEntry, IDTEntryType, IDTRValue};
+use crate::arch::x86_64::paging::{LinearAddress, SegmentSelector};
+
+/// The stack frame the processor pushes before calling a handler.
+#[repr(C)]
+pub struct InterruptStackFrame {
+    pub instruction_pointer: u64,
+    pub code_segment: u64,
+    pub cpu_flags: u64,
+    pub stack_pointer: u64,
+    pub stack_segment: u64,
+}
+
+impl core::fmt::Debug for InterruptStackFrame {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("InterruptStackFrame")
+            .field(
+                "instruction_pointer",
+                &format_args!("{:#018X}", self.instruction_pointer),
+            )
+            .field(
+                "code_segment",
+                &SegmentSelector::from_raw(self.code_segment as u16),
+            )
+            .field("cpu_flags", &format_args!("{:#X}", self.cpu_flags))
+            .field(
+                "stack_pointer",
+                &format_args!("{:#018X}", self.stack_pointer),
+            )
+            .field(
+                "stack_segment",
+                &SegmentSelector::from_raw(self.stack_segment as u16),
+            )
+            .finish()
+    }
+}
+
+/// A handler for an interrupt, or an exception that doesn't push an
+/// error code.
+pub type HandlerFunc = extern "x86-interrupt" fn(&mut InterruptStackFrame);
+
+/// A handler for an exception that pushes an error code.
+pub type HandlerFuncWithErrorCode = extern "x86-interrupt" fn(&mut InterruptStackFrame, u64);
+
+/// A handler for an exception that can't be returned from, and doesn't
+/// push an error code (#MC).
+pub type DivergingHandlerFunc = extern "x86-interrupt" fn(&mut InterruptStackFrame) -> !;
+
+/// A handler for an exception that can't be returned from, and pushes
+/// an error code (#DF).
+pub type DivergingHandlerFuncWithErrorCode =
+    extern "x86-interrupt" fn(&mut InterruptStackFrame, u64) -> !;
+
+/// Implemented by each of the handler types, so that a gate can find
+/// out where its handler lives.
+pub trait Handler: Copy {
+    /// Gets the linear address of the handler's code.
+    fn address(self) -> u64;
+}
+
+impl Handler for HandlerFunc {
+    fn address(self) -> u64 {
+        self as usize as u64
+    }
+}
+
+impl Handler for HandlerFuncWithErrorCode {
+    fn address(self) -> u64 {
+        self as usize as u64
+    }
+}
+
+impl Handler for DivergingHandlerFunc {
+    fn address(self) -> u64 {
+        self as usize as u64
+    }
+}
+
+impl Handler for DivergingHandlerFuncWithErrorCode {
+    fn address(self) -> u64 {
+        self as usize as u64
+    }
+}
+
+/// An entry in the table, which can only be given handlers of the
+/// type `F`.
+///
+/// The gate dereferences to its `IDTEntry`, so its attributes can be
+/// read and set directly.
+#[repr(transparent)]
+pub struct IDTGate<F> {
+    entry: IDTEntry,
+    handler: PhantomData<F>,
+}
+
+impl<F: Handler> IDTGate<F> {
+    /// Points the gate at the given handler in the current code
+    /// segment, making it a present interrupt gate, and returns the
+    /// entry so that its other attributes can be set.
+    pub fn set_handler(&mut self, handler: F) -> &mut IDTEntry {
+        self.entry
+            .set_handler_address(SegmentSelector::from_cs_register(), handler.address())
+            .set_entry_type(IDTEntryType::InterruptGate)
+            .set_present(true)
+    }
+}
+
+impl<F> Deref for IDTGate<F> {
+    type Target = IDTEntry;
+
+    fn deref(&self) -> &IDTEntry {
+        &self.entry
+    }
+}
+
+impl<F> DerefMut for IDTGate<F> {
+    fn deref_mut(&mut self) -> &mut IDTEntry {
+        &mut self.entry
+    }
+}
+
+/// The number of vectors reserved for exceptions.
+pub const EXCEPTION_VECTOR_COUNT: usize = 32;
+
+/// An interrupt descriptor table, with all 256 entries.
+///
+/// For more details about the exceptions see Intel 3A - 6.15.
+#[repr(C, align(16))]
+pub struct InterruptDescriptorTable {
+    /// Vector 0, #DE.
+    pub divide_error: IDTGate<HandlerFunc>,
+
+    /// Vector 1, #DB.
+    pub debug: IDTGate<HandlerFunc>,
+
+    /// Vector 2, the non-maskable interrupt.
+    pub non_maskable_interrupt: IDTGate<HandlerFunc>,
+
+    /// Vector 3, #BP.
+    pub breakpoint: IDTGate<HandlerFunc>,
+
+    /// Vector 4, #OF.
+    pub overflow: IDTGate<HandlerFunc>,
+
+    /// Vector 5, #BR.
+    pub bound_range_exceeded: IDTGate<HandlerFunc>,
+
+    /// Vector 6, #UD.
+    pub invalid_opcode: IDTGate<HandlerFunc>,
+
+    /// Vector 7, #NM.
+    pub device_not_available: IDTGate<HandlerFunc>,
+
+    /// Vector 8, #DF, whose error code is always zero.
+    pub double_fault: IDTGate<DivergingHandlerFuncWithErrorCode>,
+
+    /// Vector 9, which is no longer used.
+    coprocessor_segment_overrun: IDTGate<HandlerFunc>,
+
+    /// Vector 10, #TS.
+    pub invalid_tss: IDTGate<HandlerFuncWithErrorCode>,
+
+    /// Vector 11, #NP.
+    pub segment_not_present: IDTGate<HandlerFuncWithErrorCode>,
+
+    /// Vector 12, #SS.
+    pub stack_segment_fault: IDTGate<HandlerFuncWithErrorCode>,
+
+    /// Vector 13, #GP.
+    pub general_protection_fault: IDTGate<HandlerFuncWithErrorCode>,
+
+    /// Vector 14, #PF, with the faulting address in CR2.
+    pub page_fault: IDTGate<HandlerFuncWithErrorCode>,
+
+    /// Vector 15, which is reserved.
+    reserved_15: IDTGate<HandlerFunc>,
+
+    /// Vector 16, #MF.
+    pub x87_floating_point: IDTGate<HandlerFunc>,
+
+    /// Vector 17, #AC.
+    pub alignment_check: IDTGate<HandlerFuncWithErrorCode>,
+
+    /// Vector 18, #MC.
+    pub machine_check: IDTGate<DivergingHandlerFunc>,
+
+    /// Vector 19, #XM.
+    pub simd_floating_point: IDTGate<HandlerFunc>,
+
+    /// Vector 20, #VE.
+    pub virtualization: IDTGate<HandlerFunc>,
+
+    /// Vector 21, #CP.
+    pub control_protection: IDTGate<HandlerFuncWithErrorCode>,
+
+    /// Vectors 22 to 27, which are reserved.
+    reserved_22_27: [IDTGate<HandlerFunc>; 6],
+
+    /// Vector 28, #HV.
+    pub hypervisor_injection: IDTGate<HandlerFunc>,
+
+    /// Vector 29, #VC.
+    pub vmm_communication: IDTGate<HandlerFuncWithErrorCode>,
+
+    /// Vector 30, #SX.
+    pub security_exception: IDTGate<HandlerFuncWithErrorCode>,
+
+    /// Vector 31, which is reserved.
+    reserved_31: IDTGate<HandlerFunc>,
+
+    /// Vectors 32 to 255, for interrupts.
+    interrupts: [IDTGate<HandlerFunc>; 256 - EXCEPTION_VECTOR_COUNT],
+}
+
+impl InterruptDescriptorTable {
+    /// Constructs a table in which every entry is missing.
+    pub fn new() -> Self {
+        // NOTE: Arrays of gates can't be built from a repeated gate, as
+        // gates aren't `Copy`, but a missing gate is the same whatever
+        // its handler type, so every entry can be written the same way.
+        let mut table = core::mem::MaybeUninit::<Self>::uninit();
+        let entries = table.as_mut_ptr() as *mut IDTEntry;
+
+        unsafe {
+            for index in 0..256 {
+                entries.add(index).write(IDTEntry::missing());
+            }
+
+            table.assume_init()
+        }
+    }
+
+    /// Gets the entry for the given vector, whatever its handler type,
+    /// so that its attributes can be read or changed.
+    pub fn entry_mut(&mut self, vector: u8) -> &mut IDTEntry {
+        let entries = self as *mut Self as *mut IDTEntry;
+
+        unsafe { &mut *entries.add(usize::from(vector)) }
+    }
+
+    /// Moves the handlers of every present entry to the given code
+    /// segment, which has to be done whenever a new GDT replaces the
+    /// one their selectors refer to.
+    pub fn set_code_segment(&mut self, selector: SegmentSelector) {
+        for vector in 0..=255 {
+            let entry = self.entry_mut(vector);
+
+            if entry.is_present() {
+                entry.set_selector(selector);
+            }
+        }
+    }
+
+    /// Copies the interrupt entries (vectors 32 to 255) from the table
+    /// described by the given IDT register value, so that handlers the
+    /// firmware installed for its own interrupts keep working.
+    ///
+    /// # Safety
+    /// The value must describe a valid table, which must be accessible
+    /// at its linear address.
+    pub unsafe fn copy_interrupts_from(&mut self, idtr: IDTRValue) {
+        let count = (usize::from(idtr.limit()) + 1) / core::mem::size_of::<IDTEntry>();
+        let source = idtr.address().to_raw() as *const IDTEntry;
+
+        for vector in EXCEPTION_VECTOR_COUNT..count.min(256) {
+            core::ptr::copy_nonoverlapping(source.add(vector), self.entry_mut(vector as u8), 1);
+        }
+    }
+
+    /// Loads the table into the IDT register.
+    ///
+    /// The table has to live forever, since the processor keeps using
+    /// it until another table is loaded.
+    pub fn load(&'static self) {
+        unsafe { self.load_unchecked() }
+    }
+
+    /// Loads the table into the IDT register.
+    ///
+    /// # Safety
+    /// The table must stay where it is, and stay alive, until another
+    /// table is loaded.
+    pub unsafe fn load_unchecked(&self) {
+        let address = LinearAddress::from_raw_unchecked(self as *const Self as u64);
+        let limit = (core::mem::size_of::<Self>() - 1) as u16;
+
+        IDTRValue::new(address, limit).write();
+    }
+}
+
+impl Index<u8> for InterruptDescriptorTable {
+    type Output = IDTGate<HandlerFunc>;
+
+    /// Gets the entry for an interrupt vector, panicking if the vector
+    /// is reserved for an exception.
+    fn index(&self, vector: u8) -> &Self::Output {
+        match usize::from(vector).checked_sub(EXCEPTION_VECTOR_COUNT) {
+            Some(index) => &self.interrupts[index],
+            None => panic!("vector {} is reserved for an exception", vector),
+        }
+    }
+}
+
+impl IndexMut<u8> for InterruptDescriptorTable {
+    fn index_mut(&mut self, vector: u8) -> &mut Self::Output {
+        match usize::from(vector).checked_sub(EXCEPTION_VECTOR_COUNT) {
+            Some(index) => &mut self.interrupts[index],
+            None => panic!("vector {} is reserved for an exception", vector),
+        }
+    }
+}
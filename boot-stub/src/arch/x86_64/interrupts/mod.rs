@@ -0,0 +1,234 @@
+use super::paging::LinearAddress;
+use super::paging::LogicalAddress;
+use super::paging::SegmentSelector;
+
+mod exceptions;
+pub use exceptions::*;
+
+mod idt;
+pub use idt::*;
+
+#[repr(packed)]
+#[derive(Debug, Copy, Clone)]
+pub struct IDTRValue {
+    limit: u16,
+    address: LinearAddress,
+}
+
+impl IDTRValue {
+    /// Constructs a value describing a table at the given address,
+    /// whose last byte is `limit` bytes beyond its start.
+    pub fn new(address: LinearAddress, limit: u16) -> Self {
+        Self { limit, address }
+    }
+
+    /// Reads the current value of the IDT register.
+    pub fn read() -> Self {
+        unsafe {
+            let mut result: Self = core::mem::MaybeUninit::uninit().assume_init();
+            let result_ptr = &mut result as *mut Self;
+
+            asm!("sidt [{0}]", in(reg) result_ptr);
+
+            result
+        }
+    }
+
+    /// Loads the value into the IDT register.
+    ///
+    /// # Safety
+    /// The value must describe a valid table, which must stay where it
+    /// is until another table is loaded.
+    pub unsafe fn write(&self) {
+        asm!("lidt [{0}]", in(reg) self as *const Self);
+    }
+
+    /// Gets the linear address.
+    pub fn address(&self) -> LinearAddress {
+        self.address
+    }
+
+    /// Gets the limit.
+    pub fn limit(&self) -> u16 {
+        self.limit
+    }
+}
+
+/// The type of the IDT entry - either an interrupt gate, or a
+/// trap gate.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IDTEntryType {
+    InterruptGate,
+    TrapGate,
+    Invalid(u8),
+}
+
+/// Provides access to the data in an entry in an interrupt descriptor
+/// table.
+///
+/// | Bytes    | Length | Purpose                                  |
+/// | ---------| -------| -----------------------------------------|
+/// |  0 - 1   | 2      | Offset low bits (0..15)                  |
+/// |  2 - 3   | 2      | Segment selector                         |
+/// |  4       | 1      | Zero and reserved                        |
+/// |  5       | 1      | Type and attributes                      |
+/// |  6 - 7   | 2      | Offset middle bits (16..31)              |
+/// |  8 - 11  | 4      | Offset high bits (32..63)                |
+/// | 12 - 16  | 4      | Reserved                                 |
+///
+/// For more details about the structure of the IDT see Intel 3A - 6.14.1.
+///
+/// Note that in x86-64, only interrupt gates and trap gates are
+/// supported (task gates are deprecated).
+#[repr(packed)]
+pub struct IDTEntry {
+    // These fields are the same as ia32
+    offset_lower: u16,
+    selector: u16,
+    zero_and_reserved: u8,
+    type_and_attributes: u8,
+    offset_middle: u16,
+
+    // x86-64 doubles the entry size to 16-bytes
+    offset_high: u32,
+    extended_reserved: u32,
+}
+
+impl IDTEntry {
+    const PRESENT_MASK: u8 = 0b1000_0000;
+    const DPL_MASK: u8 = 0b0110_0000;
+    const DPL_SHIFT: usize = 5;
+    const S_MASK: u8 = 0b0001_0000;
+    const TYPE_MASK: u8 = 0b0000_1111;
+    const IST_MASK: u8 = 0b0000_0111;
+
+    const INTERRUPT_GATE: u8 = 0b1110;
+    const TRAP_GATE: u8 = 0b1111;
+
+    /// Constructs an entry that isn't present, which is an interrupt
+    /// gate with no handler.
+    pub const fn missing() -> Self {
+        Self {
+            offset_lower: 0,
+            selector: 0,
+            zero_and_reserved: 0,
+            type_and_attributes: Self::INTERRUPT_GATE,
+            offset_middle: 0,
+            offset_high: 0,
+            extended_reserved: 0,
+        }
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.type_and_attributes & Self::PRESENT_MASK != 0
+    }
+
+    pub fn dpl(&self) -> u8 {
+        (self.type_and_attributes & Self::DPL_MASK) >> Self::DPL_SHIFT
+    }
+
+    pub fn entry_type(&self) -> IDTEntryType {
+        let gate_type = self.type_and_attributes & Self::TYPE_MASK;
+
+        // Table 3-2 in Intel 3A
+        match gate_type {
+            Self::INTERRUPT_GATE => IDTEntryType::InterruptGate,
+            Self::TRAP_GATE => IDTEntryType::TrapGate,
+            other => IDTEntryType::Invalid(other),
+        }
+    }
+
+    /// Gets the index of the interrupt stack table entry the handler
+    /// runs on, if it has one.
+    pub fn ist_index(&self) -> Option<u8> {
+        match self.zero_and_reserved & Self::IST_MASK {
+            0 => None,
+            index => Some(index),
+        }
+    }
+
+    pub fn logical_address(&self) -> LogicalAddress {
+        let selector = SegmentSelector::from_raw(self.selector);
+
+        let offset = u64::from(self.offset_high) << 32
+            | u64::from(self.offset_middle) << 16
+            | u64::from(self.offset_lower);
+
+        LogicalAddress::from_selector_and_offset(selector, offset)
+    }
+
+    /// Points the entry at the handler at the given offset in the given
+    /// code segment.
+    pub fn set_handler_address(&mut self, selector: SegmentSelector, offset: u64) -> &mut Self {
+        self.selector = selector.to_raw();
+        self.offset_lower = offset as u16;
+        self.offset_middle = (offset >> 16) as u16;
+        self.offset_high = (offset >> 32) as u32;
+        self
+    }
+
+    /// Moves the entry's handler to the given code segment, keeping its
+    /// offset.
+    pub fn set_selector(&mut self, selector: SegmentSelector) -> &mut Self {
+        self.selector = selector.to_raw();
+        self
+    }
+
+    /// Sets whether the entry is present.
+    pub fn set_present(&mut self, present: bool) -> &mut Self {
+        if present {
+            self.type_and_attributes |= Self::PRESENT_MASK;
+        } else {
+            self.type_and_attributes &= !Self::PRESENT_MASK;
+        }
+
+        self
+    }
+
+    /// Sets the privilege level needed to invoke the entry with an
+    /// `int` instruction, which must be at most 3.
+    pub fn set_dpl(&mut self, dpl: u8) -> &mut Self {
+        assert!(dpl <= 3, "DPL {} is out of range", dpl);
+
+        self.type_and_attributes =
+            (self.type_and_attributes & !Self::DPL_MASK) | (dpl << Self::DPL_SHIFT);
+        self
+    }
+
+    /// Sets the type of the entry. Interrupt gates disable interrupts
+    /// while the handler runs, whereas trap gates leave them alone.
+    pub fn set_entry_type(&mut self, entry_type: IDTEntryType) -> &mut Self {
+        let gate_type = match entry_type {
+            IDTEntryType::InterruptGate => Self::INTERRUPT_GATE,
+            IDTEntryType::TrapGate => Self::TRAP_GATE,
+            IDTEntryType::Invalid(other) => other & Self::TYPE_MASK,
+        };
+
+        self.type_and_attributes = (self.type_and_attributes & !Self::TYPE_MASK) | gate_type;
+        self
+    }
+
+    /// Sets the index (1 to 7) of the interrupt stack table entry the
+    /// handler runs on, or `None` to keep using the current stack
+    /// (unless the privilege level changes).
+    pub fn set_ist_index(&mut self, index: Option<u8>) -> &mut Self {
+        let index = index.unwrap_or(0);
+
+        assert!(index <= 7, "IST index {} is out of range", index);
+
+        self.zero_and_reserved = (self.zero_and_reserved & !Self::IST_MASK) | index;
+        self
+    }
+}
+
+impl core::fmt::Debug for IDTEntry {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("IDTEntry")
+            .field("entry_type", &self.entry_type())
+            .field("present", &self.is_present())
+            .field("dpl", &self.dpl())
+            .field("ist_index", &self.ist_index())
+            .field("logical_address", &self.logical_address())
+            .finish()
+    }
+}
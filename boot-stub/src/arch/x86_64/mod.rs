@@ -1,8 +1,14 @@
 //! Provides access to 64-bit x86 specific
 //! functionality.
+pub mod apic;
+pub mod cpuid;
 pub mod gdt;
 pub mod interrupts;
+pub mod msr;
 pub mod paging;
+pub mod pic;
 pub mod port;
 pub mod registers;
 pub mod serial;
+pub mod tlb;
+pub mod trampoline;
@@ -0,0 +1,56 @@
+//! Provides access to model specific registers.
+
+/// The extended feature enable register.
+pub const IA32_EFER: ModelSpecificRegister = ModelSpecificRegister::from_raw(0xC000_0080);
+
+/// The local APIC's base address and enable bits.
+pub const IA32_APIC_BASE: ModelSpecificRegister = ModelSpecificRegister::from_raw(0x1B);
+
+/// Identifies a model specific register.
+#[derive(Debug, Copy, Clone)]
+pub struct ModelSpecificRegister(u32);
+
+impl ModelSpecificRegister {
+    /// Constructs a model specific register from its raw 32-bit index.
+    pub const fn from_raw(raw: u32) -> Self {
+        Self(raw)
+    }
+
+    /// Gets the raw 32-bit index.
+    pub fn as_raw(&self) -> u32 {
+        self.0
+    }
+
+    /// Reads the value of the register.
+    ///
+    /// # Safety
+    /// This is unsafe because reading a register that doesn't exist
+    /// causes a general protection fault.
+    pub unsafe fn read(&self) -> u64 {
+        let low: u32;
+        let high: u32;
+
+        asm!(
+            "rdmsr",
+            in("ecx") self.0,
+            out("eax") low,
+            out("edx") high,
+        );
+
+        u64::from(high) << 32 | u64::from(low)
+    }
+
+    /// Writes the value of the register.
+    ///
+    /// # Safety
+    /// This is unsafe because writing to a model specific register can
+    /// change the behaviour of the processor in arbitrary ways.
+    pub unsafe fn write(&self, value: u64) {
+        asm!(
+            "wrmsr",
+            in("ecx") self.0,
+            in("eax") value as u32,
+            in("edx") (value >> 32) as u32,
+        );
+    }
+}
@@ -0,0 +1,245 @@
+//! Provides a human readable dump of the mappings in a hierarchy of
+//! page tables.
+//!
+//! Contiguous mappings with the same flags and page size are merged
+//! into a single range, so that a dump looks like:
+//!
+//! ```text
+//! 0x0000000000000000-0x00000000001FFFFF -> 0x0 RW X 4K
+//! 0xFFFF800000000000-0xFFFF80003FFFFFFF -> 0x0 RW NX 2M physical memory
+//! ```
+//!
+//! When the regions of the address space are known, each range is
+//! followed by the name of the region it's in, and ranges are never
+//! merged across regions.
+
+use core::fmt;
+
+use super::mapper::PhysicalToLinear;
+use super::page::PageSize;
+use super::region::RegionManager;
+use super::translate::{combine_flags, table_at, ROOT_FLAGS};
+use super::{LinearAddress, PageTable, PageTableEntryFlags, PagingMode, PhysicalAddress};
+
+/// The flags that are compared when deciding whether two mappings can
+/// be merged. The accessed and dirty flags are left out since they
+/// differ from page to page for reasons that aren't interesting.
+const COMPARED_FLAGS: PageTableEntryFlags = PageTableEntryFlags::from_bits_truncate(
+    PageTableEntryFlags::WRITABLE.bits()
+        | PageTableEntryFlags::USER_ACCESSIBLE.bits()
+        | PageTableEntryFlags::WRITE_THROUGH.bits()
+        | PageTableEntryFlags::DISABLE_CACHE.bits()
+        | PageTableEntryFlags::GLOBAL.bits()
+        | PageTableEntryFlags::NO_EXECUTE.bits(),
+);
+
+/// Writes every mapping under the given root to the output, one range
+/// of linear addresses per line, naming the region each range is in
+/// if the regions are given.
+///
+/// # Safety
+/// Every table under the root must be reachable through the given
+/// physical-to-linear conversion, and the hierarchy must have as many
+/// levels as the paging mode says.
+pub unsafe fn dump_page_tables(
+    root: PhysicalAddress,
+    mode: PagingMode,
+    physical_to_linear: &impl PhysicalToLinear,
+    regions: Option<&RegionManager>,
+    output: &mut impl fmt::Write,
+) -> fmt::Result {
+    let mut ranges = RangeWriter {
+        output,
+        current: None,
+    };
+
+    let walker = Walker {
+        mode,
+        physical_to_linear,
+        regions,
+    };
+
+    let root = table_at(root, physical_to_linear);
+    walker.dump_table(root, mode.levels(), 0, ROOT_FLAGS, &mut ranges)?;
+
+    ranges.finish()
+}
+
+/// A contiguous range of linear addresses mapped to a contiguous range
+/// of physical addresses.
+#[derive(Copy, Clone)]
+struct MappedRange {
+    linear_start: u64,
+    linear_end: u64,
+    physical_start: u64,
+    flags: PageTableEntryFlags,
+    size: PageSize,
+    region: Option<&'static str>,
+}
+
+impl MappedRange {
+    fn can_extend_with(&self, next: &MappedRange) -> bool {
+        self.linear_end == next.linear_start
+            && self.physical_start + self.linear_end.wrapping_sub(self.linear_start)
+                == next.physical_start
+            && self.flags == next.flags
+            && self.size == next.size
+            && self.region == next.region
+    }
+}
+
+impl fmt::Display for MappedRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:#018X}-{:#018X} -> {:#X} ",
+            self.linear_start,
+            self.linear_end.wrapping_sub(1),
+            self.physical_start
+        )?;
+
+        if self.flags.contains(PageTableEntryFlags::WRITABLE) {
+            f.write_str("RW")?;
+        } else {
+            f.write_str("RO")?;
+        }
+
+        if self.flags.contains(PageTableEntryFlags::NO_EXECUTE) {
+            f.write_str(" NX")?;
+        } else {
+            f.write_str(" X")?;
+        }
+
+        if self.flags.contains(PageTableEntryFlags::USER_ACCESSIBLE) {
+            f.write_str(" U")?;
+        }
+
+        if self.flags.contains(PageTableEntryFlags::GLOBAL) {
+            f.write_str(" G")?;
+        }
+
+        if self.flags.contains(PageTableEntryFlags::WRITE_THROUGH) {
+            f.write_str(" WT")?;
+        }
+
+        if self.flags.contains(PageTableEntryFlags::DISABLE_CACHE) {
+            f.write_str(" UC")?;
+        }
+
+        match self.size {
+            PageSize::Size4KiB => f.write_str(" 4K")?,
+            PageSize::Size2MiB => f.write_str(" 2M")?,
+            PageSize::Size1GiB => f.write_str(" 1G")?,
+        }
+
+        match self.region {
+            Some(region) => write!(f, " {}", region),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Merges mappings into ranges, writing each range out once the next
+/// mapping can't be merged into it.
+struct RangeWriter<'w, W: fmt::Write> {
+    output: &'w mut W,
+    current: Option<MappedRange>,
+}
+
+impl<W: fmt::Write> RangeWriter<'_, W> {
+    fn push(&mut self, next: MappedRange) -> fmt::Result {
+        match &mut self.current {
+            Some(current) if current.can_extend_with(&next) => {
+                current.linear_end = next.linear_end;
+                Ok(())
+            }
+
+            _ => {
+                self.finish()?;
+                self.current = Some(next);
+                Ok(())
+            }
+        }
+    }
+
+    fn finish(&mut self) -> fmt::Result {
+        match self.current.take() {
+            Some(range) => writeln!(self.output, "{}", range),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Walks the hierarchy, passing each mapping to a `RangeWriter`.
+struct Walker<'p, P: PhysicalToLinear> {
+    mode: PagingMode,
+    physical_to_linear: &'p P,
+    regions: Option<&'p RegionManager>,
+}
+
+impl<P: PhysicalToLinear> Walker<'_, P> {
+    fn region_name(&self, linear_address: u64) -> Option<&'static str> {
+        let address = LinearAddress::new(linear_address).ok()?;
+
+        self.regions?
+            .region_containing(address)
+            .map(|region| region.name())
+    }
+
+    /// Walks a table at the given level, whose first entry maps the
+    /// given linear address.
+    unsafe fn dump_table<W: fmt::Write>(
+        &self,
+        table: &PageTable,
+        level: usize,
+        linear_base: u64,
+        path_flags: PageTableEntryFlags,
+        ranges: &mut RangeWriter<'_, W>,
+    ) -> fmt::Result {
+        let entry_span = 1u64 << (12 + 9 * (level - 1));
+
+        for index in 0..PageTable::ENTRY_COUNT {
+            let entry = &table[index];
+            let flags = entry.flags();
+
+            if !flags.contains(PageTableEntryFlags::PRESENT) {
+                continue;
+            }
+
+            let linear_start = self
+                .mode
+                .canonicalize(linear_base + index as u64 * entry_span);
+
+            let entry_flags = combine_flags(path_flags, flags);
+            let is_huge = flags.contains(PageTableEntryFlags::HUGE_PAGE);
+
+            let size = match level {
+                1 => Some(PageSize::Size4KiB),
+                2 if is_huge => Some(PageSize::Size2MiB),
+                3 if is_huge => Some(PageSize::Size1GiB),
+                _ => None,
+            };
+
+            match size {
+                Some(size) => ranges.push(MappedRange {
+                    linear_start,
+                    linear_end: linear_start.wrapping_add(entry_span),
+                    physical_start: entry.physical_address().to_raw() & !(size.bytes() - 1),
+                    flags: entry_flags & COMPARED_FLAGS,
+                    size,
+                    region: self.region_name(linear_start),
+                })?,
+
+                None => self.dump_table(
+                    table_at(entry.physical_address(), self.physical_to_linear),
+                    level - 1,
+                    linear_start,
+                    entry_flags,
+                    ranges,
+                )?,
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,480 @@
+//! Provides creation, removal and modification of mappings in a
+//! hierarchy of page tables.
+//!
+//! Mappings can be made with any of the page sizes, and huge mappings
+//! can be split into mappings of the next size down.
+//!
+//! The mapper doesn't invalidate any TLB entries itself, instead each
+//! change returns a `MapperFlush`, which must either be flushed (when
+//! the tables being modified are active) or explicitly ignored.
+
+use super::super::{cpuid, tlb};
+use super::page::{Page, PageSize, PageSizeMarker, PhysFrame, Size4KiB};
+use super::translate::walk;
+use super::{
+    LinearAddress, PageTable, PageTableEntry, PageTableEntryFlags, PagingMode, PhysicalAddress,
+};
+
+/// Provides frames of physical memory for use as page tables.
+pub trait FrameAllocator {
+    /// Allocates a frame, returning `None` if there are none left.
+    fn allocate_frame(&mut self) -> Option<PhysFrame>;
+
+    /// Returns a frame that is no longer in use.
+    ///
+    /// # Safety
+    /// The frame must have come from this allocator, and must not be
+    /// in use any longer.
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame);
+}
+
+/// Converts the physical address of a page table into the linear
+/// address it can be accessed at.
+///
+/// This depends on how physical memory is mapped into the current
+/// address space, e.g. UEFI identity maps physical memory, whereas a
+/// kernel might map it at an offset.
+pub trait PhysicalToLinear {
+    /// Gets the linear address at which the given physical address
+    /// can be accessed.
+    fn to_linear(&self, physical_address: PhysicalAddress) -> LinearAddress;
+}
+
+/// Accesses physical memory that is identity mapped.
+#[derive(Debug, Copy, Clone)]
+pub struct IdentityMapping;
+
+impl PhysicalToLinear for IdentityMapping {
+    fn to_linear(&self, physical_address: PhysicalAddress) -> LinearAddress {
+        unsafe { LinearAddress::from_raw_unchecked(physical_address.to_raw()) }
+    }
+}
+
+/// Accesses physical memory that is mapped in its entirety at a fixed
+/// offset in the linear address space.
+#[derive(Debug, Copy, Clone)]
+pub struct OffsetMapping {
+    offset: u64,
+}
+
+impl OffsetMapping {
+    /// Constructs a mapping where physical address zero is at the
+    /// given linear address.
+    pub fn new(offset: u64) -> Self {
+        Self { offset }
+    }
+}
+
+impl PhysicalToLinear for OffsetMapping {
+    fn to_linear(&self, physical_address: PhysicalAddress) -> LinearAddress {
+        unsafe { LinearAddress::from_raw_unchecked(physical_address.to_raw() + self.offset) }
+    }
+}
+
+/// The ways in which a mapping operation can fail.
+#[derive(Debug)]
+pub enum MapperError {
+    /// The page is already mapped, to the frame containing the given
+    /// address.
+    AlreadyMapped(PhysFrame),
+
+    /// The page isn't mapped.
+    NotMapped,
+
+    /// One of the tables on the way to the page is actually a huge
+    /// page, which covers the page.
+    ParentIsHugePage,
+
+    /// The page is mapped, but by a page of a different size.
+    PageSizeMismatch,
+
+    /// The frame allocator ran out of frames for new page tables.
+    OutOfFrames,
+
+    /// The processor doesn't support pages of the size asked for.
+    PageSizeUnsupported,
+}
+
+/// A change to the mapping of a page, which won't necessarily take
+/// effect until the page has been flushed from the TLB.
+#[must_use = "the page must be flushed from the TLB, or the flush explicitly ignored"]
+pub struct MapperFlush<S: PageSizeMarker>(Page<S>);
+
+impl<S: PageSizeMarker> MapperFlush<S> {
+    fn new(page: Page<S>) -> Self {
+        Self(page)
+    }
+
+    /// Gets the page whose mapping was changed.
+    pub fn page(&self) -> Page<S> {
+        self.0
+    }
+
+    /// Flushes the page from the TLB, which is needed when the change
+    /// was made to the active page tables.
+    pub fn flush(self) {
+        tlb::flush(self.0.start_address());
+    }
+
+    /// Ignores the need for a flush, which is fine when the change was
+    /// made to page tables that aren't active.
+    pub fn ignore(self) {}
+}
+
+/// Provides modification of the mappings in a hierarchy of page
+/// tables.
+pub struct Mapper<'a, P: PhysicalToLinear> {
+    root: &'a mut PageTable,
+    mode: PagingMode,
+    physical_to_linear: P,
+}
+
+impl<'a, P: PhysicalToLinear> Mapper<'a, P> {
+    /// Constructs a mapper for the page tables under the given root,
+    /// which has as many levels below it as the paging mode says.
+    ///
+    /// # Safety
+    /// Every table under the root must be reachable through the given
+    /// physical-to-linear conversion, and nothing else may modify the
+    /// tables while the mapper exists.
+    pub unsafe fn new(root: &'a mut PageTable, mode: PagingMode, physical_to_linear: P) -> Self {
+        Self {
+            root,
+            mode,
+            physical_to_linear,
+        }
+    }
+
+    /// Translates a linear address using the tables being modified,
+    /// see `translate` for details.
+    pub fn translate(
+        &self,
+        address: LinearAddress,
+    ) -> Option<(PhysicalAddress, PageSize, PageTableEntryFlags)> {
+        // NOTE: This is safe because the requirements are the same as
+        // those for constructing the mapper.
+        unsafe { walk(self.root, self.mode, address, &self.physical_to_linear) }
+    }
+
+    /// Maps the page to the frame with the given flags, creating any
+    /// intermediate tables that are needed. `PRESENT` is always added
+    /// to the flags, as is `HUGE_PAGE` for huge pages.
+    ///
+    /// 1GiB pages can only be mapped when the processor supports them,
+    /// see `cpuid::has_1gib_pages`.
+    pub fn map<S: PageSizeMarker>(
+        &mut self,
+        page: Page<S>,
+        frame: PhysFrame<S>,
+        flags: PageTableEntryFlags,
+        allocator: &mut impl FrameAllocator,
+    ) -> Result<MapperFlush<S>, MapperError> {
+        // NOTE: Otherwise the page size bit at the PDPT level is
+        // reserved, and any access through the mapping would fault.
+        if S::KIND == PageSize::Size1GiB && !cpuid::has_1gib_pages() {
+            return Err(MapperError::PageSizeUnsupported);
+        }
+
+        let flags = leaf_flags::<S>(flags);
+        let address = page.start_address();
+        let p2l = &self.physical_to_linear;
+
+        let mut table = &mut *self.root;
+
+        for level in (leaf_level::<S>() + 1..=self.mode.levels()).rev() {
+            table = create_next_table(&mut table[index(address, level)], flags, p2l, allocator)?;
+        }
+
+        let entry = &mut table[index(address, leaf_level::<S>())];
+
+        if !entry.is_unused() {
+            if !is_leaf::<S>(entry) {
+                return Err(MapperError::PageSizeMismatch);
+            }
+
+            return Err(MapperError::AlreadyMapped(PhysFrame::containing_address(
+                entry.physical_address(),
+            )));
+        }
+
+        entry.set(frame.start_address(), flags);
+
+        Ok(MapperFlush::new(page))
+    }
+
+    /// Removes the mapping of the page, returning the frame it was
+    /// mapped to. Any page tables left empty are returned to the
+    /// allocator.
+    pub fn unmap<S: PageSizeMarker>(
+        &mut self,
+        page: Page<S>,
+        allocator: &mut impl FrameAllocator,
+    ) -> Result<(PhysFrame<S>, MapperFlush<S>), MapperError> {
+        let address = page.start_address();
+
+        // NOTE: The root table is never freed, even when it's empty.
+        let physical_address = unmap_below::<S, _, _>(
+            &mut *self.root,
+            self.mode.levels(),
+            address,
+            &self.physical_to_linear,
+            allocator,
+        )?;
+
+        Ok((
+            PhysFrame::containing_address(physical_address),
+            MapperFlush::new(page),
+        ))
+    }
+
+    /// Replaces the flags of the mapping of the page, leaving the frame
+    /// it's mapped to alone. `PRESENT` is always added to the flags, as
+    /// is `HUGE_PAGE` for huge pages.
+    pub fn update_flags<S: PageSizeMarker>(
+        &mut self,
+        page: Page<S>,
+        flags: PageTableEntryFlags,
+    ) -> Result<MapperFlush<S>, MapperError> {
+        let entry = self.leaf_entry(page)?;
+        entry.set_flags(leaf_flags::<S>(flags));
+
+        Ok(MapperFlush::new(page))
+    }
+
+    /// Splits the mapping of a huge page into mappings of the next size
+    /// down, covering the same frames with the same flags.
+    pub fn split<S: PageSizeMarker>(
+        &mut self,
+        page: Page<S>,
+        allocator: &mut impl FrameAllocator,
+    ) -> Result<MapperFlush<S>, MapperError> {
+        if S::KIND == PageSize::Size4KiB {
+            return Err(MapperError::PageSizeMismatch);
+        }
+
+        let p2l = &self.physical_to_linear;
+        let entry = leaf_entry_below(&mut *self.root, self.mode.levels(), page, p2l)?;
+
+        let flags = entry.flags();
+        let base = entry.physical_address().to_raw() & !(S::SIZE - 1);
+        let child_size = S::SIZE / PageTable::ENTRY_COUNT as u64;
+
+        // NOTE: The PAT bit lives at bit 12 in a huge page's entry,
+        // which is within the address field, and moves to bit 7 (where
+        // HUGE_PAGE would otherwise be) in a page table entry.
+        let pat = entry.physical_address().to_raw() & PAT_HUGE_BIT;
+
+        let (child_flags, child_pat) = if S::KIND == PageSize::Size2MiB {
+            let child_flags = flags - PageTableEntryFlags::HUGE_PAGE;
+
+            if pat != 0 {
+                (child_flags | PageTableEntryFlags::HUGE_PAGE, 0)
+            } else {
+                (child_flags, 0)
+            }
+        } else {
+            (flags, pat)
+        };
+
+        let frame = allocator.allocate_frame().ok_or(MapperError::OutOfFrames)?;
+        let table = unsafe { table_at(frame.start_address(), p2l) };
+
+        for child in 0..PageTable::ENTRY_COUNT {
+            let address = (base + child as u64 * child_size) | child_pat;
+            table[child].set(
+                unsafe { PhysicalAddress::from_raw_unchecked(address) },
+                child_flags,
+            );
+        }
+
+        entry.set(frame.start_address(), table_flags(flags));
+
+        Ok(MapperFlush::new(page))
+    }
+
+    /// Gets the entry that maps the page.
+    fn leaf_entry<S: PageSizeMarker>(
+        &mut self,
+        page: Page<S>,
+    ) -> Result<&mut PageTableEntry, MapperError> {
+        leaf_entry_below(
+            &mut *self.root,
+            self.mode.levels(),
+            page,
+            &self.physical_to_linear,
+        )
+    }
+}
+
+/// The bit of a huge page's entry that selects the PAT entry.
+const PAT_HUGE_BIT: u64 = 1 << 12;
+
+/// Gets the level of the table whose entries map pages of the given
+/// size, where page tables are level 1.
+fn leaf_level<S: PageSizeMarker>() -> usize {
+    match S::KIND {
+        PageSize::Size4KiB => 1,
+        PageSize::Size2MiB => 2,
+        PageSize::Size1GiB => 3,
+    }
+}
+
+/// Adds the flags that every entry mapping a page of the given size
+/// needs.
+fn leaf_flags<S: PageSizeMarker>(flags: PageTableEntryFlags) -> PageTableEntryFlags {
+    if S::KIND == PageSize::Size4KiB {
+        flags | PageTableEntryFlags::PRESENT
+    } else {
+        flags | PageTableEntryFlags::PRESENT | PageTableEntryFlags::HUGE_PAGE
+    }
+}
+
+/// Determines whether an entry in use maps a page of the given size,
+/// rather than referring to a table.
+fn is_leaf<S: PageSizeMarker>(entry: &PageTableEntry) -> bool {
+    S::KIND == PageSize::Size4KiB || entry.flags().contains(PageTableEntryFlags::HUGE_PAGE)
+}
+
+/// Gets the flags for an entry referring to a table which contains
+/// mappings with the given flags.
+///
+/// Permissions are restricted at the leaves, so intermediate entries
+/// allow writing and execution, and only need to become user
+/// accessible when a leaf below them is.
+fn table_flags(leaf_flags: PageTableEntryFlags) -> PageTableEntryFlags {
+    PageTableEntryFlags::PRESENT
+        | PageTableEntryFlags::WRITABLE
+        | (leaf_flags & PageTableEntryFlags::USER_ACCESSIBLE)
+}
+
+fn index(address: LinearAddress, level: usize) -> usize {
+    usize::from(address.level(level))
+}
+
+unsafe fn table_at<'b>(
+    physical_address: PhysicalAddress,
+    physical_to_linear: &impl PhysicalToLinear,
+) -> &'b mut PageTable {
+    let linear = physical_to_linear.to_linear(physical_address);
+    &mut *(linear.to_raw() as *mut PageTable)
+}
+
+/// Gets the table referred to by an entry in the level above it.
+fn next_table<'b>(
+    entry: &'b mut PageTableEntry,
+    physical_to_linear: &impl PhysicalToLinear,
+) -> Result<&'b mut PageTable, MapperError> {
+    let flags = entry.flags();
+
+    if !flags.contains(PageTableEntryFlags::PRESENT) {
+        return Err(MapperError::NotMapped);
+    }
+
+    if flags.contains(PageTableEntryFlags::HUGE_PAGE) {
+        return Err(MapperError::ParentIsHugePage);
+    }
+
+    Ok(unsafe { table_at(entry.physical_address(), physical_to_linear) })
+}
+
+/// Gets the table referred to by an entry in the level above it,
+/// creating the table if it doesn't exist.
+fn create_next_table<'b>(
+    entry: &'b mut PageTableEntry,
+    leaf_flags: PageTableEntryFlags,
+    physical_to_linear: &impl PhysicalToLinear,
+    allocator: &mut impl FrameAllocator,
+) -> Result<&'b mut PageTable, MapperError> {
+    let user_flag = leaf_flags & PageTableEntryFlags::USER_ACCESSIBLE;
+
+    if entry.is_unused() {
+        let frame = allocator.allocate_frame().ok_or(MapperError::OutOfFrames)?;
+
+        let table = unsafe { table_at(frame.start_address(), physical_to_linear) };
+        table.zero();
+
+        entry.set(frame.start_address(), table_flags(leaf_flags));
+    } else if entry.flags().contains(PageTableEntryFlags::HUGE_PAGE) {
+        // NOTE: This has to be checked before the user flag is touched,
+        // otherwise a failed mapping would leave the whole huge page
+        // accessible from user mode.
+        return Err(MapperError::ParentIsHugePage);
+    } else if !entry.flags().contains(user_flag) {
+        entry.set_flags(entry.flags() | user_flag);
+    }
+
+    next_table(entry, physical_to_linear)
+}
+
+/// Gets the entry that maps the page, starting from a table at the
+/// given level.
+fn leaf_entry_below<'b, S: PageSizeMarker>(
+    table: &'b mut PageTable,
+    level: usize,
+    page: Page<S>,
+    physical_to_linear: &impl PhysicalToLinear,
+) -> Result<&'b mut PageTableEntry, MapperError> {
+    let address = page.start_address();
+    let entry = &mut table[index(address, level)];
+
+    if level > leaf_level::<S>() {
+        let next = next_table(entry, physical_to_linear)?;
+        return leaf_entry_below(next, level - 1, page, physical_to_linear);
+    }
+
+    if entry.is_unused() {
+        return Err(MapperError::NotMapped);
+    }
+
+    if !is_leaf::<S>(entry) {
+        return Err(MapperError::PageSizeMismatch);
+    }
+
+    Ok(entry)
+}
+
+/// Removes the mapping of the page at the given address, starting
+/// from a table at the given level, and frees any tables below that
+/// level which are left empty.
+fn unmap_below<S: PageSizeMarker, P: PhysicalToLinear, A: FrameAllocator>(
+    table: &mut PageTable,
+    level: usize,
+    address: LinearAddress,
+    physical_to_linear: &P,
+    allocator: &mut A,
+) -> Result<PhysicalAddress, MapperError> {
+    let entry = &mut table[index(address, level)];
+
+    if level == leaf_level::<S>() {
+        if entry.is_unused() {
+            return Err(MapperError::NotMapped);
+        }
+
+        if !is_leaf::<S>(entry) {
+            return Err(MapperError::PageSizeMismatch);
+        }
+
+        let physical_address = entry.physical_address();
+        entry.set_unused();
+
+        return Ok(physical_address);
+    }
+
+    let next = next_table(entry, physical_to_linear)?;
+    let physical_address =
+        unmap_below::<S, _, _>(next, level - 1, address, physical_to_linear, allocator)?;
+
+    if next.is_empty() {
+        unsafe { free_table(entry, allocator) };
+    }
+
+    Ok(physical_address)
+}
+
+/// Frees the table referred to by the entry, and clears the entry.
+unsafe fn free_table(entry: &mut PageTableEntry, allocator: &mut impl FrameAllocator) {
+    allocator.deallocate_frame(PhysFrame::<Size4KiB>::containing_address(
+        entry.physical_address(),
+    ));
+    entry.set_unused();
+}
@@ -0,0 +1,645 @@
+//! Provides facilities for working with page tables.
+
+use super::cpuid;
+use super::registers::{CR4Flags, CR4Value};
+use bitflags::bitflags;
+use core::ops::{Add, AddAssign, Index, IndexMut, Sub, SubAssign};
+use core::sync::atomic::{AtomicU8, Ordering};
+
+mod dump;
+pub use dump::*;
+
+mod mapper;
+pub use mapper::*;
+
+mod page;
+pub use page::*;
+
+mod range;
+pub use range::*;
+
+mod region;
+pub use region::*;
+
+mod translate;
+pub use translate::*;
+
+#[derive(Copy, Clone)]
+pub struct LogicalAddress {
+    selector: SegmentSelector,
+    offset: u64,
+}
+
+impl LogicalAddress {
+    pub fn from_selector_and_offset(selector: SegmentSelector, offset: u64) -> Self {
+        Self { selector, offset }
+    }
+}
+
+impl core::fmt::Debug for LogicalAddress {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("LogicalAddress")
+            .field(&self.selector)
+            .field(&format_args!("{:#018X}", self.offset))
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+pub enum TableIndicator {
+    Global,
+    Local,
+}
+
+#[derive(Copy, Clone)]
+pub struct SegmentSelector(u16);
+
+impl SegmentSelector {
+    /// Constructs a selector for the given index into the GDT, with the
+    /// given requested privilege level.
+    pub fn new(index: u16, rpl: u16) -> Self {
+        Self(index << 3 | (rpl & 0b011))
+    }
+
+    pub fn from_cs_register() -> Self {
+        let result;
+
+        unsafe {
+            asm!("mov {0:x}, cs", out(reg) result);
+        }
+
+        Self::from_raw(result)
+    }
+
+    pub fn from_raw(segment_selector: u16) -> Self {
+        Self(segment_selector)
+    }
+
+    pub fn to_raw(&self) -> u16 {
+        self.0
+    }
+
+    pub fn index(&self) -> u16 {
+        self.0 >> 3
+    }
+
+    pub fn rpl(&self) -> u16 {
+        self.0 & 0b011
+    }
+
+    pub fn indicator(&self) -> TableIndicator {
+        let global = (self.0 & 0b100) == 0;
+
+        if global {
+            TableIndicator::Global
+        } else {
+            TableIndicator::Local
+        }
+    }
+}
+
+impl core::fmt::Debug for SegmentSelector {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("SegmentSelector")
+            .field(&self.indicator())
+            .field(&self.rpl())
+            .field(&self.index())
+            .finish()
+    }
+}
+
+/// The reasons a raw address might be rejected.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AddressError {
+    /// The linear address isn't canonical in the current paging mode.
+    NonCanonical(u64),
+
+    /// The physical address is beyond the physical address width the
+    /// processor supports (MAXPHYADDR).
+    BeyondMaxPhysicalAddress(u64),
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LinearAddress(u64);
+
+impl LinearAddress {
+    /// Constructs a linear address, checking that it's canonical in the
+    /// current paging mode.
+    pub fn new(raw_linear_address: u64) -> Result<Self, AddressError> {
+        if PagingMode::current().is_canonical(raw_linear_address) {
+            Ok(Self(raw_linear_address))
+        } else {
+            Err(AddressError::NonCanonical(raw_linear_address))
+        }
+    }
+
+    /// Constructs a linear address, making it canonical in the current
+    /// paging mode by sign-extending its most significant bit.
+    pub fn new_truncate(raw_linear_address: u64) -> Self {
+        Self(PagingMode::current().canonicalize(raw_linear_address))
+    }
+
+    pub unsafe fn from_raw_unchecked(raw_linear_address: u64) -> Self {
+        Self(raw_linear_address)
+    }
+
+    /// Rounds the address down to the given alignment, which must be a
+    /// power of two.
+    pub fn align_down(self, align: u64) -> Self {
+        Self::from_arithmetic(Some(align_down(self.0, align)))
+    }
+
+    /// Rounds the address up to the given alignment, which must be a
+    /// power of two.
+    pub fn align_up(self, align: u64) -> Self {
+        Self::from_arithmetic(align_up(self.0, align))
+    }
+
+    /// Determines whether the address has the given alignment, which
+    /// must be a power of two.
+    pub fn is_aligned(self, align: u64) -> bool {
+        align_down(self.0, align) == self.0
+    }
+
+    /// Checks the result of some arithmetic, panicking if it overflowed
+    /// or left the address non-canonical.
+    fn from_arithmetic(raw_linear_address: Option<u64>) -> Self {
+        match raw_linear_address.map(Self::new) {
+            Some(Ok(address)) => address,
+            Some(Err(err)) => panic!("linear address arithmetic failed: {:?}", err),
+            None => panic!("linear address arithmetic overflowed"),
+        }
+    }
+
+    pub fn offset(&self) -> u16 {
+        (self.0 & 0b1111_1111_1111) as u16
+    }
+
+    pub fn level1(&self) -> u16 {
+        ((self.0 >> 12) & 0b1_1111_1111) as u16
+    }
+
+    pub fn level2(&self) -> u16 {
+        ((self.0 >> 21) & 0b1_1111_1111) as u16
+    }
+
+    pub fn level3(&self) -> u16 {
+        ((self.0 >> 30) & 0b1_1111_1111) as u16
+    }
+
+    pub fn level4(&self) -> u16 {
+        ((self.0 >> 39) & 0b1_1111_1111) as u16
+    }
+
+    pub fn level5(&self) -> u16 {
+        ((self.0 >> 48) & 0b1_1111_1111) as u16
+    }
+
+    /// Gets the index into the table at the given level, where page
+    /// tables are level 1.
+    pub fn level(&self, level: usize) -> u16 {
+        match level {
+            1 => self.level1(),
+            2 => self.level2(),
+            3 => self.level3(),
+            4 => self.level4(),
+            5 => self.level5(),
+            _ => panic!("there is no paging level {}", level),
+        }
+    }
+
+    pub fn to_raw(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Add<u64> for LinearAddress {
+    type Output = Self;
+
+    fn add(self, offset: u64) -> Self {
+        Self::from_arithmetic(self.0.checked_add(offset))
+    }
+}
+
+impl AddAssign<u64> for LinearAddress {
+    fn add_assign(&mut self, offset: u64) {
+        *self = *self + offset;
+    }
+}
+
+impl Sub<u64> for LinearAddress {
+    type Output = Self;
+
+    fn sub(self, offset: u64) -> Self {
+        Self::from_arithmetic(self.0.checked_sub(offset))
+    }
+}
+
+impl SubAssign<u64> for LinearAddress {
+    fn sub_assign(&mut self, offset: u64) {
+        *self = *self - offset;
+    }
+}
+
+impl Sub<LinearAddress> for LinearAddress {
+    type Output = u64;
+
+    fn sub(self, other: LinearAddress) -> u64 {
+        self.0
+            .checked_sub(other.0)
+            .expect("linear address subtraction overflowed")
+    }
+}
+
+impl core::fmt::Debug for LinearAddress {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("LinearAddress")
+            .field(&format_args!("{}", self.level5()))
+            .field(&format_args!("{}", self.level4()))
+            .field(&format_args!("{}", self.level3()))
+            .field(&format_args!("{}", self.level2()))
+            .field(&format_args!("{}", self.level1()))
+            .field(&format_args!("{:#05X}", self.offset()))
+            .finish()
+    }
+}
+
+/// The paging modes supported in long mode, which determine the
+/// number of levels in the hierarchy of page tables, and therefore the
+/// number of bits in a linear address.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PagingMode {
+    /// 4-level paging, with 48-bit linear addresses and a PML4 at the
+    /// root.
+    FourLevel,
+
+    /// 5-level paging, with 57-bit linear addresses and a PML5 at the
+    /// root. This is enabled by CR4.LA57.
+    FiveLevel,
+}
+
+impl PagingMode {
+    /// Gets the paging mode currently in use.
+    ///
+    /// This is cached after the first call, since it's needed whenever a
+    /// linear address is constructed, and CR4.LA57 can't change while
+    /// paging is enabled.
+    pub fn current() -> Self {
+        // NOTE: Zero means the mode hasn't been read yet, otherwise this
+        // holds the number of levels.
+        static CACHED_LEVELS: AtomicU8 = AtomicU8::new(0);
+
+        match CACHED_LEVELS.load(Ordering::Relaxed) {
+            4 => return Self::FourLevel,
+            5 => return Self::FiveLevel,
+            _ => {}
+        }
+
+        let mode = if CR4Value::read().flags().contains(CR4Flags::LA57) {
+            Self::FiveLevel
+        } else {
+            Self::FourLevel
+        };
+
+        CACHED_LEVELS.store(mode.levels() as u8, Ordering::Relaxed);
+
+        mode
+    }
+
+    /// Gets the deepest paging mode the processor supports, which may
+    /// not be the one in use.
+    pub fn max_supported() -> Self {
+        if cpuid::has_la57() {
+            Self::FiveLevel
+        } else {
+            Self::FourLevel
+        }
+    }
+
+    /// Gets the number of levels of page tables, which is also the
+    /// level of the root table.
+    pub fn levels(&self) -> usize {
+        match self {
+            Self::FourLevel => 4,
+            Self::FiveLevel => 5,
+        }
+    }
+
+    /// Gets the number of significant bits in a linear address.
+    pub fn linear_address_bits(&self) -> u32 {
+        match self {
+            Self::FourLevel => 48,
+            Self::FiveLevel => 57,
+        }
+    }
+
+    /// Sign-extends the most significant bit of a linear address into
+    /// the bits above it, making it canonical.
+    pub fn canonicalize(&self, raw_linear_address: u64) -> u64 {
+        let unused_bits = 64 - self.linear_address_bits();
+        (((raw_linear_address << unused_bits) as i64) >> unused_bits) as u64
+    }
+
+    /// Determines whether a linear address is canonical, i.e. whether
+    /// the bits above the most significant bit are copies of it.
+    pub fn is_canonical(&self, raw_linear_address: u64) -> bool {
+        self.canonicalize(raw_linear_address) == raw_linear_address
+    }
+}
+
+/// Provides a type for physical addresses.
+///
+/// On the x86-64 architecture, physical addresses are fundamentally
+/// limited to a maximum of 52 bits.
+///
+/// Reading/writing a physical address isn't generically possible
+/// because all memory access goes through paging. Therefore, the
+/// physical address needs to be converted to a linear address
+/// before it can be read/written, and how that happens depends on
+/// the paging structure in place.
+///
+/// In an identity mapped system, it's simple, the physical addresses
+/// and linear addresses are in 1:1 correspondence.
+///
+/// Another possibility is to map the entire physical address space into
+/// part of the linear address space, that way there can be a simple 1:1
+/// mapping from physical to linear.
+///
+/// Other possibilities such as recursive page table mapping also exist.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PhysicalAddress(u64);
+
+impl PhysicalAddress {
+    /// Constructs a new physical address, checking that it's within the
+    /// physical address width the processor supports.
+    pub fn new(raw_physical_address: u64) -> Result<Self, AddressError> {
+        if raw_physical_address >> cpuid::max_physical_address_bits() == 0 {
+            Ok(Self(raw_physical_address))
+        } else {
+            Err(AddressError::BeyondMaxPhysicalAddress(raw_physical_address))
+        }
+    }
+
+    /// Constructs a new physical address from the provided raw physical
+    /// address.
+    ///
+    /// # Safety
+    /// This function performs no checks to see if the physical address is
+    /// properly formed.
+    pub unsafe fn from_raw_unchecked(raw_physical_address: u64) -> Self {
+        PhysicalAddress(raw_physical_address)
+    }
+
+    /// Gets the underlying raw physical address.
+    pub fn to_raw(&self) -> u64 {
+        self.0
+    }
+
+    /// Rounds the address down to the given alignment, which must be a
+    /// power of two.
+    pub fn align_down(self, align: u64) -> Self {
+        Self::from_arithmetic(Some(align_down(self.0, align)))
+    }
+
+    /// Rounds the address up to the given alignment, which must be a
+    /// power of two.
+    pub fn align_up(self, align: u64) -> Self {
+        Self::from_arithmetic(align_up(self.0, align))
+    }
+
+    /// Determines whether the address has the given alignment, which
+    /// must be a power of two.
+    pub fn is_aligned(self, align: u64) -> bool {
+        align_down(self.0, align) == self.0
+    }
+
+    /// Checks the result of some arithmetic, panicking if it overflowed
+    /// or went beyond the physical address width.
+    fn from_arithmetic(raw_physical_address: Option<u64>) -> Self {
+        match raw_physical_address.map(Self::new) {
+            Some(Ok(address)) => address,
+            Some(Err(err)) => panic!("physical address arithmetic failed: {:?}", err),
+            None => panic!("physical address arithmetic overflowed"),
+        }
+    }
+}
+
+impl Add<u64> for PhysicalAddress {
+    type Output = Self;
+
+    fn add(self, offset: u64) -> Self {
+        Self::from_arithmetic(self.0.checked_add(offset))
+    }
+}
+
+impl AddAssign<u64> for PhysicalAddress {
+    fn add_assign(&mut self, offset: u64) {
+        *self = *self + offset;
+    }
+}
+
+impl Sub<u64> for PhysicalAddress {
+    type Output = Self;
+
+    fn sub(self, offset: u64) -> Self {
+        Self::from_arithmetic(self.0.checked_sub(offset))
+    }
+}
+
+impl SubAssign<u64> for PhysicalAddress {
+    fn sub_assign(&mut self, offset: u64) {
+        *self = *self - offset;
+    }
+}
+
+impl Sub<PhysicalAddress> for PhysicalAddress {
+    type Output = u64;
+
+    fn sub(self, other: PhysicalAddress) -> u64 {
+        self.0
+            .checked_sub(other.0)
+            .expect("physical address subtraction overflowed")
+    }
+}
+
+/// Rounds a raw address down to the given alignment.
+fn align_down(raw_address: u64, align: u64) -> u64 {
+    assert!(align.is_power_of_two(), "alignment must be a power of two");
+    raw_address & !(align - 1)
+}
+
+/// Rounds a raw address up to the given alignment, or gives `None` if
+/// that would overflow.
+fn align_up(raw_address: u64, align: u64) -> Option<u64> {
+    assert!(align.is_power_of_two(), "alignment must be a power of two");
+    raw_address
+        .checked_add(align - 1)
+        .map(|raw_address| raw_address & !(align - 1))
+}
+
+impl core::fmt::Debug for PhysicalAddress {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("PhysicalAddress")
+            .field(&format_args!("{:#018X}", self.0))
+            .finish()
+    }
+}
+
+bitflags! {
+    pub struct PageTableEntryFlags: u64 {
+
+        // The first byte's flags
+        const PRESENT = 0b0000_0001;
+        const WRITABLE = 0b0000_0010;
+        const USER_ACCESSIBLE = 0b0000_0100;
+        const WRITE_THROUGH = 0b0000_1000;
+        const DISABLE_CACHE = 0b0001_0000;
+        const ACCESSED = 0b0010_0000;
+        const DIRTY = 0b0100_0000;
+        const HUGE_PAGE = 0b1000_0000;
+
+        // The second byte's flags
+        const GLOBAL = 0b0000_0001 << 8;
+
+        // The eighth byte's flags
+        const NO_EXECUTE = 0b1000_0000 << 56;
+    }
+}
+
+/// Provides a wrapper around a page table entry.
+///
+/// A page table entry is 64-bits long, and is laid out
+/// as follows:
+///
+/// | Bits         | Length | Purpose                 |
+/// | -------------| -------| ------------------------|
+/// |  0           |  1     | Present Flag            |
+/// |  1           |  1     | Writable Flag           |
+/// |  2           |  1     | User Accessible Flag    |
+/// |  3           |  1     | Write Through Flag      |
+/// |  4           |  1     | Disable Cache Flag      |
+/// |  5           |  1     | Accessed Flag           |
+/// |  6           |  1     | Dirty Flag              |
+/// |  7           |  1     | Huge Page Flag          |
+/// |  8           |  1     | Global Flag             |
+/// |  9 - 11      |  3     | Unused/Available        |
+/// | 12 - 51      | 40     | Physical Address        |
+/// | 52 - 62      | 11     | Unused/Available        |
+/// | 63           |  1     | No Execute Bit          |
+#[repr(transparent)]
+pub struct PageTableEntry(u64);
+
+impl PageTableEntry {
+    // NOTE: we only present the bits that are defined
+    const FLAGS_MASK_B1: u64 = 0b1111_1111;
+    const FLAGS_MASK_B2: u64 = 0b0000_0001;
+    const FLAGS_MASK_B3: u64 = 0b0000_0000;
+    const FLAGS_MASK_B4: u64 = 0b0000_0000;
+    const FLAGS_MASK_B5: u64 = 0b0000_0000;
+    const FLAGS_MASK_B6: u64 = 0b0000_0000;
+    const FLAGS_MASK_B7: u64 = 0b0000_0000;
+    const FLAGS_MASK_B8: u64 = 0b1000_0000;
+
+    const FLAGS_MASK: u64 = Self::FLAGS_MASK_B8 << 56
+        | Self::FLAGS_MASK_B7 << 48
+        | Self::FLAGS_MASK_B6 << 40
+        | Self::FLAGS_MASK_B5 << 32
+        | Self::FLAGS_MASK_B4 << 24
+        | Self::FLAGS_MASK_B3 << 16
+        | Self::FLAGS_MASK_B2 << 8
+        | Self::FLAGS_MASK_B1;
+
+    const PHYSICAL_ADDRESS_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+    /// Constructs an entry referring to the given physical address
+    /// with the given flags.
+    pub fn new(physical_address: PhysicalAddress, flags: PageTableEntryFlags) -> Self {
+        let mut entry = Self(0);
+        entry.set(physical_address, flags);
+        entry
+    }
+
+    /// Determines whether the entry is entirely zero, and so refers
+    /// to nothing.
+    pub fn is_unused(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Sets the entry to refer to the given physical address with the
+    /// given flags.
+    pub fn set(&mut self, physical_address: PhysicalAddress, flags: PageTableEntryFlags) {
+        self.0 = (physical_address.to_raw() & Self::PHYSICAL_ADDRESS_MASK) | flags.bits();
+    }
+
+    /// Replaces the flags of the entry, leaving the physical address
+    /// alone.
+    pub fn set_flags(&mut self, flags: PageTableEntryFlags) {
+        self.0 = (self.0 & !Self::FLAGS_MASK) | flags.bits();
+    }
+
+    /// Clears the entry.
+    pub fn set_unused(&mut self) {
+        self.0 = 0;
+    }
+
+    pub fn flags(&self) -> PageTableEntryFlags {
+        let flags = self.0 & Self::FLAGS_MASK;
+
+        // NOTE: This is safe because we already mask off
+        // any flags that aren't defined.
+        unsafe { PageTableEntryFlags::from_bits_unchecked(flags) }
+    }
+
+    pub fn physical_address(&self) -> PhysicalAddress {
+        // NOTE: The physical address already has the lower 12-bits
+        // set to zero due to the way the page table entry is laid out.
+        let physical_address = self.0 & Self::PHYSICAL_ADDRESS_MASK;
+        unsafe { PhysicalAddress::from_raw_unchecked(physical_address) }
+    }
+}
+
+impl core::fmt::Debug for PageTableEntry {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("PageTableEntry")
+            .field("flags", &self.flags())
+            .field("physical_address", &self.physical_address())
+            .finish()
+    }
+}
+
+/// Provides support for inspecting and modifying a page table.
+#[repr(C, align(4096))]
+pub struct PageTable {
+    entries: [PageTableEntry; 512],
+}
+
+impl PageTable {
+    /// The number of entries in a page table.
+    pub const ENTRY_COUNT: usize = 512;
+
+    /// Clears every entry in the table.
+    pub fn zero(&mut self) {
+        for entry in self.entries.iter_mut() {
+            entry.set_unused();
+        }
+    }
+
+    /// Determines whether every entry in the table is unused.
+    pub fn is_empty(&self) -> bool {
+        self.entries.iter().all(PageTableEntry::is_unused)
+    }
+}
+
+impl Index<usize> for PageTable {
+    type Output = PageTableEntry;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        &self.entries[index]
+    }
+}
+
+impl IndexMut<usize> for PageTable {
+    fn index_mut(&mut self, index: usize) -> &mut PageTableEntry {
+        &mut self.entries[index]
+    }
+}
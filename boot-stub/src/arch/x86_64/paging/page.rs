@@ -0,0 +1,153 @@
+//! Provides types for pages of linear memory and the frames of
+//! physical memory that back them.
+//!
+//! Pages and frames are generic over their size, which is one of the
+//! `Size4KiB`, `Size2MiB` and `Size1GiB` markers, so that a huge page
+//! can't be constructed at an address that isn't suitably aligned.
+
+use core::marker::PhantomData;
+
+use super::{LinearAddress, PhysicalAddress};
+
+/// The size of a regular page (and a frame) in bytes.
+pub const PAGE_SIZE: u64 = 4096;
+
+/// The size of a page that a linear address is mapped by.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PageSize {
+    /// A page mapped by a page table entry.
+    Size4KiB,
+
+    /// A huge page mapped by a page directory entry.
+    Size2MiB,
+
+    /// A huge page mapped by a page directory pointer table entry.
+    Size1GiB,
+}
+
+impl PageSize {
+    /// Gets the size of the page in bytes.
+    pub fn bytes(&self) -> u64 {
+        match self {
+            Self::Size4KiB => Size4KiB::SIZE,
+            Self::Size2MiB => Size2MiB::SIZE,
+            Self::Size1GiB => Size1GiB::SIZE,
+        }
+    }
+}
+
+/// Implemented by the markers for each size of page.
+pub trait PageSizeMarker: Copy + Eq + Ord {
+    /// The size in bytes.
+    const SIZE: u64;
+
+    /// The size as a value.
+    const KIND: PageSize;
+}
+
+/// Marks a regular 4KiB page.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Size4KiB {}
+
+/// Marks a 2MiB huge page.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Size2MiB {}
+
+/// Marks a 1GiB huge page.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Size1GiB {}
+
+impl PageSizeMarker for Size4KiB {
+    const SIZE: u64 = PAGE_SIZE;
+    const KIND: PageSize = PageSize::Size4KiB;
+}
+
+impl PageSizeMarker for Size2MiB {
+    const SIZE: u64 = 0x20_0000;
+    const KIND: PageSize = PageSize::Size2MiB;
+}
+
+impl PageSizeMarker for Size1GiB {
+    const SIZE: u64 = 0x4000_0000;
+    const KIND: PageSize = PageSize::Size1GiB;
+}
+
+/// A page of the linear address space.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Page<S: PageSizeMarker = Size4KiB>(u64, PhantomData<S>);
+
+impl<S: PageSizeMarker> Page<S> {
+    /// Gets the page containing the given address.
+    pub fn containing_address(address: LinearAddress) -> Self {
+        Self(address.to_raw() & !(S::SIZE - 1), PhantomData)
+    }
+
+    /// Gets the page starting at the given address, or `None` if the
+    /// address isn't aligned to the size of the page.
+    pub fn from_start_address(address: LinearAddress) -> Option<Self> {
+        if address.to_raw() & (S::SIZE - 1) == 0 {
+            Some(Self(address.to_raw(), PhantomData))
+        } else {
+            None
+        }
+    }
+
+    /// Gets the address of the start of the page.
+    pub fn start_address(&self) -> LinearAddress {
+        unsafe { LinearAddress::from_raw_unchecked(self.0) }
+    }
+
+    /// Gets the size of the page in bytes.
+    pub fn size(&self) -> u64 {
+        S::SIZE
+    }
+}
+
+impl<S: PageSizeMarker> core::fmt::Debug for Page<S> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("Page")
+            .field(&format_args!("{:#018X}", self.0))
+            .field(&S::KIND)
+            .finish()
+    }
+}
+
+/// A frame of physical memory.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct PhysFrame<S: PageSizeMarker = Size4KiB>(u64, PhantomData<S>);
+
+impl<S: PageSizeMarker> PhysFrame<S> {
+    /// Gets the frame containing the given address.
+    pub fn containing_address(address: PhysicalAddress) -> Self {
+        Self(address.to_raw() & !(S::SIZE - 1), PhantomData)
+    }
+
+    /// Gets the frame starting at the given address, or `None` if the
+    /// address isn't aligned to the size of the frame.
+    pub fn from_start_address(address: PhysicalAddress) -> Option<Self> {
+        if address.to_raw() & (S::SIZE - 1) == 0 {
+            Some(Self(address.to_raw(), PhantomData))
+        } else {
+            None
+        }
+    }
+
+    /// Gets the address of the start of the frame.
+    pub fn start_address(&self) -> PhysicalAddress {
+        unsafe { PhysicalAddress::from_raw_unchecked(self.0) }
+    }
+
+    /// Gets the size of the frame in bytes.
+    pub fn size(&self) -> u64 {
+        S::SIZE
+    }
+}
+
+impl<S: PageSizeMarker> core::fmt::Debug for PhysFrame<S> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("PhysFrame")
+            .field(&format_args!("{:#018X}", self.0))
+            .field(&S::KIND)
+            .finish()
+    }
+}
@@ -0,0 +1,157 @@
+//! Provides half-open ranges of linear and physical addresses, which
+//! can be iterated over a page (or frame) at a time.
+
+use super::page::{Page, PageSizeMarker, PhysFrame};
+use super::{LinearAddress, PhysicalAddress};
+
+/// A half-open range of linear addresses.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct LinearAddressRange {
+    start: LinearAddress,
+    end: LinearAddress,
+}
+
+impl LinearAddressRange {
+    /// Constructs a range from its first address and the address just
+    /// beyond its end.
+    pub fn new(start: LinearAddress, end: LinearAddress) -> Self {
+        assert!(start <= end, "address range ends before it starts");
+        Self { start, end }
+    }
+
+    /// Constructs a range from its first address and its length.
+    pub fn from_start_and_len(start: LinearAddress, len: u64) -> Self {
+        Self::new(start, start + len)
+    }
+
+    /// Gets the first address in the range.
+    pub fn start(&self) -> LinearAddress {
+        self.start
+    }
+
+    /// Gets the address just beyond the end of the range.
+    pub fn end(&self) -> LinearAddress {
+        self.end
+    }
+
+    /// Gets the length of the range in bytes.
+    pub fn len(&self) -> u64 {
+        self.end - self.start
+    }
+
+    /// Determines whether the range is empty.
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// Determines whether the range contains the given address.
+    pub fn contains(&self, address: LinearAddress) -> bool {
+        self.start <= address && address < self.end
+    }
+
+    /// Iterates over the pages that overlap the range.
+    pub fn pages<S: PageSizeMarker>(&self) -> impl Iterator<Item = Page<S>> {
+        let (first, last) = page_bounds(self.start.to_raw(), self.end.to_raw(), S::SIZE);
+
+        (first..last)
+            .step_by(S::SIZE as usize)
+            .map(|raw| Page::containing_address(unsafe { LinearAddress::from_raw_unchecked(raw) }))
+    }
+}
+
+impl core::fmt::Debug for LinearAddressRange {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{:#018X}..{:#018X}",
+            self.start.to_raw(),
+            self.end.to_raw()
+        )
+    }
+}
+
+/// A half-open range of physical addresses.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct PhysicalAddressRange {
+    start: PhysicalAddress,
+    end: PhysicalAddress,
+}
+
+impl PhysicalAddressRange {
+    /// Constructs a range from its first address and the address just
+    /// beyond its end.
+    pub fn new(start: PhysicalAddress, end: PhysicalAddress) -> Self {
+        assert!(start <= end, "address range ends before it starts");
+        Self { start, end }
+    }
+
+    /// Constructs a range from its first address and its length.
+    pub fn from_start_and_len(start: PhysicalAddress, len: u64) -> Self {
+        Self::new(start, start + len)
+    }
+
+    /// Gets the first address in the range.
+    pub fn start(&self) -> PhysicalAddress {
+        self.start
+    }
+
+    /// Gets the address just beyond the end of the range.
+    pub fn end(&self) -> PhysicalAddress {
+        self.end
+    }
+
+    /// Gets the length of the range in bytes.
+    pub fn len(&self) -> u64 {
+        self.end - self.start
+    }
+
+    /// Determines whether the range is empty.
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// Determines whether the range contains the given address.
+    pub fn contains(&self, address: PhysicalAddress) -> bool {
+        self.start <= address && address < self.end
+    }
+
+    /// Iterates over the frames that overlap the range.
+    pub fn frames<S: PageSizeMarker>(&self) -> impl Iterator<Item = PhysFrame<S>> {
+        let (first, last) = page_bounds(self.start.to_raw(), self.end.to_raw(), S::SIZE);
+
+        (first..last).step_by(S::SIZE as usize).map(|raw| {
+            PhysFrame::containing_address(unsafe { PhysicalAddress::from_raw_unchecked(raw) })
+        })
+    }
+}
+
+impl core::fmt::Debug for PhysicalAddressRange {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{:#018X}..{:#018X}",
+            self.start.to_raw(),
+            self.end.to_raw()
+        )
+    }
+}
+
+/// Gets the start of the first page overlapping a range, and the end
+/// of the last one.
+///
+/// NOTE: When the range ends inside the top page of the address space,
+/// the end of that page can't be represented, so it's clamped to the
+/// last address instead, which still lies beyond the page's start.
+fn page_bounds(start: u64, end: u64, page_size: u64) -> (u64, u64) {
+    if start == end {
+        return (start, start);
+    }
+
+    let first = start & !(page_size - 1);
+    let last = match end.checked_add(page_size - 1) {
+        Some(rounded) => rounded & !(page_size - 1),
+        None => u64::MAX,
+    };
+
+    (first, last)
+}
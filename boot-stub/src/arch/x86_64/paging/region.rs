@@ -0,0 +1,227 @@
+//! Provides bookkeeping of what lives where in a linear address space.
+//!
+//! Each region is a named, non-overlapping range of linear addresses,
+//! along with the permissions it's meant to be mapped with, so that
+//! dumps and fault reports can say which region an address belongs to.
+
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+
+use super::{LinearAddress, LinearAddressRange, PageTableEntryFlags, PhysicalAddress};
+
+/// The ways reserving a region can fail.
+#[derive(Debug)]
+pub enum RegionError {
+    /// The region being reserved (named first) overlaps an existing
+    /// region.
+    Overlaps(&'static str, Region),
+
+    /// The region being reserved is empty.
+    Empty(&'static str),
+
+    /// There's no gap large enough for the named region.
+    NoGap(&'static str, u64),
+}
+
+/// A named range of linear addresses.
+#[derive(Copy, Clone)]
+pub struct Region {
+    name: &'static str,
+    range: LinearAddressRange,
+    flags: PageTableEntryFlags,
+}
+
+impl Region {
+    /// Gets the name of the region.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Gets the range of linear addresses the region covers.
+    pub fn range(&self) -> LinearAddressRange {
+        self.range
+    }
+
+    /// Gets the flags the region is meant to be mapped with.
+    pub fn flags(&self) -> PageTableEntryFlags {
+        self.flags
+    }
+}
+
+impl core::fmt::Debug for Region {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{} {:?} {:?}", self.name, self.range, self.flags)
+    }
+}
+
+/// Keeps track of the regions in the address space with the given
+/// root page table.
+pub struct RegionManager {
+    root: PhysicalAddress,
+
+    /// The regions, sorted by their start address.
+    regions: Vec<Region>,
+}
+
+impl RegionManager {
+    /// Constructs a manager with no regions.
+    pub fn new(root: PhysicalAddress) -> Self {
+        Self {
+            root,
+            regions: Vec::new(),
+        }
+    }
+
+    /// Gets the physical address of the root page table of the address
+    /// space.
+    pub fn root(&self) -> PhysicalAddress {
+        self.root
+    }
+
+    /// Iterates over the regions in address order.
+    pub fn regions(&self) -> impl Iterator<Item = &Region> {
+        self.regions.iter()
+    }
+
+    /// Reserves the given range, failing if it overlaps an existing
+    /// region.
+    pub fn reserve(
+        &mut self,
+        name: &'static str,
+        range: LinearAddressRange,
+        flags: PageTableEntryFlags,
+    ) -> Result<Region, RegionError> {
+        if range.is_empty() {
+            return Err(RegionError::Empty(name));
+        }
+
+        let index = self.regions_starting_before(range.start(), false);
+
+        let neighbours = index.checked_sub(1).into_iter().chain(Some(index));
+
+        for neighbour in neighbours.filter_map(|index| self.regions.get(index)) {
+            if overlaps(neighbour.range, range) {
+                return Err(RegionError::Overlaps(name, *neighbour));
+            }
+        }
+
+        let region = Region { name, range, flags };
+        self.regions.insert(index, region);
+
+        Ok(region)
+    }
+
+    /// Reserves a range of the given length and alignment in the first
+    /// gap within `bounds` that's large enough.
+    pub fn reserve_anywhere(
+        &mut self,
+        name: &'static str,
+        len: u64,
+        align: u64,
+        bounds: LinearAddressRange,
+        flags: PageTableEntryFlags,
+    ) -> Result<Region, RegionError> {
+        if len == 0 {
+            return Err(RegionError::Empty(name));
+        }
+
+        let start = self
+            .find_gap(len, align, bounds)
+            .ok_or(RegionError::NoGap(name, len))?;
+
+        self.reserve(
+            name,
+            LinearAddressRange::from_start_and_len(start, len),
+            flags,
+        )
+    }
+
+    /// Finds the first address within `bounds`, aligned to `align`
+    /// (which must be a power of two), with `len` bytes free after it.
+    pub fn find_gap(
+        &self,
+        len: u64,
+        align: u64,
+        bounds: LinearAddressRange,
+    ) -> Option<LinearAddress> {
+        let end = bounds.end().to_raw();
+        let mut candidate = bounds.start().to_raw();
+
+        for region in &self.regions {
+            let region_start = region.range.start().to_raw();
+            let region_end = region.range.end().to_raw();
+
+            if region_end <= candidate {
+                continue;
+            }
+
+            if let Some(start) = fits_before(candidate, align, len, region_start.min(end)) {
+                return LinearAddress::new(start).ok();
+            }
+
+            if region_end >= end {
+                return None;
+            }
+
+            candidate = region_end;
+        }
+
+        fits_before(candidate, align, len, end).and_then(|start| LinearAddress::new(start).ok())
+    }
+
+    /// Releases the region starting at the given address, returning it.
+    pub fn release(&mut self, start: LinearAddress) -> Option<Region> {
+        let index = self
+            .regions
+            .binary_search_by_key(&start, |region| region.range.start())
+            .ok()?;
+
+        Some(self.regions.remove(index))
+    }
+
+    /// Counts the regions that start before the given address (or at
+    /// it, if `inclusive` is set), which is also where a region starting
+    /// at the address would be inserted.
+    fn regions_starting_before(&self, address: LinearAddress, inclusive: bool) -> usize {
+        let search = self.regions.binary_search_by(|region| {
+            let start = region.range.start();
+
+            if start < address || (inclusive && start == address) {
+                Ordering::Less
+            } else {
+                Ordering::Greater
+            }
+        });
+
+        // NOTE: The comparison never returns `Equal`, so the search
+        // always fails, with the index where the address would go.
+        search.unwrap_or_else(|index| index)
+    }
+
+    /// Finds the region containing the given address.
+    pub fn region_containing(&self, address: LinearAddress) -> Option<&Region> {
+        let index = self.regions_starting_before(address, true);
+
+        index
+            .checked_sub(1)
+            .map(|index| &self.regions[index])
+            .filter(|region| region.range.contains(address))
+    }
+}
+
+fn overlaps(a: LinearAddressRange, b: LinearAddressRange) -> bool {
+    a.start() < b.end() && b.start() < a.end()
+}
+
+/// Aligns `candidate` up, returning it if `len` bytes from there fit
+/// before `limit`.
+fn fits_before(candidate: u64, align: u64, len: u64, limit: u64) -> Option<u64> {
+    let start = candidate.checked_add(align - 1)? & !(align - 1);
+    let end = start.checked_add(len)?;
+
+    if end <= limit {
+        Some(start)
+    } else {
+        None
+    }
+}
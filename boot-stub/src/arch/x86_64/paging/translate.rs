@@ -0,0 +1,130 @@
+//! Provides translation of linear addresses into physical addresses
+//! by walking a hierarchy of page tables.
+
+use super::super::registers::CR3Value;
+use super::mapper::PhysicalToLinear;
+use super::page::PageSize;
+use super::{LinearAddress, PageTable, PageTableEntryFlags, PagingMode, PhysicalAddress};
+
+/// The flags that restrict access to a page, and which therefore have
+/// to be combined across every level of the hierarchy.
+const RESTRICTING_FLAGS: PageTableEntryFlags = PageTableEntryFlags::from_bits_truncate(
+    PageTableEntryFlags::WRITABLE.bits() | PageTableEntryFlags::USER_ACCESSIBLE.bits(),
+);
+
+/// The effective flags of the path to the root table, i.e. before any
+/// entries have restricted anything.
+pub(super) const ROOT_FLAGS: PageTableEntryFlags = RESTRICTING_FLAGS;
+
+/// Combines the effective flags of the path to an entry with the
+/// entry's own flags, giving the effective flags of the path through
+/// the entry.
+pub(super) fn combine_flags(
+    path: PageTableEntryFlags,
+    entry: PageTableEntryFlags,
+) -> PageTableEntryFlags {
+    let mut combined = (entry - RESTRICTING_FLAGS) | (entry & path & RESTRICTING_FLAGS);
+
+    combined.set(
+        PageTableEntryFlags::NO_EXECUTE,
+        path.contains(PageTableEntryFlags::NO_EXECUTE)
+            || entry.contains(PageTableEntryFlags::NO_EXECUTE),
+    );
+
+    combined
+}
+
+/// Translates a linear address using the page tables under the given
+/// root, returning the physical address, the size of the page that
+/// maps it, and the effective flags of the mapping.
+///
+/// The effective flags are those of the final entry, except that the
+/// page is only writable or user accessible if every level allows it,
+/// and is no-execute if any level says so.
+///
+/// # Safety
+/// Every table under the root must be reachable through the given
+/// physical-to-linear conversion, and the hierarchy must have as many
+/// levels as the paging mode says.
+pub unsafe fn translate(
+    root: PhysicalAddress,
+    mode: PagingMode,
+    address: LinearAddress,
+    physical_to_linear: &impl PhysicalToLinear,
+) -> Option<(PhysicalAddress, PageSize, PageTableEntryFlags)> {
+    let root = table_at(root, physical_to_linear);
+    walk(root, mode, address, physical_to_linear)
+}
+
+/// Translates a linear address using the active page tables, i.e.
+/// those referred to by CR3, in the current paging mode.
+///
+/// # Safety
+/// Every active table must be reachable through the given
+/// physical-to-linear conversion.
+pub unsafe fn translate_active(
+    address: LinearAddress,
+    physical_to_linear: &impl PhysicalToLinear,
+) -> Option<(PhysicalAddress, PageSize, PageTableEntryFlags)> {
+    translate(
+        CR3Value::read().root_address(),
+        PagingMode::current(),
+        address,
+        physical_to_linear,
+    )
+}
+
+/// Walks the hierarchy below the given root table.
+pub(super) unsafe fn walk(
+    root: &PageTable,
+    mode: PagingMode,
+    address: LinearAddress,
+    physical_to_linear: &impl PhysicalToLinear,
+) -> Option<(PhysicalAddress, PageSize, PageTableEntryFlags)> {
+    if !mode.is_canonical(address.to_raw()) {
+        return None;
+    }
+
+    let mut table = root;
+    let mut path_flags = ROOT_FLAGS;
+
+    for level in (1..=mode.levels()).rev() {
+        let entry = &table[usize::from(address.level(level))];
+        let flags = entry.flags();
+
+        if !flags.contains(PageTableEntryFlags::PRESENT) {
+            return None;
+        }
+
+        path_flags = combine_flags(path_flags, flags);
+
+        let size = match level {
+            3 if flags.contains(PageTableEntryFlags::HUGE_PAGE) => Some(PageSize::Size1GiB),
+            2 if flags.contains(PageTableEntryFlags::HUGE_PAGE) => Some(PageSize::Size2MiB),
+            1 => Some(PageSize::Size4KiB),
+            _ => None,
+        };
+
+        if let Some(size) = size {
+            let mask = size.bytes() - 1;
+
+            // NOTE: The bottom bits of a huge page's address field hold
+            // other things (such as the PAT bit) so mask them off.
+            let base = entry.physical_address().to_raw() & !mask;
+            let physical = PhysicalAddress::from_raw_unchecked(base | (address.to_raw() & mask));
+
+            return Some((physical, size, path_flags));
+        }
+
+        table = table_at(entry.physical_address(), physical_to_linear);
+    }
+
+    None
+}
+
+pub(super) unsafe fn table_at<'a>(
+    address: PhysicalAddress,
+    physical_to_linear: &impl PhysicalToLinear,
+) -> &'a PageTable {
+    &*(physical_to_linear.to_linear(address).to_raw() as *const PageTable)
+}
@@ -0,0 +1,260 @@
+//! Provides a driver for the pair of 8259 programmable interrupt
+//! controllers found in PCs, chained so that the secondary's output is
+//! on the primary's IRQ 2.
+//!
+//! Out of the box, the primary's IRQs are delivered on vectors 8 to 15,
+//! which collide with the exceptions, so the controllers must be
+//! remapped (or disabled) before interrupts are enabled.
+//!
+//! For more details see the Intel 8259A datasheet.
+
+use super::port::{Port, PortAddress};
+
+/// The IO address of the primary controller's command port.
+pub const PRIMARY_COMMAND_ADDRESS: PortAddress = PortAddress::from_raw(0x20);
+
+/// The IO address of the primary controller's data port.
+pub const PRIMARY_DATA_ADDRESS: PortAddress = PortAddress::from_raw(0x21);
+
+/// The IO address of the secondary controller's command port.
+pub const SECONDARY_COMMAND_ADDRESS: PortAddress = PortAddress::from_raw(0xA0);
+
+/// The IO address of the secondary controller's data port.
+pub const SECONDARY_DATA_ADDRESS: PortAddress = PortAddress::from_raw(0xA1);
+
+/// The first vector the primary's IRQs are conventionally remapped
+/// to, just after the exceptions.
+pub const DEFAULT_PRIMARY_OFFSET: u8 = 32;
+
+/// The first vector the secondary's IRQs are conventionally remapped
+/// to, just after the primary's.
+pub const DEFAULT_SECONDARY_OFFSET: u8 = 40;
+
+/// The number of IRQs each controller has.
+const IRQS_PER_PIC: u8 = 8;
+
+/// The primary's IRQ that the secondary is attached to.
+const CASCADE_IRQ: u8 = 2;
+
+/// The IRQ (on each controller) that spurious interrupts arrive on.
+const SPURIOUS_IRQ: u8 = 7;
+
+/// ICW1: Initialization is starting, and ICW4 will be sent.
+const ICW1_INIT_WITH_ICW4: u8 = 0x11;
+
+/// ICW4: The controller is in 8086 mode.
+const ICW4_8086: u8 = 0x01;
+
+/// OCW2: A non-specific end of interrupt.
+const OCW2_EOI: u8 = 0x20;
+
+/// OCW3: The next read of the command port gets the IRR.
+const OCW3_READ_IRR: u8 = 0x0A;
+
+/// OCW3: The next read of the command port gets the ISR.
+const OCW3_READ_ISR: u8 = 0x0B;
+
+/// A single controller.
+struct Pic {
+    offset: u8,
+    command: Port<u8>,
+    data: Port<u8>,
+}
+
+impl Pic {
+    fn read_register(&self, ocw3: u8) -> u8 {
+        self.command.write(ocw3);
+        self.command.read()
+    }
+
+    fn end_of_interrupt(&self) {
+        self.command.write(OCW2_EOI);
+    }
+}
+
+/// The pair of chained controllers.
+///
+/// IRQs are numbered 0 to 15, with 8 to 15 being the secondary's.
+pub struct ChainedPics {
+    primary: Pic,
+    secondary: Pic,
+
+    /// A port that's written to give the controllers time to act on
+    /// each initialization word, since they're slow on real hardware.
+    wait: Port<u8>,
+}
+
+impl ChainedPics {
+    /// Constructs a driver for the controllers at their standard
+    /// locations, which delivers IRQs at the given vector offsets once
+    /// initialized.
+    ///
+    /// # Safety
+    /// This is unsafe because nothing else may be using the
+    /// controllers, and there must be controllers to drive.
+    pub unsafe fn new(primary_offset: u8, secondary_offset: u8) -> Self {
+        Self {
+            primary: Pic {
+                offset: primary_offset,
+                command: Port::new(PRIMARY_COMMAND_ADDRESS),
+                data: Port::new(PRIMARY_DATA_ADDRESS),
+            },
+            secondary: Pic {
+                offset: secondary_offset,
+                command: Port::new(SECONDARY_COMMAND_ADDRESS),
+                data: Port::new(SECONDARY_DATA_ADDRESS),
+            },
+            wait: Port::new(PortAddress::from_raw(0x80)),
+        }
+    }
+
+    /// Initializes the controllers so that IRQs are delivered at the
+    /// configured vectors, keeping the masks they had before.
+    pub fn initialize(&mut self) {
+        let masks = self.masks();
+
+        self.write_commands(ICW1_INIT_WITH_ICW4, ICW1_INIT_WITH_ICW4);
+
+        // ICW2: The vector offsets.
+        self.write_data(self.primary.offset, self.secondary.offset);
+
+        // ICW3: The primary has the secondary on its cascade IRQ (as a
+        // bit mask), and the secondary is told its cascade identity.
+        self.write_data(1 << CASCADE_IRQ, CASCADE_IRQ);
+
+        self.write_data(ICW4_8086, ICW4_8086);
+
+        self.set_masks(masks);
+    }
+
+    /// Disables the controllers entirely, for when the APIC takes over.
+    ///
+    /// The controllers are remapped to the configured vectors first,
+    /// since even fully masked, they can still raise spurious IRQs,
+    /// which mustn't arrive on the exception vectors.
+    pub fn disable(&mut self) {
+        self.initialize();
+        self.set_masks(0xFFFF);
+    }
+
+    /// Gets the vector the given IRQ is delivered on.
+    pub fn vector(&self, irq: u8) -> u8 {
+        let (pic, line) = self.pic_for(irq);
+        pic.offset + line
+    }
+
+    /// Gets the IRQ that's delivered on the given vector, if any.
+    pub fn irq(&self, vector: u8) -> Option<u8> {
+        [&self.primary, &self.secondary]
+            .iter()
+            .enumerate()
+            .find(|(_, pic)| vector >= pic.offset && vector - pic.offset < IRQS_PER_PIC)
+            .map(|(index, pic)| index as u8 * IRQS_PER_PIC + (vector - pic.offset))
+    }
+
+    /// Gets the masks of both controllers, with a set bit meaning that
+    /// the IRQ is masked. The secondary's masks are in the upper byte.
+    pub fn masks(&self) -> u16 {
+        u16::from(self.secondary.data.read()) << 8 | u16::from(self.primary.data.read())
+    }
+
+    /// Sets the masks of both controllers, as returned by `masks`.
+    pub fn set_masks(&mut self, masks: u16) {
+        self.primary.data.write(masks as u8);
+        self.secondary.data.write((masks >> 8) as u8);
+    }
+
+    /// Stops the given IRQ from being delivered.
+    pub fn mask(&mut self, irq: u8) {
+        let (pic, line) = self.pic_for(irq);
+        pic.data.write(pic.data.read() | 1 << line);
+    }
+
+    /// Allows the given IRQ to be delivered. Unmasking one of the
+    /// secondary's IRQs also unmasks the cascade IRQ on the primary.
+    pub fn unmask(&mut self, irq: u8) {
+        let (pic, line) = self.pic_for(irq);
+        pic.data.write(pic.data.read() & !(1 << line));
+
+        if irq >= IRQS_PER_PIC {
+            let primary = &self.primary.data;
+            primary.write(primary.read() & !(1 << CASCADE_IRQ));
+        }
+    }
+
+    /// Gets the in-service registers of both controllers, which hold
+    /// the IRQs currently being handled. The secondary's are in the
+    /// upper byte.
+    pub fn in_service(&self) -> u16 {
+        u16::from(self.secondary.read_register(OCW3_READ_ISR)) << 8
+            | u16::from(self.primary.read_register(OCW3_READ_ISR))
+    }
+
+    /// Gets the interrupt request registers of both controllers, which
+    /// hold the IRQs that have been raised but not yet delivered. The
+    /// secondary's are in the upper byte.
+    pub fn requested(&self) -> u16 {
+        u16::from(self.secondary.read_register(OCW3_READ_IRR)) << 8
+            | u16::from(self.primary.read_register(OCW3_READ_IRR))
+    }
+
+    /// Determines whether an interrupt for the given IRQ is spurious,
+    /// in which case it must not be handled, or acknowledged with
+    /// `end_of_interrupt`.
+    ///
+    /// Only IRQs 7 and 15 can be spurious, and they are when the IRQ
+    /// isn't in service. For a spurious IRQ 15 the primary still gets
+    /// an EOI here, since as far as it's concerned, the cascade IRQ was
+    /// genuine.
+    pub fn is_spurious(&mut self, irq: u8) -> bool {
+        let (pic, line) = self.pic_for(irq);
+
+        if line != SPURIOUS_IRQ || pic.read_register(OCW3_READ_ISR) & 1 << line != 0 {
+            return false;
+        }
+
+        if irq >= IRQS_PER_PIC {
+            self.primary.end_of_interrupt();
+        }
+
+        true
+    }
+
+    /// Acknowledges the given IRQ, so that the controllers can deliver
+    /// further IRQs of the same or lower priority.
+    pub fn end_of_interrupt(&mut self, irq: u8) {
+        if irq >= IRQS_PER_PIC {
+            self.secondary.end_of_interrupt();
+        }
+
+        self.primary.end_of_interrupt();
+    }
+
+    fn pic_for(&self, irq: u8) -> (&Pic, u8) {
+        assert!(irq < 2 * IRQS_PER_PIC, "IRQ {} is out of range", irq);
+
+        if irq < IRQS_PER_PIC {
+            (&self.primary, irq)
+        } else {
+            (&self.secondary, irq - IRQS_PER_PIC)
+        }
+    }
+
+    fn write_commands(&self, primary: u8, secondary: u8) {
+        self.primary.command.write(primary);
+        self.io_wait();
+        self.secondary.command.write(secondary);
+        self.io_wait();
+    }
+
+    fn write_data(&self, primary: u8, secondary: u8) {
+        self.primary.data.write(primary);
+        self.io_wait();
+        self.secondary.data.write(secondary);
+        self.io_wait();
+    }
+
+    fn io_wait(&self) {
+        self.wait.write(0);
+    }
+}
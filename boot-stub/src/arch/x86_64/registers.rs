@@ -1,4 +1,34 @@
-use super::paging::PhysicalAddress;
+use super::msr::IA32_EFER;
+use super::paging::{PhysFrame, PhysicalAddress};
+use bitflags::bitflags;
+
+/// Provides support for inspecting the contents of the second
+/// control register, which holds the linear address that caused the
+/// most recent page fault.
+#[repr(transparent)]
+pub struct CR2Value(u64);
+
+impl CR2Value {
+    /// Reads the current value of CR2.
+    pub fn read() -> Self {
+        let result: u64;
+
+        unsafe {
+            asm!(
+            "mov {0}, cr2",
+            out(reg) result,
+            );
+        }
+
+        Self(result)
+    }
+
+    /// Gets the raw value, which isn't necessarily canonical, since
+    /// it's whatever address the faulting access tried to use.
+    pub fn to_raw(&self) -> u64 {
+        self.0
+    }
+}
 
 /// Provides support for inspecting/manipulating the
 /// contents of the third control register.
@@ -7,7 +37,7 @@ pub struct CR3Value(u64);
 
 impl CR3Value {
     const FLAGS_OR_PCID_MASK: u64 = 0x0000_0000_0000_0FFF;
-    const PML4_MASK: u64 = 0xFFFF_FFFF_FFFF_F000;
+    const ROOT_MASK: u64 = 0xFFFF_FFFF_FFFF_F000;
 
     /// Reads the current value of CR3.
     pub fn read() -> Self {
@@ -23,11 +53,17 @@ impl CR3Value {
         Self(result)
     }
 
+    /// Gets the physical address of the root page table, which
+    /// is a PML5 when CR4.LA57 is set, and a PML4 otherwise.
+    pub fn root_address(&self) -> PhysicalAddress {
+        let root = self.0 & Self::ROOT_MASK;
+        unsafe { PhysicalAddress::from_raw_unchecked(root) }
+    }
+
     /// Gets the physical address of the root page table
-    /// (Page Map Level 4).
+    /// (Page Map Level 4), assuming 4-level paging.
     pub fn pml4_address(&self) -> PhysicalAddress {
-        let pml4 = self.0 & Self::PML4_MASK;
-        unsafe { PhysicalAddress::from_raw_unchecked(pml4) }
+        self.root_address()
     }
 
     /// Gets the flags (if CR4.PCID is 0), or the
@@ -35,4 +71,189 @@ impl CR3Value {
     pub fn flags_or_pcid(&self) -> u16 {
         (self.0 & Self::FLAGS_OR_PCID_MASK) as u16
     }
+
+    /// Switches to the root page table in the given frame, with
+    /// the given flags (if CR4.PCID is 0), or PCID (if CR4.PCID
+    /// is 1).
+    ///
+    /// Unless PCIDs are in use, this also flushes all non-global
+    /// TLB entries.
+    ///
+    /// # Safety
+    /// This is unsafe because the new page tables must map the
+    /// code that's running, its stack, and anything else that's
+    /// still in use.
+    pub unsafe fn write(root: PhysFrame, flags_or_pcid: u16) {
+        let value =
+            root.start_address().to_raw() | (u64::from(flags_or_pcid) & Self::FLAGS_OR_PCID_MASK);
+
+        asm!(
+        "mov cr3, {0}",
+        in(reg) value,
+        options(nostack, preserves_flags),
+        );
+    }
+}
+
+bitflags! {
+    /// The flags held in CR0.
+    pub struct CR0Flags: u64 {
+        const PROTECTED_MODE_ENABLE = 1 << 0;
+        const MONITOR_COPROCESSOR = 1 << 1;
+        const EMULATION = 1 << 2;
+        const TASK_SWITCHED = 1 << 3;
+        const EXTENSION_TYPE = 1 << 4;
+        const NUMERIC_ERROR = 1 << 5;
+        const WRITE_PROTECT = 1 << 16;
+        const ALIGNMENT_MASK = 1 << 18;
+        const NOT_WRITE_THROUGH = 1 << 29;
+        const CACHE_DISABLE = 1 << 30;
+        const PAGING = 1 << 31;
+    }
+}
+
+/// Provides support for inspecting/manipulating the
+/// contents of the first control register.
+#[repr(transparent)]
+pub struct CR0Value(u64);
+
+impl CR0Value {
+    /// Reads the current value of CR0.
+    pub fn read() -> Self {
+        let result: u64;
+
+        unsafe {
+            asm!(
+            "mov {0}, cr0",
+            out(reg) result,
+            );
+        }
+
+        Self(result)
+    }
+
+    /// Gets the flags.
+    pub fn flags(&self) -> CR0Flags {
+        CR0Flags::from_bits_truncate(self.0)
+    }
+
+    /// Replaces the flags, preserving any reserved bits.
+    ///
+    /// # Safety
+    /// This is unsafe because changing CR0 can change the way memory
+    /// is accessed in arbitrary ways.
+    pub unsafe fn write_flags(&self, flags: CR0Flags) {
+        let value = (self.0 & !CR0Flags::all().bits()) | flags.bits();
+
+        asm!(
+        "mov cr0, {0}",
+        in(reg) value,
+        );
+    }
+}
+
+bitflags! {
+    /// The flags held in the IA32_EFER model specific register.
+    pub struct EFERFlags: u64 {
+        const SYSTEM_CALL_EXTENSIONS = 1 << 0;
+        const LONG_MODE_ENABLE = 1 << 8;
+        const LONG_MODE_ACTIVE = 1 << 10;
+        const NO_EXECUTE_ENABLE = 1 << 11;
+    }
+}
+
+/// Provides support for inspecting/manipulating the contents of
+/// the extended feature enable register.
+#[repr(transparent)]
+pub struct EFERValue(u64);
+
+impl EFERValue {
+    /// Reads the current value of IA32_EFER.
+    pub fn read() -> Self {
+        // NOTE: This is safe because IA32_EFER always exists in
+        // long mode.
+        Self(unsafe { IA32_EFER.read() })
+    }
+
+    /// Gets the flags.
+    pub fn flags(&self) -> EFERFlags {
+        EFERFlags::from_bits_truncate(self.0)
+    }
+
+    /// Replaces the flags, preserving any other bits.
+    ///
+    /// # Safety
+    /// This is unsafe because changing IA32_EFER can change the way
+    /// memory is accessed in arbitrary ways.
+    pub unsafe fn write_flags(&self, flags: EFERFlags) {
+        IA32_EFER.write((self.0 & !EFERFlags::all().bits()) | flags.bits());
+    }
+}
+
+bitflags! {
+    /// The flags held in CR4.
+    pub struct CR4Flags: u64 {
+        const VIRTUAL_8086_MODE_EXTENSIONS = 1 << 0;
+        const PROTECTED_MODE_VIRTUAL_INTERRUPTS = 1 << 1;
+        const TIME_STAMP_DISABLE = 1 << 2;
+        const DEBUGGING_EXTENSIONS = 1 << 3;
+        const PAGE_SIZE_EXTENSIONS = 1 << 4;
+        const PHYSICAL_ADDRESS_EXTENSION = 1 << 5;
+        const MACHINE_CHECK_ENABLE = 1 << 6;
+        const PAGE_GLOBAL_ENABLE = 1 << 7;
+        const PERFORMANCE_COUNTER_ENABLE = 1 << 8;
+        const OSFXSR = 1 << 9;
+        const OSXMMEXCPT = 1 << 10;
+        const USER_MODE_INSTRUCTION_PREVENTION = 1 << 11;
+        const LA57 = 1 << 12;
+        const VMX_ENABLE = 1 << 13;
+        const SMX_ENABLE = 1 << 14;
+        const FSGSBASE = 1 << 16;
+        const PCID_ENABLE = 1 << 17;
+        const OSXSAVE = 1 << 18;
+        const SUPERVISOR_MODE_EXECUTION_PREVENTION = 1 << 20;
+        const SUPERVISOR_MODE_ACCESS_PREVENTION = 1 << 21;
+        const PROTECTION_KEY_ENABLE = 1 << 22;
+    }
+}
+
+/// Provides support for inspecting/manipulating the
+/// contents of the fourth control register.
+#[repr(transparent)]
+pub struct CR4Value(u64);
+
+impl CR4Value {
+    /// Reads the current value of CR4.
+    pub fn read() -> Self {
+        let result: u64;
+
+        unsafe {
+            asm!(
+            "mov {0}, cr4",
+            out(reg) result,
+            );
+        }
+
+        Self(result)
+    }
+
+    /// Gets the flags.
+    pub fn flags(&self) -> CR4Flags {
+        CR4Flags::from_bits_truncate(self.0)
+    }
+
+    /// Replaces the flags, preserving any reserved bits.
+    ///
+    /// # Safety
+    /// This is unsafe because changing CR4 can change the way memory
+    /// is accessed in arbitrary ways. Note that LA57 can't be changed
+    /// while paging is enabled.
+    pub unsafe fn write_flags(&self, flags: CR4Flags) {
+        let value = (self.0 & !CR4Flags::all().bits()) | flags.bits();
+
+        asm!(
+        "mov cr4, {0}",
+        in(reg) value,
+        );
+    }
 }
@@ -0,0 +1,116 @@
+//! Provides invalidation of the translations cached in the TLB.
+//!
+//! Whenever a mapping in the active page tables is changed or
+//! removed, the processor may carry on using the old translation
+//! until it's invalidated using one of these.
+
+use super::cpuid;
+use super::paging::{LinearAddress, PhysFrame};
+use super::registers::CR3Value;
+
+/// Invalidates any translation of the page containing the given
+/// address, for the current PCID. This includes global translations.
+pub fn flush(address: LinearAddress) {
+    // NOTE: This is safe because invalidating a translation can only
+    // cause it to be fetched from the page tables again.
+    unsafe {
+        asm!(
+        "invlpg [{0}]",
+        in(reg) address.to_raw(),
+        options(nostack, preserves_flags),
+        );
+    }
+}
+
+/// Invalidates every non-global translation, for the current PCID,
+/// by reloading CR3.
+pub fn flush_all() {
+    let cr3 = CR3Value::read();
+
+    // NOTE: This is safe because the same page tables are reloaded.
+    unsafe {
+        CR3Value::write(
+            PhysFrame::containing_address(cr3.root_address()),
+            cr3.flags_or_pcid(),
+        );
+    }
+}
+
+/// The kinds of invalidation that INVPCID can perform.
+#[derive(Debug, Copy, Clone)]
+pub enum InvpcidCommand {
+    /// Invalidates the translation of a single address tagged with
+    /// the given PCID, excluding global translations.
+    Address(u16, LinearAddress),
+
+    /// Invalidates all translations tagged with the given PCID,
+    /// excluding global translations.
+    SingleContext(u16),
+
+    /// Invalidates all translations for every PCID, including global
+    /// translations.
+    AllContextsIncludingGlobal,
+
+    /// Invalidates all translations for every PCID, excluding global
+    /// translations.
+    AllContexts,
+}
+
+/// The descriptor INVPCID reads from memory.
+///
+/// | Bits     | Length | Purpose                                  |
+/// | ---------| -------| -----------------------------------------|
+/// |  0 - 11  | 12     | PCID                                     |
+/// | 12 - 63  | 52     | Reserved (must be zero)                  |
+/// | 64 - 127 | 64     | Linear address                           |
+#[repr(C, align(16))]
+struct InvpcidDescriptor {
+    pcid: u64,
+    address: u64,
+}
+
+/// Provides access to the INVPCID instruction, which can only be
+/// obtained on processors that support it.
+#[derive(Debug, Copy, Clone)]
+pub struct Invpcid(());
+
+impl Invpcid {
+    const PCID_MASK: u16 = 0x0FFF;
+
+    /// Gets access to INVPCID, or `None` if the processor doesn't
+    /// support it.
+    pub fn new() -> Option<Self> {
+        if cpuid::has_invpcid() {
+            Some(Self(()))
+        } else {
+            None
+        }
+    }
+
+    /// Performs the given invalidation.
+    pub fn invalidate(&self, command: InvpcidCommand) {
+        let (invalidation_type, pcid, address) = match command {
+            InvpcidCommand::Address(pcid, address) => (0u64, pcid, address.to_raw()),
+            InvpcidCommand::SingleContext(pcid) => (1, pcid, 0),
+            InvpcidCommand::AllContextsIncludingGlobal => (2, 0, 0),
+            InvpcidCommand::AllContexts => (3, 0, 0),
+        };
+
+        let descriptor = InvpcidDescriptor {
+            pcid: u64::from(pcid & Self::PCID_MASK),
+            address,
+        };
+
+        // NOTE: This is safe because the instruction is known to be
+        // supported, and invalidating a translation can only cause it
+        // to be fetched from the page tables again.
+        unsafe {
+            asm!(
+            "invpcid {0}, [{1}]",
+            in(reg) invalidation_type,
+            in(reg) &descriptor,
+            options(nostack, preserves_flags),
+            );
+        }
+    }
+}
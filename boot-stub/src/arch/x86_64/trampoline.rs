@@ -0,0 +1,47 @@
+//! Provides the final jump from the boot stub into the kernel.
+
+use super::paging::PhysicalAddress;
+
+/// Switches to the given root page table and stack, and then jumps
+/// to the kernel's entry point, passing `argument` as the first
+/// parameter according to the System V calling convention.
+///
+/// Interrupts are disabled before the switch, the kernel is expected
+/// to install its own IDT before enabling them again. The boot stub's
+/// IDT is still loaded, but neither it nor its handlers are mapped by
+/// the new page tables, so any exception before then triple faults.
+///
+/// # Safety
+/// The code of this function must be identity mapped by the new page
+/// tables, since it is still executing when CR3 is reloaded. The
+/// stack and entry point must be mapped by the new page tables, and
+/// the stack top must be 16-byte aligned.
+#[inline(never)]
+pub unsafe fn enter_kernel(
+    root_table: PhysicalAddress,
+    stack_top: u64,
+    entry_point: u64,
+    argument: u64,
+) -> ! {
+    // NOTE: The zero pushed onto the new stack stands in for a return
+    // address, so that the kernel sees the same stack alignment as it
+    // would had it been called.
+    asm!(
+        "cli",
+        "mov cr3, {root_table}",
+        "mov rsp, {stack_top}",
+        "push 0",
+        "jmp {entry_point}",
+        root_table = in(reg) root_table.to_raw(),
+        stack_top = in(reg) stack_top,
+        entry_point = in(reg) entry_point,
+        in("rdi") argument,
+        options(noreturn)
+    );
+}
+
+/// Gets the address of the trampoline's code, so that it can be
+/// identity mapped in the kernel's page tables.
+pub fn enter_kernel_address() -> u64 {
+    enter_kernel as *const () as u64
+}
@@ -0,0 +1,368 @@
+//! Provides construction of the address space the kernel is
+//! started in.
+//!
+//! The address space contains:
+//!
+//! * The kernel's segments, at the linear addresses they were linked
+//!   at.
+//! * All of physical memory, at `PHYSICAL_MEMORY_OFFSET`, using huge
+//!   pages.
+//! * The trampoline that switches to the address space, identity
+//!   mapped so that it can keep running across the switch.
+//! * Stacks, each with an unmapped guard page below it, between
+//!   `KERNEL_STACKS_START` and `KERNEL_STACKS_END`.
+//!
+//! The tables use 4-level or 5-level paging to match the firmware.
+//!
+//! Everything that gets mapped is also reserved as a named region, so
+//! that overlapping mappings are caught, and so that the regions can be
+//! handed on to anything that needs to know what lives where.
+
+use alloc::vec::Vec;
+
+use uefi::prelude::*;
+use uefi::table::boot::{AllocateType, MemoryType};
+
+use crate::arch::x86_64::cpuid;
+use crate::arch::x86_64::paging::*;
+use crate::memory::stack::{self, Stack, StackError};
+
+use osc_os_elf::SegmentFlags;
+
+use super::memory_map::{KERNEL_STACKS_MEMORY_TYPE, PAGE_TABLES_MEMORY_TYPE};
+use super::LoadedSegment;
+
+/// The linear address at which all of physical memory is mapped.
+pub const PHYSICAL_MEMORY_OFFSET: u64 = 0xFFFF_8000_0000_0000;
+
+/// The least amount of physical address space that gets mapped, even
+/// if there's less memory than this. This ensures that the devices
+/// that typically live just below 4GiB (the local APIC, I/O APIC and
+/// so on) are always reachable.
+pub const MINIMUM_PHYSICAL_MEMORY_END: u64 = 0x1_0000_0000;
+
+/// The start of the linear addresses that stacks are mapped in.
+pub const KERNEL_STACKS_START: u64 = 0xFFFF_FF00_0000_0000;
+
+/// The end (exclusive) of the linear addresses that stacks are mapped
+/// in.
+pub const KERNEL_STACKS_END: u64 = 0xFFFF_FF80_0000_0000;
+
+const PAGE_MASK: u64 = PAGE_SIZE - 1;
+
+/// The ways building the address space can fail.
+#[derive(Debug)]
+pub enum AddressSpaceError {
+    /// UEFI couldn't provide a page for the root page table.
+    AllocateRootFailed(Status),
+
+    /// Mapping the page at the given linear address failed.
+    MapFailed(u64, MapperError),
+
+    /// The linear and physical addresses of a kernel segment have
+    /// different offsets within their pages.
+    MisalignedSegment(u64),
+
+    /// An address involved in a mapping isn't valid.
+    InvalidAddress(AddressError),
+
+    /// Reserving the region for a mapping failed.
+    ReserveRegionFailed(RegionError),
+
+    /// Allocating a stack failed.
+    AllocateStackFailed(StackError),
+}
+
+/// Allocates frames of the given memory type from UEFI.
+struct UefiFrameAllocator<'a> {
+    boot_services: &'a BootServices,
+    memory_type: MemoryType,
+}
+
+impl FrameAllocator for UefiFrameAllocator<'_> {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        let address = self
+            .boot_services
+            .allocate_pages(AllocateType::AnyPages, self.memory_type, 1)
+            .warning_as_error()
+            .ok()?;
+
+        PhysFrame::from_start_address(unsafe { PhysicalAddress::from_raw_unchecked(address) })
+    }
+
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        // NOTE: There's nothing useful to do if this fails, the frame is
+        // just lost.
+        let _ = self
+            .boot_services
+            .free_pages(frame.start_address().to_raw(), 1);
+    }
+}
+
+/// Builds a fresh set of page tables using pages allocated from UEFI.
+pub struct AddressSpaceBuilder<'a> {
+    allocator: UefiFrameAllocator<'a>,
+    root: PhysicalAddress,
+    mapper: Mapper<'a, IdentityMapping>,
+    regions: RegionManager,
+}
+
+impl<'a> AddressSpaceBuilder<'a> {
+    /// Constructs a builder with an empty root page table.
+    ///
+    /// The tables are built for the current paging mode, since LA57
+    /// can't be changed while paging is enabled, so the kernel has to
+    /// be started in the same mode as the firmware.
+    pub fn new(boot_services: &'a BootServices) -> Result<Self, AddressSpaceError> {
+        let root = boot_services
+            .allocate_pages(AllocateType::AnyPages, PAGE_TABLES_MEMORY_TYPE, 1)
+            .warning_as_error()
+            .map_err(|err| AddressSpaceError::AllocateRootFailed(err.status()))?;
+
+        // NOTE: This is safe because we own the page we just allocated,
+        // and UEFI identity maps physical memory.
+        let root_table = unsafe { &mut *(root as *mut PageTable) };
+        root_table.zero();
+
+        let root = unsafe { PhysicalAddress::from_raw_unchecked(root) };
+
+        Ok(Self {
+            allocator: UefiFrameAllocator {
+                boot_services,
+                memory_type: PAGE_TABLES_MEMORY_TYPE,
+            },
+            root,
+            mapper: unsafe { Mapper::new(root_table, PagingMode::current(), IdentityMapping) },
+            regions: RegionManager::new(root),
+        })
+    }
+
+    /// Gets the physical address of the root page table.
+    pub fn root(&self) -> PhysicalAddress {
+        self.root
+    }
+
+    /// Finishes building, returning the regions that were mapped.
+    pub fn into_regions(self) -> RegionManager {
+        self.regions
+    }
+
+    /// Maps the loaded kernel segments at their linear addresses, with
+    /// the permissions they ask for.
+    pub(super) fn map_kernel_segments(
+        &mut self,
+        segments: &[LoadedSegment],
+    ) -> Result<(), AddressSpaceError> {
+        let mut pages = Vec::new();
+
+        for segment in segments {
+            if segment.virtual_address & PAGE_MASK != segment.physical_address & PAGE_MASK {
+                return Err(AddressSpaceError::MisalignedSegment(
+                    segment.virtual_address,
+                ));
+            }
+
+            let mut flags = PageTableEntryFlags::empty();
+
+            if segment.flags.contains(SegmentFlags::WRITABLE) {
+                flags |= PageTableEntryFlags::WRITABLE;
+            }
+
+            if !segment.flags.contains(SegmentFlags::EXECUTABLE) {
+                flags |= PageTableEntryFlags::NO_EXECUTE;
+            }
+
+            let linear = LinearAddressRange::from_start_and_len(
+                linear_address(segment.virtual_address)?,
+                segment.memory_size,
+            );
+
+            self.reserve(segment_region_name(segment.flags), linear, flags)?;
+
+            let physical_start = physical_address(segment.physical_address)?.align_down(PAGE_SIZE);
+
+            for (index, page) in linear.pages::<Size4KiB>().enumerate() {
+                let frame =
+                    PhysFrame::containing_address(physical_start + index as u64 * PAGE_SIZE);
+                pages.push((page, frame, flags));
+            }
+        }
+
+        // NOTE: Segments aren't required to be page aligned, so two
+        // segments can share a page, in which case the page gets the
+        // combined permissions of both.
+        pages.sort_unstable_by_key(|(page, _, _)| *page);
+
+        let mut merged: Vec<(Page, PhysFrame, PageTableEntryFlags)> =
+            Vec::with_capacity(pages.len());
+
+        for (page, frame, flags) in pages {
+            match merged.last_mut() {
+                Some(last) if last.0 == page => {
+                    if last.1 != frame {
+                        return Err(AddressSpaceError::MapFailed(
+                            page.start_address().to_raw(),
+                            MapperError::AlreadyMapped(last.1),
+                        ));
+                    }
+
+                    let executable = !last.2.contains(PageTableEntryFlags::NO_EXECUTE)
+                        || !flags.contains(PageTableEntryFlags::NO_EXECUTE);
+
+                    last.2 |= flags;
+                    last.2.set(PageTableEntryFlags::NO_EXECUTE, !executable);
+                }
+
+                _ => merged.push((page, frame, flags)),
+            }
+        }
+
+        for (page, frame, flags) in merged {
+            self.map(page, frame, flags)?;
+        }
+
+        Ok(())
+    }
+
+    /// Maps physical memory from zero up to (but excluding) the given
+    /// address at `PHYSICAL_MEMORY_OFFSET`.
+    ///
+    /// This uses the largest pages the processor supports, rounding the
+    /// end up to a multiple of their size, so that mapping machines
+    /// with lots of memory doesn't take lots of page tables.
+    pub fn map_physical_memory(&mut self, physical_end: u64) -> Result<(), AddressSpaceError> {
+        if cpuid::has_1gib_pages() {
+            self.map_physical_memory_with::<Size1GiB>(physical_end)
+        } else {
+            self.map_physical_memory_with::<Size2MiB>(physical_end)
+        }
+    }
+
+    fn map_physical_memory_with<S: PageSizeMarker>(
+        &mut self,
+        physical_end: u64,
+    ) -> Result<(), AddressSpaceError> {
+        let flags = PageTableEntryFlags::WRITABLE | PageTableEntryFlags::NO_EXECUTE;
+        let offset = linear_address(PHYSICAL_MEMORY_OFFSET)?;
+        let physical =
+            PhysicalAddressRange::new(physical_address(0)?, physical_address(physical_end)?);
+
+        let linear = LinearAddressRange::from_start_and_len(
+            offset,
+            physical.end().align_up(S::SIZE).to_raw(),
+        );
+
+        self.reserve("physical memory", linear, flags)?;
+
+        for frame in physical.frames::<S>() {
+            let page = Page::containing_address(offset + frame.start_address().to_raw());
+            self.map(page, frame, flags)?;
+        }
+
+        Ok(())
+    }
+
+    /// Maps the pages covering the given range of physical memory at
+    /// the same linear addresses, as a region with the given name.
+    pub fn identity_map(
+        &mut self,
+        name: &'static str,
+        physical_start: u64,
+        length: u64,
+        flags: PageTableEntryFlags,
+    ) -> Result<(), AddressSpaceError> {
+        let physical =
+            PhysicalAddressRange::from_start_and_len(physical_address(physical_start)?, length);
+
+        let linear = LinearAddressRange::new(
+            linear_address(physical.start().align_down(PAGE_SIZE).to_raw())?,
+            linear_address(physical.end().align_up(PAGE_SIZE).to_raw())?,
+        );
+
+        self.reserve(name, linear, flags)?;
+
+        for frame in physical.frames::<Size4KiB>() {
+            let page = Page::containing_address(linear_address(frame.start_address().to_raw())?);
+            self.map(page, frame, flags)?;
+        }
+
+        Ok(())
+    }
+
+    /// Allocates a stack of the given number of pages, with an unmapped
+    /// guard page below it, as a region with the given name.
+    ///
+    /// This can be used for as many stacks as are needed, such as the
+    /// stacks for interrupts and for other processors, as well as the
+    /// stack the kernel is started on.
+    pub fn allocate_stack(
+        &mut self,
+        name: &'static str,
+        pages: u64,
+    ) -> Result<Stack, AddressSpaceError> {
+        let mut stack_frames = UefiFrameAllocator {
+            boot_services: self.allocator.boot_services,
+            memory_type: KERNEL_STACKS_MEMORY_TYPE,
+        };
+
+        let window = LinearAddressRange::new(
+            linear_address(KERNEL_STACKS_START)?,
+            linear_address(KERNEL_STACKS_END)?,
+        );
+
+        stack::allocate_stack(
+            &mut self.mapper,
+            &mut self.regions,
+            &mut stack_frames,
+            &mut self.allocator,
+            name,
+            pages,
+            window,
+        )
+        .map_err(AddressSpaceError::AllocateStackFailed)
+    }
+
+    fn reserve(
+        &mut self,
+        name: &'static str,
+        range: LinearAddressRange,
+        flags: PageTableEntryFlags,
+    ) -> Result<(), AddressSpaceError> {
+        self.regions
+            .reserve(name, range, flags)
+            .map(|_| ())
+            .map_err(AddressSpaceError::ReserveRegionFailed)
+    }
+
+    fn map<S: PageSizeMarker>(
+        &mut self,
+        page: Page<S>,
+        frame: PhysFrame<S>,
+        flags: PageTableEntryFlags,
+    ) -> Result<(), AddressSpaceError> {
+        // NOTE: The tables being built aren't active, so there's
+        // nothing to flush.
+        self.mapper
+            .map(page, frame, flags, &mut self.allocator)
+            .map(MapperFlush::ignore)
+            .map_err(|err| AddressSpaceError::MapFailed(page.start_address().to_raw(), err))
+    }
+}
+
+fn segment_region_name(flags: SegmentFlags) -> &'static str {
+    if flags.contains(SegmentFlags::EXECUTABLE) {
+        "kernel code"
+    } else if flags.contains(SegmentFlags::WRITABLE) {
+        "kernel data"
+    } else {
+        "kernel read-only data"
+    }
+}
+
+fn linear_address(raw: u64) -> Result<LinearAddress, AddressSpaceError> {
+    LinearAddress::new(raw).map_err(AddressSpaceError::InvalidAddress)
+}
+
+fn physical_address(raw: u64) -> Result<PhysicalAddress, AddressSpaceError> {
+    PhysicalAddress::new(raw).map_err(AddressSpaceError::InvalidAddress)
+}
@@ -0,0 +1,145 @@
+//! Provides a single block of memory holding the boot information
+//! along with everything it refers to, so that the kernel can reclaim
+//! it all in one go once it's done with it.
+
+use core::mem::{align_of, size_of};
+use osc_os_boot_info::*;
+
+/// A block of pages into which the boot information is built.
+pub struct BootInfoArea {
+    physical_start: u64,
+    size: usize,
+    used: usize,
+    physical_memory_offset: u64,
+    memory_map: *mut MemoryRegion,
+    memory_map_capacity: usize,
+}
+
+impl BootInfoArea {
+    /// Works out an upper bound on the number of bytes needed to hold
+    /// the boot information, a memory map with the given capacity, and
+    /// the given strings and modules.
+    pub fn required_size<'a>(
+        memory_map_capacity: usize,
+        strings: impl Iterator<Item = &'a str>,
+        module_count: usize,
+    ) -> usize {
+        // NOTE: Each item might need up to eight bytes of padding to be
+        // properly aligned.
+        let strings_size: usize = strings.map(|string| string.len() + 8).sum();
+
+        size_of::<BootInfo>()
+            + (memory_map_capacity * size_of::<MemoryRegion>())
+            + 8
+            + strings_size
+            + (module_count * size_of::<Module>())
+            + 8
+    }
+
+    /// Constructs an area from a block of memory, placing an empty
+    /// `BootInfo` at its start, followed by space for a memory map
+    /// with the given capacity.
+    ///
+    /// # Safety
+    /// The block must be owned by the caller, must be identity mapped
+    /// in the current address space, and must be mapped at
+    /// `physical_memory_offset` in the kernel's address space.
+    pub unsafe fn new(
+        physical_start: u64,
+        size: usize,
+        physical_memory_offset: u64,
+        memory_map_capacity: usize,
+    ) -> Self {
+        let mut area = Self {
+            physical_start,
+            size,
+            used: 0,
+            physical_memory_offset,
+            memory_map: core::ptr::null_mut(),
+            memory_map_capacity,
+        };
+
+        let info = area.reserve::<BootInfo>(1);
+        info.write(BootInfo::new());
+
+        area.memory_map = area.reserve::<MemoryRegion>(memory_map_capacity);
+        core::ptr::write_bytes(area.memory_map, 0, memory_map_capacity);
+
+        area
+    }
+
+    /// Gets the linear address of the boot information in the kernel's
+    /// address space.
+    pub fn linear_address(&self) -> u64 {
+        self.physical_start + self.physical_memory_offset
+    }
+
+    /// Gets the boot information for modification.
+    pub fn info_mut(&mut self) -> &mut BootInfo {
+        // NOTE: This is safe because the boot information was written
+        // at the start of the area on construction.
+        unsafe { &mut *(self.physical_start as *mut BootInfo) }
+    }
+
+    /// Gets the space reserved for the memory map, so that it can be
+    /// filled in once boot services have been exited.
+    pub fn memory_map_mut(&mut self) -> &mut [MemoryRegion] {
+        // NOTE: This is safe because the space was reserved and zeroed
+        // on construction.
+        unsafe { core::slice::from_raw_parts_mut(self.memory_map, self.memory_map_capacity) }
+    }
+
+    /// Records how many entries at the start of the memory map space
+    /// are in use.
+    pub fn set_memory_map_len(&mut self, len: usize) {
+        assert!(len <= self.memory_map_capacity);
+
+        let memory_map = BootSlice::from_raw_parts(self.to_linear(self.memory_map), len as u64);
+        self.info_mut().memory_map = memory_map;
+    }
+
+    /// Copies a string into the area.
+    pub fn push_str(&mut self, string: &str) -> BootStr {
+        let dest = self.reserve::<u8>(string.len());
+
+        // NOTE: This is safe because reserve gave us exclusive access to
+        // enough space.
+        unsafe {
+            core::ptr::copy_nonoverlapping(string.as_ptr(), dest, string.len());
+        }
+
+        BootStr::from_raw_parts(self.to_linear(dest), string.len() as u64)
+    }
+
+    /// Moves the items produced by the iterator into the area.
+    pub fn push_iter<T>(&mut self, items: impl ExactSizeIterator<Item = T>) -> BootSlice<T> {
+        let count = items.len();
+        let dest = self.reserve::<T>(count);
+
+        for (index, item) in items.take(count).enumerate() {
+            // NOTE: This is safe because reserve gave us exclusive
+            // access to enough space.
+            unsafe {
+                dest.add(index).write(item);
+            }
+        }
+
+        BootSlice::from_raw_parts(self.to_linear(dest), count as u64)
+    }
+
+    fn reserve<T>(&mut self, count: usize) -> *mut T {
+        let align = align_of::<T>();
+        let start = (self.used + align - 1) & !(align - 1);
+        let end = start + (count * size_of::<T>());
+
+        assert!(end <= self.size, "boot information area exhausted");
+
+        self.used = end;
+
+        (self.physical_start as usize + start) as *mut T
+    }
+
+    fn to_linear<T>(&self, identity_mapped: *mut T) -> u64 {
+        identity_mapped as u64 + self.physical_memory_offset
+    }
+}
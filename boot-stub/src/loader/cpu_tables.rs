@@ -0,0 +1,129 @@
+//! Provides the descriptor tables the kernel is started with.
+//!
+//! OVMF's GDT lives in boot services memory, which the kernel is free
+//! to reuse, so the boot stub builds a table of its own and switches to
+//! it once boot services have been exited, along with a TSS holding the
+//! stacks for interrupts. The tables get a page to themselves, so that
+//! they can be mapped into the kernel's address space without dragging
+//! anything else along.
+
+use alloc::boxed::Box;
+use osc_os_boot_info::CpuTablesInfo;
+
+use crate::arch::x86_64::gdt::{
+    load_task_register, reload_segments, Descriptor, GlobalDescriptorTable, TaskStateSegment,
+};
+use crate::arch::x86_64::paging::{LinearAddress, SegmentSelector};
+
+/// The selectors for the segments in the GDT.
+///
+/// The code and data segments are in the order `syscall` and `sysret`
+/// expect: kernel code, kernel data, user data, then user code.
+#[derive(Debug, Copy, Clone)]
+pub struct Selectors {
+    pub kernel_code: SegmentSelector,
+    pub kernel_data: SegmentSelector,
+    pub user_data: SegmentSelector,
+    pub user_code: SegmentSelector,
+    pub tss: SegmentSelector,
+}
+
+#[repr(C, align(4096))]
+pub struct CpuTables {
+    gdt: GlobalDescriptorTable,
+    tss: TaskStateSegment,
+    selectors: Selectors,
+}
+
+impl CpuTables {
+    /// Builds the tables, which live for the rest of the boot stub's
+    /// life (and beyond).
+    pub fn new() -> &'static mut Self {
+        let mut gdt = GlobalDescriptorTable::new();
+
+        let kernel_code = gdt.append(Descriptor::kernel_code_segment());
+        let kernel_data = gdt.append(Descriptor::kernel_data_segment());
+        let user_data = gdt.append(Descriptor::user_data_segment());
+        let user_code = gdt.append(Descriptor::user_code_segment());
+
+        let tables = Box::leak(Box::new(Self {
+            gdt,
+            tss: TaskStateSegment::new(),
+            selectors: Selectors {
+                kernel_code,
+                kernel_data,
+                user_data,
+                user_code,
+                tss: SegmentSelector::from_raw(0),
+            },
+        }));
+
+        // NOTE: This is safe because the tables have just been leaked,
+        // so the TSS stays where it is forever.
+        tables.selectors.tss = tables
+            .gdt
+            .append(unsafe { Descriptor::tss_segment(&tables.tss) });
+
+        tables
+    }
+
+    /// Gets the linear address of the tables, which is also their
+    /// physical address while UEFI's page tables are active.
+    pub fn address(&self) -> u64 {
+        self as *const Self as u64
+    }
+
+    /// Gets the number of bytes the tables take up.
+    pub fn size(&self) -> u64 {
+        core::mem::size_of::<Self>() as u64
+    }
+
+    /// Gets the selectors for the segments in the GDT.
+    pub fn selectors(&self) -> Selectors {
+        self.selectors
+    }
+
+    /// Describes the tables for the kernel.
+    pub fn info(&self) -> CpuTablesInfo {
+        let mut interrupt_stack_tops = [0; 7];
+
+        for (index, top) in (1..=7).zip(interrupt_stack_tops.iter_mut()) {
+            *top = self.tss.interrupt_stack(index);
+        }
+
+        CpuTablesInfo {
+            gdt_address: self.gdt.address(),
+            gdt_size: self.gdt.size(),
+            tss_address: &self.tss as *const TaskStateSegment as u64,
+            kernel_code_selector: self.selectors.kernel_code.to_raw(),
+            kernel_data_selector: self.selectors.kernel_data.to_raw(),
+            user_data_selector: self.selectors.user_data.to_raw(),
+            user_code_selector: self.selectors.user_code.to_raw(),
+            tss_selector: self.selectors.tss.to_raw(),
+            interrupt_stack_tops,
+        }
+    }
+
+    /// Sets the stack switched to when an interrupt arrives while
+    /// running in user mode.
+    pub fn set_kernel_stack(&mut self, top: LinearAddress) {
+        self.tss.set_privilege_stack(0, top);
+    }
+
+    /// Sets the stack for handlers with the given IST index (1 to 7).
+    pub fn set_interrupt_stack(&mut self, index: u8, top: LinearAddress) {
+        self.tss.set_interrupt_stack(index, top);
+    }
+
+    /// Loads the GDT, reloads every segment register from it, and loads
+    /// the TSS.
+    ///
+    /// # Safety
+    /// This must only be done once boot services have been exited,
+    /// since the firmware expects its own segments.
+    pub unsafe fn load(&'static self) {
+        self.gdt.load();
+        reload_segments(self.selectors.kernel_code, self.selectors.kernel_data);
+        load_task_register(self.selectors.tss);
+    }
+}
@@ -0,0 +1,37 @@
+//! Provides conversion from the UEFI memory map into the
+//! firmware-independent regions handed to the kernel, see
+//! `osc_os_boot_info::convert_memory_map` for the conversion itself.
+
+use osc_os_boot_info::{MemoryRegion, UefiMemoryDescriptor, UefiMemoryType};
+use uefi::table::boot::{MemoryDescriptor, MemoryType};
+
+pub use osc_os_boot_info::MemoryMapTooLarge;
+
+/// The memory type used for pages holding the kernel's segments.
+pub const KERNEL_IMAGE_MEMORY_TYPE: MemoryType = MemoryType(UefiMemoryType::KERNEL_IMAGE.0);
+
+/// The memory type used for pages holding page tables built for the
+/// kernel.
+pub const PAGE_TABLES_MEMORY_TYPE: MemoryType = MemoryType(UefiMemoryType::PAGE_TABLES.0);
+
+/// The memory type used for pages holding modules.
+pub const MODULES_MEMORY_TYPE: MemoryType = MemoryType(UefiMemoryType::MODULES.0);
+
+/// The memory type used for pages holding stacks built for the kernel.
+pub const KERNEL_STACKS_MEMORY_TYPE: MemoryType = MemoryType(UefiMemoryType::KERNEL_STACKS.0);
+
+/// Converts UEFI memory descriptors into memory regions, writing them
+/// sorted by address with adjacent regions of the same kind merged.
+/// Returns the number of regions written.
+pub fn convert_memory_map<'a>(
+    descriptors: impl Iterator<Item = &'a MemoryDescriptor>,
+    regions: &mut [MemoryRegion],
+) -> Result<usize, MemoryMapTooLarge> {
+    let descriptors = descriptors.map(|descriptor| UefiMemoryDescriptor {
+        memory_type: UefiMemoryType(descriptor.ty.0),
+        physical_start: descriptor.phys_start,
+        page_count: descriptor.page_count,
+    });
+
+    osc_os_boot_info::convert_memory_map(descriptors, regions)
+}
@@ -1,14 +1,74 @@
+use alloc::boxed::Box;
 use alloc::format;
+use alloc::string::{String, ToString};
 use alloc::vec;
 use alloc::vec::Vec;
 
+use core::fmt::Write;
+use core::mem::size_of;
+
 use uefi::prelude::*;
+use uefi::proto::console::gop::{GraphicsOutput, PixelFormat as GopPixelFormat};
 use uefi::proto::loaded_image::*;
 use uefi::proto::media::file::*;
 use uefi::proto::media::fs::*;
+use uefi::table::boot::{AllocateType, MemoryDescriptor, MemoryType};
+use uefi::table::cfg::{ACPI2_GUID, ACPI_GUID};
 use uefi::CStr16;
 
+use osc_os_boot_info::{AddressSpaceLayout, FramebufferInfo, Module, PixelFormat};
+use osc_os_elf::{ElfError, ElfFile, ProgramHeader, SegmentFlags};
+
+use crate::arch::x86_64::gdt::{DOUBLE_FAULT_IST_INDEX, MACHINE_CHECK_IST_INDEX, NMI_IST_INDEX};
+use crate::arch::x86_64::interrupts::{
+    install_default_handlers, IDTRValue, InterruptDescriptorTable,
+};
+use crate::arch::x86_64::paging::{
+    dump_page_tables, IdentityMapping, LinearAddress, LinearAddressRange, PageTableEntryFlags,
+    PagingMode, PhysicalAddress, RegionManager,
+};
+use crate::arch::x86_64::pic::{ChainedPics, DEFAULT_PRIMARY_OFFSET, DEFAULT_SECONDARY_OFFSET};
+use crate::arch::x86_64::registers::{CR0Flags, CR0Value, EFERFlags, EFERValue};
+use crate::arch::x86_64::serial::{SerialPort, SerialPortDescriptor};
+use crate::arch::x86_64::trampoline;
+use crate::memory::heap::{self, HeapKind};
+use crate::memory::stack::Stack;
+
+mod address_space;
+use address_space::*;
+
+mod boot_info;
+use boot_info::*;
+
+mod cpu_tables;
+use cpu_tables::*;
+
+mod memory_map;
+use memory_map::*;
+
 const KERNEL_LOCATION: &'static str = "OSCOS\\KERNEL.BIN";
+const COMMAND_LINE_LOCATION: &'static str = "OSCOS\\CMDLINE.TXT";
+const MODULE_LIST_LOCATION: &'static str = "OSCOS\\MODULES.TXT";
+const PAGE_SIZE: u64 = 4096;
+const PAGE_MASK: u64 = PAGE_SIZE - 1;
+const KERNEL_STACK_PAGES: u64 = 16;
+const INTERRUPT_STACK_PAGES: u64 = 4;
+
+/// The stacks the kernel's IDT is expected to use for the exceptions
+/// that have to be handled whatever state the current stack is in. They
+/// are handed over in `CpuTablesInfo::interrupt_stack_tops`, and the
+/// kernel points its gates at them with `IDTEntry::set_ist_index`.
+const INTERRUPT_STACKS: [(u8, &str); 3] = [
+    (DOUBLE_FAULT_IST_INDEX, "double fault stack"),
+    (NMI_IST_INDEX, "NMI stack"),
+    (MACHINE_CHECK_IST_INDEX, "machine check stack"),
+];
+
+/// The kind of heap the allocator switches to at handoff. The slab
+/// caches check for corruption in debug builds.
+const HEAP_KIND: HeapKind = HeapKind::Slab {
+    debug: cfg!(debug_assertions),
+};
 
 enum BootError {
     RetrieveImageInfoFailed(Status),
@@ -17,16 +77,86 @@ enum BootError {
     OpenKernelFailed(Status),
     StatKernelFailed(Status),
     ReadKernelFailed(Status),
+    InvalidKernel(ElfError),
+    AllocateKernelSegmentFailed(u64, Status),
+    AllocateHeapFailed(Status),
+    ReadCommandLineFailed(Status),
+    ReadModuleListFailed(Status),
+    ReadModuleFailed(String, Status),
+    AllocateModuleFailed(String, Status),
+    AllocateBootInfoFailed(Status),
+    RetrieveMemoryMapFailed(Status),
+    BuildAddressSpaceFailed(AddressSpaceError),
+}
+
+/// The ways reading a whole file can fail.
+enum FileError {
+    Open(Status),
+    Stat(Status),
+    Read(Status),
+}
+
+impl FileError {
+    fn status(&self) -> Status {
+        match self {
+            Self::Open(status) | Self::Stat(status) | Self::Read(status) => *status,
+        }
+    }
+}
+
+/// A segment of the kernel that has been copied to its
+/// physical location.
+struct LoadedSegment {
+    virtual_address: u64,
+    physical_address: u64,
+    memory_size: u64,
+    flags: SegmentFlags,
+}
+
+/// The kernel image once its segments have been loaded.
+struct KernelImage {
+    entry_point: u64,
+    segments: Vec<LoadedSegment>,
+}
+
+/// A module that has been copied into its own pages.
+struct LoadedModule {
+    name: String,
+    physical_address: u64,
+    size: u64,
 }
 
 struct PreparedKernel {
-    loaded_image: Vec<u8>,
+    image: KernelImage,
+
+    /// The linear address of the top of the kernel's stack, in the
+    /// kernel's address space.
+    stack_top: u64,
+
+    boot_info: BootInfoArea,
+    memory_map_buffer: Vec<u8>,
+    page_table_root: PhysicalAddress,
+
+    /// What lives where in the kernel's address space.
+    regions: RegionManager,
+
+    /// The descriptor tables switched to once boot services have been
+    /// exited.
+    cpu_tables: &'static CpuTables,
+
+    /// The memory the allocator switches to once boot services have
+    /// been exited.
+    heap: LinearAddressRange,
 }
 
 pub struct Prepare;
 
 pub struct Ready {
     kernel: PreparedKernel,
+
+    /// The boot stub's IDT, whose handlers have to be moved to our code
+    /// segment once the kernel's GDT is loaded.
+    idt: &'static mut InterruptDescriptorTable,
 }
 
 pub struct Loader<Phase> {
@@ -37,22 +167,125 @@ pub struct Loader<Phase> {
 
 impl Loader<Ready> {
     fn transfer_to_kernel(self) -> ! {
-        // Get the estimated map size
-        let map_size = self.system_table.boot_services().memory_map_size();
-        let _map_dest = vec![0u8; map_size << 2];
+        // NOTE: Once boot services have been exited, the console is no
+        // longer available, so we report progress over the serial port.
+        let mut com1 = unsafe { SerialPort::new(SerialPortDescriptor::StandardCom1) };
 
-        let kernel_str =
-            unsafe { core::str::from_utf8_unchecked(self.phase_data.kernel.loaded_image.as_ref()) };
+        let mut kernel = self.phase_data.kernel;
 
-        print_string(
-            &self.system_table,
-            format!(
-                "Would run kernel with mem map of size {}: {}",
-                map_size, kernel_str
-            ),
-        );
+        // NOTE: uefi-rs fetches the final memory map, and if the map key
+        // has gone stale by the time ExitBootServices is called, fetches
+        // it again and retries.
+        let (_runtime_table, memory_map) = match self
+            .system_table
+            .exit_boot_services(self.image_handle, &mut kernel.memory_map_buffer)
+            .warning_as_error()
+        {
+            Ok(result) => result,
 
-        loop {}
+            Err(err) => {
+                let Status(status_code) = err.status();
+
+                writeln!(com1, "Failed to exit boot services ({:#x})", status_code).unwrap();
+
+                loop {}
+            }
+        };
+
+        // The allocator sat atop boot services, which are now gone, so
+        // move it onto the heap reserved for this
+        unsafe {
+            heap::switch_to_heap(kernel.heap, HEAP_KIND);
+        }
+
+        // NOTE: This is safe because boot services have been exited, so
+        // the firmware no longer cares which segments are in use.
+        unsafe {
+            kernel.cpu_tables.load();
+        }
+
+        // NOTE: The IDT's handlers still refer to the firmware's code
+        // segment, which may lie beyond the end of the new GDT, so an
+        // exception before the trampoline switches address spaces would
+        // otherwise triple fault. Neither the table nor the handlers are
+        // mapped in the kernel's address space though, so this doesn't
+        // help once the kernel is entered. This is safe because the
+        // table is leaked, so it never moves or goes away.
+        //
+        // The interrupt stacks are left alone though, since they're only
+        // mapped in the kernel's address space, so they're described in
+        // the boot information for the kernel's own IDT instead.
+        let idt = self.phase_data.idt;
+        idt.set_code_segment(kernel.cpu_tables.selectors().kernel_code);
+
+        unsafe {
+            idt.load_unchecked();
+        }
+
+        // The kernel is started with the PICs remapped away from the
+        // exception vectors, and every IRQ masked, so that it can choose
+        // between them and the APIC without being interrupted.
+        unsafe {
+            ChainedPics::new(DEFAULT_PRIMARY_OFFSET, DEFAULT_SECONDARY_OFFSET).disable();
+        }
+
+        let descriptor_count = memory_map.len();
+
+        let region_count = match convert_memory_map(memory_map, kernel.boot_info.memory_map_mut()) {
+            Ok(region_count) => region_count,
+
+            Err(MemoryMapTooLarge) => {
+                writeln!(com1, "The memory map is too large to hand to the kernel").unwrap();
+
+                loop {}
+            }
+        };
+
+        kernel.boot_info.set_memory_map_len(region_count);
+
+        writeln!(
+            com1,
+            "Exited boot services with {} memory map entries ({} regions), entering kernel at {:#x}",
+            descriptor_count,
+            region_count,
+            kernel.image.entry_point
+        )
+        .unwrap();
+
+        writeln!(com1, "Kernel page tables:").unwrap();
+
+        // NOTE: This is safe because the kernel's page tables were
+        // allocated from UEFI, so they're still identity mapped.
+        unsafe {
+            dump_page_tables(
+                kernel.page_table_root,
+                PagingMode::current(),
+                &IdentityMapping,
+                Some(&kernel.regions),
+                &mut com1,
+            )
+        }
+        .unwrap();
+
+        // NOTE: The kernel's page tables use the no-execute bit, which is
+        // reserved unless enabled, and mark pages read-only, which only
+        // applies to the kernel when write protection is enabled.
+        unsafe {
+            let efer = EFERValue::read();
+            efer.write_flags(efer.flags() | EFERFlags::NO_EXECUTE_ENABLE);
+
+            let cr0 = CR0Value::read();
+            cr0.write_flags(cr0.flags() | CR0Flags::WRITE_PROTECT);
+        }
+
+        unsafe {
+            trampoline::enter_kernel(
+                kernel.page_table_root,
+                kernel.stack_top,
+                kernel.image.entry_point,
+                kernel.boot_info.linear_address(),
+            )
+        }
     }
 }
 
@@ -68,12 +301,16 @@ impl Loader<Prepare> {
     pub fn run(self) -> ! {
         self.print_string("UEFI boot stub entered.\r\n");
 
-        // Make uefi-rs's built-in allocator atop UEFI allocation
-        // available for us to use
+        // Make allocation atop UEFI allocation available for us to use,
+        // until we switch to our own heap at handoff
         unsafe {
-            uefi::alloc::init(self.system_table.boot_services());
+            heap::init(self.system_table.boot_services());
         }
 
+        // Report faults in the boot stub ourselves, rather than leaving
+        // them to the firmware
+        let idt = install_exception_handlers();
+
         match self.prepare() {
             Ok(kernel) => {
                 self.print_string("Preparation succeeded, transferring to kernel.\r\n");
@@ -81,7 +318,7 @@ impl Loader<Prepare> {
                 let ready = Loader {
                     image_handle: self.image_handle,
                     system_table: self.system_table,
-                    phase_data: Ready { kernel },
+                    phase_data: Ready { kernel, idt },
                 };
 
                 ready.transfer_to_kernel();
@@ -123,6 +360,66 @@ impl Loader<Prepare> {
                         "Failed to read the kernel file into memory ({:#x})\r\n",
                         status_code
                     )),
+
+                    BootError::InvalidKernel(elf_error) => self.print_string(format!(
+                        "The kernel file is not a usable ELF64 image ({:?})\r\n",
+                        elf_error
+                    )),
+
+                    BootError::AllocateKernelSegmentFailed(address, Status(status_code)) => self
+                        .print_string(format!(
+                            "Failed to allocate memory for the kernel segment at {:#x} ({:#x})\r\n",
+                            address, status_code
+                        )),
+
+                    BootError::AllocateHeapFailed(Status(status_code)) => {
+                        self.print_string(format!(
+                            "Failed to allocate memory for the post-boot heap ({:#x})\r\n",
+                            status_code
+                        ))
+                    }
+
+                    BootError::ReadCommandLineFailed(Status(status_code)) => {
+                        self.print_string(format!(
+                            "Failed to read the kernel command line ({:#x})\r\n",
+                            status_code
+                        ))
+                    }
+
+                    BootError::ReadModuleListFailed(Status(status_code)) => self.print_string(
+                        format!("Failed to read the module list ({:#x})\r\n", status_code),
+                    ),
+
+                    BootError::ReadModuleFailed(name, Status(status_code)) => {
+                        self.print_string(format!(
+                            "Failed to read the module {} ({:#x})\r\n",
+                            name, status_code
+                        ))
+                    }
+
+                    BootError::AllocateModuleFailed(name, Status(status_code)) => self
+                        .print_string(format!(
+                            "Failed to allocate memory for the module {} ({:#x})\r\n",
+                            name, status_code
+                        )),
+
+                    BootError::AllocateBootInfoFailed(Status(status_code)) => {
+                        self.print_string(format!(
+                            "Failed to allocate memory for the boot information ({:#x})\r\n",
+                            status_code
+                        ))
+                    }
+
+                    BootError::RetrieveMemoryMapFailed(Status(status_code)) => self.print_string(
+                        format!("Failed to get the memory map ({:#x})\r\n", status_code),
+                    ),
+
+                    BootError::BuildAddressSpaceFailed(address_space_error) => {
+                        self.print_string(format!(
+                            "Failed to build the kernel's address space ({:?})\r\n",
+                            address_space_error
+                        ))
+                    }
                 }
 
                 self.exit();
@@ -131,6 +428,67 @@ impl Loader<Prepare> {
     }
 
     fn prepare(&self) -> Result<PreparedKernel, BootError> {
+        let mut volume = self.open_boot_volume()?;
+
+        let file_data = read_file(&mut volume, KERNEL_LOCATION).map_err(|err| match err {
+            FileError::Open(status) => BootError::OpenKernelFailed(status),
+            FileError::Stat(status) => BootError::StatKernelFailed(status),
+            FileError::Read(status) => BootError::ReadKernelFailed(status),
+        })?;
+
+        let image = self.load_kernel(&file_data)?;
+        let command_line = read_command_line(&mut volume)?;
+        let modules = self.load_modules(&mut volume)?;
+        let heap = self.allocate_heap()?;
+
+        // NOTE: Allocating the buffer can itself grow the memory map, as
+        // can anything else the firmware does before we exit, so leave
+        // plenty of room.
+        let map_size = self.system_table.boot_services().memory_map_size();
+        let mut memory_map_buffer = vec![0u8; map_size << 2];
+
+        // NOTE: Firmware descriptors are never smaller than ours, so the
+        // buffer can't hold more descriptors than this, and converting
+        // never produces more regions than there are descriptors.
+        let memory_map_capacity = memory_map_buffer.len() / size_of::<MemoryDescriptor>();
+
+        let mut boot_info =
+            self.build_boot_info(&image, &command_line, &modules, memory_map_capacity)?;
+
+        let cpu_tables = CpuTables::new();
+
+        let (regions, stack) = self.build_address_space(
+            &image,
+            cpu_tables,
+            heap,
+            &mut memory_map_buffer,
+            &boot_info.info_mut().framebuffer,
+        )?;
+
+        let page_table_root = regions.root();
+        let stack_top = stack.top().to_raw();
+
+        let layout = &mut boot_info.info_mut().layout;
+        layout.page_table_root = page_table_root.to_raw();
+        layout.stack_top = stack_top;
+        layout.stack_size = stack.size();
+        layout.stack_guard_size = stack.guard().len();
+
+        boot_info.info_mut().cpu_tables = cpu_tables.info();
+
+        Ok(PreparedKernel {
+            image,
+            stack_top,
+            boot_info,
+            memory_map_buffer,
+            page_table_root,
+            regions,
+            cpu_tables,
+            heap,
+        })
+    }
+
+    fn open_boot_volume(&self) -> Result<Directory, BootError> {
         let image_info_cell = self
             .system_table
             .boot_services()
@@ -149,37 +507,388 @@ impl Loader<Prepare> {
 
         let sfs = unsafe { &mut *sfs_cell.get() };
 
-        let mut volume = sfs
-            .open_volume()
+        sfs.open_volume()
             .warning_as_error()
-            .map_err(|err| BootError::RetrieveVolumeFailed(err.status()))?;
+            .map_err(|err| BootError::RetrieveVolumeFailed(err.status()))
+    }
+
+    /// Copies each loadable segment of the kernel image to the physical
+    /// address it requests, zero-filling any part of the segment that
+    /// isn't backed by the file (i.e. the BSS).
+    fn load_kernel(&self, file_data: &[u8]) -> Result<KernelImage, BootError> {
+        let elf = ElfFile::parse(file_data).map_err(BootError::InvalidKernel)?;
+
+        // NOTE: Position-independent kernels are loaded at the addresses
+        // in their program headers, just like executables, so any that
+        // would need relocating are rejected.
+        if let Some(index) = elf.segment_requiring_relocation() {
+            return Err(BootError::InvalidKernel(ElfError::RelocationRequired(
+                index,
+            )));
+        }
+
+        let mut program_headers: Vec<(usize, ProgramHeader)> = elf.loadable_segments().collect();
+        program_headers.sort_unstable_by_key(|(_, ph)| ph.physical_address());
+
+        let mut segments = Vec::with_capacity(program_headers.len());
+
+        // NOTE: Segments aren't required to be page aligned, so adjacent
+        // segments may share a page, we track how far we've allocated so
+        // that we don't try to allocate the same page twice.
+        let mut allocated_until = 0u64;
+
+        for (index, ph) in program_headers {
+            if ph.memory_size() == 0 {
+                continue;
+            }
+
+            let start = ph.physical_address() & !PAGE_MASK;
+            let end = (ph.physical_address() + ph.memory_size())
+                .checked_add(PAGE_MASK)
+                .ok_or(BootError::InvalidKernel(ElfError::SegmentAddressOverflow(
+                    index,
+                )))?
+                & !PAGE_MASK;
+            let first_unallocated = start.max(allocated_until);
+
+            if first_unallocated < end {
+                let page_count = ((end - first_unallocated) / PAGE_SIZE) as usize;
+
+                self.system_table
+                    .boot_services()
+                    .allocate_pages(
+                        AllocateType::Address(first_unallocated as usize),
+                        KERNEL_IMAGE_MEMORY_TYPE,
+                        page_count,
+                    )
+                    .warning_as_error()
+                    .map_err(|err| {
+                        BootError::AllocateKernelSegmentFailed(ph.physical_address(), err.status())
+                    })?;
+
+                allocated_until = end;
+            }
 
-        let file = volume
-            .open(KERNEL_LOCATION, FileMode::Read, FileAttribute::empty())
+            let data = elf.segment_data(&ph);
+            let bss_size = (ph.memory_size() - ph.file_size()) as usize;
+
+            // NOTE: This is safe because we now own the pages covering
+            // the segment, and UEFI identity maps physical memory.
+            unsafe {
+                let dest = ph.physical_address() as *mut u8;
+                core::ptr::copy_nonoverlapping(data.as_ptr(), dest, data.len());
+                core::ptr::write_bytes(dest.add(data.len()), 0, bss_size);
+            }
+
+            segments.push(LoadedSegment {
+                virtual_address: ph.virtual_address(),
+                physical_address: ph.physical_address(),
+                memory_size: ph.memory_size(),
+                flags: ph.flags(),
+            });
+        }
+
+        Ok(KernelImage {
+            entry_point: elf.entry_point(),
+            segments,
+        })
+    }
+
+    /// Allocates the pages the allocator switches to once boot services
+    /// have been exited.
+    fn allocate_heap(&self) -> Result<LinearAddressRange, BootError> {
+        let heap_start = self
+            .system_table
+            .boot_services()
+            .allocate_pages(
+                AllocateType::AnyPages,
+                MemoryType::LOADER_DATA,
+                heap::HEAP_PAGES,
+            )
             .warning_as_error()
-            .map_err(|err| BootError::OpenKernelFailed(err.status()))?;
+            .map_err(|err| BootError::AllocateHeapFailed(err.status()))?;
+
+        // NOTE: This is safe because UEFI identity maps physical memory,
+        // and the pages came from it.
+        let heap_start = unsafe { LinearAddress::from_raw_unchecked(heap_start) };
+
+        Ok(LinearAddressRange::from_start_and_len(
+            heap_start,
+            heap::HEAP_PAGES as u64 * PAGE_SIZE,
+        ))
+    }
+
+    /// Loads each of the modules named in the module list into its own
+    /// pages. A missing module list just means there are no modules.
+    fn load_modules(&self, volume: &mut Directory) -> Result<Vec<LoadedModule>, BootError> {
+        let list = match read_optional_file(volume, MODULE_LIST_LOCATION) {
+            Ok(Some(data)) => String::from_utf8_lossy(&data).into_owned(),
+            Ok(None) => return Ok(Vec::new()),
+            Err(err) => return Err(BootError::ReadModuleListFailed(err.status())),
+        };
 
-        let mut file = unsafe { RegularFile::new(file) };
+        let mut modules = Vec::new();
 
-        let mut info_buffer = vec![0u8; 4096];
+        for name in list.lines().map(str::trim) {
+            if name.is_empty() || name.starts_with('#') {
+                continue;
+            }
+
+            let data = read_file(volume, name)
+                .map_err(|err| BootError::ReadModuleFailed(name.to_string(), err.status()))?;
+
+            let page_count = ((data.len() as u64 + PAGE_MASK) / PAGE_SIZE).max(1) as usize;
+
+            let physical_address = self
+                .system_table
+                .boot_services()
+                .allocate_pages(AllocateType::AnyPages, MODULES_MEMORY_TYPE, page_count)
+                .warning_as_error()
+                .map_err(|err| BootError::AllocateModuleFailed(name.to_string(), err.status()))?;
+
+            // NOTE: This is safe because we now own the pages, and UEFI
+            // identity maps physical memory.
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    data.as_ptr(),
+                    physical_address as *mut u8,
+                    data.len(),
+                );
+            }
 
-        let file_info = file
-            .get_info::<FileInfo>(info_buffer.as_mut())
+            modules.push(LoadedModule {
+                name: name.to_string(),
+                physical_address,
+                size: data.len() as u64,
+            });
+        }
+
+        Ok(modules)
+    }
+
+    /// Gathers up everything the kernel needs to know into a block of
+    /// memory that will survive the exit from boot services.
+    fn build_boot_info(
+        &self,
+        image: &KernelImage,
+        command_line: &str,
+        modules: &[LoadedModule],
+        memory_map_capacity: usize,
+    ) -> Result<BootInfoArea, BootError> {
+        let strings = core::iter::once(command_line).chain(modules.iter().map(|m| m.name.as_str()));
+        let size = BootInfoArea::required_size(memory_map_capacity, strings, modules.len());
+        let page_count = ((size as u64 + PAGE_MASK) / PAGE_SIZE) as usize;
+
+        let physical_start = self
+            .system_table
+            .boot_services()
+            .allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, page_count)
             .warning_as_error()
-            .map_err(|err| BootError::StatKernelFailed(err.status()))?;
+            .map_err(|err| BootError::AllocateBootInfoFailed(err.status()))?;
+
+        // NOTE: This is safe because we now own the pages, and all of
+        // physical memory is mapped into the kernel's address space.
+        let mut area = unsafe {
+            BootInfoArea::new(
+                physical_start,
+                page_count * PAGE_SIZE as usize,
+                PHYSICAL_MEMORY_OFFSET,
+                memory_map_capacity,
+            )
+        };
 
-        let mut data = {
-            let file_size = file_info.file_size();
-            vec![0u8; file_size as usize]
+        let command_line = area.push_str(command_line);
+
+        let module_names: Vec<_> = modules.iter().map(|m| area.push_str(&m.name)).collect();
+
+        let modules =
+            area.push_iter(
+                modules
+                    .iter()
+                    .zip(module_names)
+                    .map(|(module, name)| Module {
+                        name,
+                        physical_address: module.physical_address,
+                        size: module.size,
+                    }),
+            );
+
+        let kernel_physical_start = image.segments.iter().map(|s| s.physical_address).min();
+        let kernel_virtual_start = image.segments.iter().map(|s| s.virtual_address).min();
+        let kernel_virtual_end = image
+            .segments
+            .iter()
+            .map(|s| s.virtual_address + s.memory_size)
+            .max();
+
+        let info = area.info_mut();
+
+        info.framebuffer = self.framebuffer_info();
+        info.rsdp_address = self.rsdp_address();
+        info.command_line = command_line;
+        info.modules = modules;
+
+        info.layout = AddressSpaceLayout {
+            kernel_physical_start: kernel_physical_start.unwrap_or(0),
+            kernel_virtual_start: kernel_virtual_start.unwrap_or(0),
+            kernel_size: kernel_virtual_end.unwrap_or(0) - kernel_virtual_start.unwrap_or(0),
+            physical_memory_offset: PHYSICAL_MEMORY_OFFSET,
+            ..AddressSpaceLayout::empty()
         };
 
-        file.read(&mut data)
+        Ok(area)
+    }
+
+    /// Builds the page tables the kernel is started with, returning
+    /// the regions that were mapped (along with the root table), and
+    /// the stack the kernel is started on. The stacks for interrupts
+    /// are put in the TSS.
+    fn build_address_space(
+        &self,
+        image: &KernelImage,
+        cpu_tables: &mut CpuTables,
+        heap: LinearAddressRange,
+        memory_map_buffer: &mut [u8],
+        framebuffer: &FramebufferInfo,
+    ) -> Result<(RegionManager, Stack), BootError> {
+        let boot_services = self.system_table.boot_services();
+
+        let (_map_key, descriptors) = boot_services
+            .memory_map(memory_map_buffer)
+            .warning_as_error()
+            .map_err(|err| BootError::RetrieveMemoryMapFailed(err.status()))?;
+
+        let memory_end = descriptors
+            .map(|descriptor| descriptor.phys_start + (descriptor.page_count * PAGE_SIZE))
+            .max()
+            .unwrap_or(0);
+
+        let physical_end = memory_end
+            .max(framebuffer.address + framebuffer.size)
+            .max(MINIMUM_PHYSICAL_MEMORY_END);
+
+        let mut builder =
+            AddressSpaceBuilder::new(boot_services).map_err(BootError::BuildAddressSpaceFailed)?;
+
+        builder
+            .map_kernel_segments(&image.segments)
+            .map_err(BootError::BuildAddressSpaceFailed)?;
+
+        builder
+            .map_physical_memory(physical_end)
+            .map_err(BootError::BuildAddressSpaceFailed)?;
+
+        // NOTE: The trampoline is still running when it switches page
+        // tables, so it needs to be at the same address in both.
+        builder
+            .identity_map(
+                "trampoline",
+                trampoline::enter_kernel_address(),
+                PAGE_SIZE,
+                PageTableEntryFlags::empty(),
+            )
+            .map_err(BootError::BuildAddressSpaceFailed)?;
+
+        // NOTE: The descriptor tables are loaded before switching page
+        // tables, so they're mapped at the same address in both.
+        builder
+            .identity_map(
+                "descriptor tables",
+                cpu_tables.address(),
+                cpu_tables.size(),
+                PageTableEntryFlags::WRITABLE | PageTableEntryFlags::NO_EXECUTE,
+            )
+            .map_err(BootError::BuildAddressSpaceFailed)?;
+
+        // NOTE: The allocator switches to the heap before switching page
+        // tables, so it's mapped at the same address in both, which keeps
+        // anything allocated after handoff reachable by the kernel.
+        builder
+            .identity_map(
+                "boot stub heap",
+                heap.start().to_raw(),
+                heap.len(),
+                PageTableEntryFlags::WRITABLE | PageTableEntryFlags::NO_EXECUTE,
+            )
+            .map_err(BootError::BuildAddressSpaceFailed)?;
+
+        let stack = builder
+            .allocate_stack("kernel stack", KERNEL_STACK_PAGES)
+            .map_err(BootError::BuildAddressSpaceFailed)?;
+
+        cpu_tables.set_kernel_stack(stack.top());
+
+        for &(index, name) in INTERRUPT_STACKS.iter() {
+            let interrupt_stack = builder
+                .allocate_stack(name, INTERRUPT_STACK_PAGES)
+                .map_err(BootError::BuildAddressSpaceFailed)?;
+
+            cpu_tables.set_interrupt_stack(index, interrupt_stack.top());
+        }
+
+        Ok((builder.into_regions(), stack))
+    }
+
+    /// Describes the framebuffer of the graphics output protocol, if
+    /// there is one that can be drawn on directly.
+    fn framebuffer_info(&self) -> FramebufferInfo {
+        let gop_cell = match self
+            .system_table
+            .boot_services()
+            .locate_protocol::<GraphicsOutput>()
             .warning_as_error()
-            .map_err(|err| BootError::ReadKernelFailed(err.status()))?;
+        {
+            Ok(gop_cell) => gop_cell,
+            Err(_) => return FramebufferInfo::none(),
+        };
+
+        let gop = unsafe { &mut *gop_cell.get() };
+        let mode_info = gop.current_mode_info();
+        let (width, height) = mode_info.resolution();
+        let mut frame_buffer = gop.frame_buffer();
+
+        let mut info = FramebufferInfo {
+            address: frame_buffer.as_mut_ptr() as u64,
+            size: frame_buffer.size() as u64,
+            width: width as u32,
+            height: height as u32,
+            stride: mode_info.stride() as u32,
+            ..FramebufferInfo::none()
+        };
 
-        let kernel = PreparedKernel { loaded_image: data };
+        match mode_info.pixel_format() {
+            GopPixelFormat::RGB => info.format = PixelFormat::RGB,
+            GopPixelFormat::BGR => info.format = PixelFormat::BGR,
+
+            GopPixelFormat::Bitmask => match mode_info.pixel_bitmask() {
+                Some(bitmask) => {
+                    info.format = PixelFormat::BITMASK;
+                    info.red_mask = bitmask.red;
+                    info.green_mask = bitmask.green;
+                    info.blue_mask = bitmask.blue;
+                    info.reserved_mask = bitmask.reserved;
+                }
+
+                None => return FramebufferInfo::none(),
+            },
 
-        Ok(kernel)
+            GopPixelFormat::BltOnly => return FramebufferInfo::none(),
+        }
+
+        info
+    }
+
+    /// Finds the ACPI RSDP in the firmware's configuration tables,
+    /// preferring the ACPI 2.0 version.
+    fn rsdp_address(&self) -> u64 {
+        let config_table = self.system_table.config_table();
+
+        config_table
+            .iter()
+            .find(|entry| entry.guid == ACPI2_GUID)
+            .or_else(|| config_table.iter().find(|entry| entry.guid == ACPI_GUID))
+            .map(|entry| entry.address as u64)
+            .unwrap_or(0)
     }
 
     fn exit(self) -> ! {
@@ -192,15 +901,87 @@ impl Loader<Prepare> {
     }
 }
 
+/// Reads the whole of the given file into memory.
+fn read_file(volume: &mut Directory, path: &str) -> Result<Vec<u8>, FileError> {
+    let file = volume
+        .open(path, FileMode::Read, FileAttribute::empty())
+        .warning_as_error()
+        .map_err(|err| FileError::Open(err.status()))?;
+
+    let mut file = unsafe { RegularFile::new(file) };
+
+    let mut info_buffer = vec![0u8; 4096];
+
+    let file_info = file
+        .get_info::<FileInfo>(info_buffer.as_mut())
+        .warning_as_error()
+        .map_err(|err| FileError::Stat(err.status()))?;
+
+    let mut data = {
+        let file_size = file_info.file_size();
+        vec![0u8; file_size as usize]
+    };
+
+    file.read(&mut data)
+        .warning_as_error()
+        .map_err(|err| FileError::Read(err.status()))?;
+
+    Ok(data)
+}
+
+/// Reads the whole of the given file into memory, if it exists.
+fn read_optional_file(volume: &mut Directory, path: &str) -> Result<Option<Vec<u8>>, FileError> {
+    match read_file(volume, path) {
+        Ok(data) => Ok(Some(data)),
+        Err(FileError::Open(Status::NOT_FOUND)) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Takes over the exceptions from the firmware, so that faults in the
+/// boot stub are reported over COM1. The firmware's interrupts keep
+/// their handlers. The table is returned so that it can be updated
+/// when the GDT changes.
+fn install_exception_handlers() -> &'static mut InterruptDescriptorTable {
+    let idt = Box::leak(Box::new(InterruptDescriptorTable::new()));
+
+    // NOTE: This is safe because UEFI identity maps physical memory,
+    // including the firmware's table.
+    unsafe { idt.copy_interrupts_from(IDTRValue::read()) };
+
+    install_default_handlers(idt);
+
+    // NOTE: This is safe because the table is leaked, so it never moves
+    // or goes away.
+    unsafe { idt.load_unchecked() };
+
+    idt
+}
+
+/// Reads the kernel's command line, which is empty if there is no
+/// command line file.
+fn read_command_line(volume: &mut Directory) -> Result<String, BootError> {
+    match read_optional_file(volume, COMMAND_LINE_LOCATION) {
+        Ok(Some(data)) => Ok(String::from_utf8_lossy(&data).trim().to_string()),
+        Ok(None) => Ok(String::new()),
+        Err(err) => Err(BootError::ReadCommandLineFailed(err.status())),
+    }
+}
+
 fn print_string(st: &SystemTable<Boot>, string: impl AsRef<str>) {
     let string_bytes = string.as_ref().as_bytes();
-    let mut buf = [0u16; 64];
 
-    for i in 0..string_bytes.len() {
-        buf[i] = string_bytes[i] as u16;
-    }
+    // NOTE: The buffer always keeps its last element as a null
+    // terminator, so longer strings are written in chunks.
+    for chunk in string_bytes.chunks(63) {
+        let mut buf = [0u16; 64];
 
-    st.stdout()
-        .output_string(unsafe { &CStr16::from_u16_with_nul_unchecked(&buf) })
-        .unwrap_success();
+        for i in 0..chunk.len() {
+            buf[i] = chunk[i] as u16;
+        }
+
+        st.stdout()
+            .output_string(unsafe { &CStr16::from_u16_with_nul_unchecked(&buf) })
+            .unwrap_success();
+    }
 }
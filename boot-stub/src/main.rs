@@ -2,8 +2,10 @@
 #![no_main]
 #![feature(alloc_error_handler)]
 #![feature(abi_efiapi)]
+#![feature(abi_x86_interrupt)]
 #![feature(never_type)]
 #![feature(asm)]
+#![feature(global_asm)]
 #![allow(dead_code)]
 extern crate alloc;
 extern crate rlibc;
@@ -13,12 +15,16 @@ use uefi::prelude::*;
 use core::fmt::Write;
 use core::panic::PanicInfo;
 
+mod acpi;
 mod ansi;
 mod arch;
 
 mod loader;
 use loader::*;
 
+mod memory;
+mod sync;
+
 use arch::x86_64::gdt::*;
 use arch::x86_64::interrupts::*;
 use arch::x86_64::paging::*;
@@ -38,21 +44,30 @@ pub extern "efiapi" fn efi_main(image_handle: Handle, system_table: SystemTable<
 
     let cr3_value = arch::x86_64::registers::CR3Value::read();
 
+    let paging_mode = PagingMode::current();
+
     writeln!(
         com1,
-        "PML4 Location according to CR3 (with flags {:#X}): {:?}",
+        "Root page table according to CR3 ({:?}, {:?} supported, with flags {:#X}): {:?}",
+        paging_mode,
+        PagingMode::max_supported(),
         cr3_value.flags_or_pcid(),
-        cr3_value.pml4_address()
+        cr3_value.root_address()
     )
     .unwrap();
 
-    let pt_ptr = cr3_value.pml4_address().to_raw() as *const PageTable;
-    let pt_ref = unsafe { &*pt_ptr };
-
-    for index in 0..16 {
-        let entry = &pt_ref[index];
-        writeln!(com1, "PT entry {} is {:?}", index, entry).unwrap();
+    // NOTE: This is safe because UEFI identity maps physical memory,
+    // including the page tables it's using.
+    unsafe {
+        dump_page_tables(
+            cr3_value.root_address(),
+            paging_mode,
+            &IdentityMapping,
+            None,
+            &mut com1,
+        )
     }
+    .unwrap();
 
     let idtr_value = arch::x86_64::interrupts::IDTRValue::read();
 
@@ -70,18 +85,15 @@ pub extern "efiapi" fn efi_main(image_handle: Handle, system_table: SystemTable<
     }
 
     let gdtr_value = GDTRValue::read();
-    let mut gdtr_ptr = gdtr_value.address().to_raw() as *const GDTEntry;
     let gdte_count = (usize::from(gdtr_value.limit()) + 1) / core::mem::size_of::<GDTEntry>();
 
     writeln!(com1, "CS: {:?}", SegmentSelector::from_cs_register()).unwrap();
     writeln!(com1, "GDTR: {:?}, Count: {}", gdtr_value, gdte_count).unwrap();
 
-    for index in 0..gdte_count {
-        let gdtr_ref = unsafe { &*gdtr_ptr };
-
-        writeln!(com1, "{}: {:?}", index, gdtr_ref).unwrap();
-
-        gdtr_ptr = unsafe { gdtr_ptr.offset(1) };
+    // NOTE: This is safe because UEFI identity maps physical memory,
+    // including the firmware's GDT.
+    for descriptor in unsafe { gdtr_value.descriptors() } {
+        writeln!(com1, "{}: {:?}", descriptor.index(), descriptor).unwrap();
     }
 
     Loader::new(image_handle, system_table).run();
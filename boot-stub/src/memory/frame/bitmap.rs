@@ -0,0 +1,68 @@
+//! Provides a frame allocator that tracks each frame with a bit.
+
+use osc_os_boot_info::MemoryRegion;
+use osc_os_frame::BitmapFrames;
+
+use crate::arch::x86_64::paging::{FrameAllocator, PhysFrame, PhysicalAddressRange};
+
+use super::{frame_at, ContiguousFrameAllocator, Zone};
+
+/// Allocates frames using a bitmap with a bit per frame, so that they
+/// can be freed and reused, see `BitmapFrames`.
+pub struct BitmapFrameAllocator<'a> {
+    frames: BitmapFrames<'a>,
+}
+
+impl<'a> BitmapFrameAllocator<'a> {
+    /// Gets the number of words of bitmap needed to track every usable
+    /// frame in the given memory map.
+    pub fn required_words(regions: &[MemoryRegion]) -> usize {
+        BitmapFrames::required_words(regions)
+    }
+
+    /// Constructs an allocator over the usable regions of the given
+    /// memory map, keeping track of them in the given bitmap. Usable
+    /// frames beyond the end of the bitmap are ignored.
+    ///
+    /// # Safety
+    /// Nothing else may be using the usable regions, other than the
+    /// parts subsequently passed to `reserve`.
+    pub unsafe fn new(regions: &[MemoryRegion], bitmap: &'a mut [u64]) -> Self {
+        Self {
+            frames: BitmapFrames::new(regions, bitmap),
+        }
+    }
+
+    /// Marks the frames overlapping the given range as in use, e.g.
+    /// those handed out by an earlier allocator.
+    pub fn reserve(&mut self, range: PhysicalAddressRange) {
+        self.frames
+            .reserve(range.start().to_raw(), range.end().to_raw());
+    }
+
+    /// Gets the number of frames that are free.
+    pub fn free_frames(&self) -> usize {
+        self.frames.free_frames()
+    }
+}
+
+impl FrameAllocator for BitmapFrameAllocator<'_> {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        self.frames.allocate_frame().map(frame_at)
+    }
+
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        self.frames.deallocate(frame.start_address().to_raw(), 1);
+    }
+}
+
+impl ContiguousFrameAllocator for BitmapFrameAllocator<'_> {
+    fn allocate_contiguous(&mut self, count: usize, align: u64, zone: Zone) -> Option<PhysFrame> {
+        self.frames.allocate(count, align, zone).map(frame_at)
+    }
+
+    unsafe fn deallocate_contiguous(&mut self, first: PhysFrame, count: usize) {
+        self.frames
+            .deallocate(first.start_address().to_raw(), count);
+    }
+}
@@ -0,0 +1,65 @@
+//! Provides a frame allocator that hands out frames in address order.
+
+use osc_os_boot_info::MemoryRegion;
+use osc_os_frame::{BumpFrames, FRAME_SIZE};
+
+use crate::arch::x86_64::paging::{
+    FrameAllocator, PhysFrame, PhysicalAddress, PhysicalAddressRange,
+};
+
+use super::{frame_at, ContiguousFrameAllocator, Zone};
+
+/// Allocates frames from the usable regions of the memory map in
+/// address order, never reusing them, see `BumpFrames`.
+pub struct BumpFrameAllocator<'a> {
+    frames: BumpFrames<'a>,
+}
+
+impl<'a> BumpFrameAllocator<'a> {
+    /// Constructs an allocator over the usable regions of the given
+    /// memory map, which must be sorted by address.
+    ///
+    /// # Safety
+    /// Nothing else may be using the usable regions.
+    pub unsafe fn new(regions: &'a [MemoryRegion]) -> Self {
+        Self {
+            frames: BumpFrames::new(regions),
+        }
+    }
+
+    /// Gets the range of physical memory that the allocator has moved
+    /// through, so that a later allocator can treat it as in use.
+    pub fn allocated_range(&self) -> PhysicalAddressRange {
+        let (start, end) = self.frames.allocated_range();
+
+        // NOTE: This is safe because the range comes from the memory
+        // map.
+        unsafe {
+            PhysicalAddressRange::new(
+                PhysicalAddress::from_raw_unchecked(start),
+                PhysicalAddress::from_raw_unchecked(end),
+            )
+        }
+    }
+}
+
+impl FrameAllocator for BumpFrameAllocator<'_> {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        self.allocate_contiguous(1, FRAME_SIZE, Zone::Any)
+    }
+
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        self.deallocate_contiguous(frame, 1);
+    }
+}
+
+impl ContiguousFrameAllocator for BumpFrameAllocator<'_> {
+    fn allocate_contiguous(&mut self, count: usize, align: u64, zone: Zone) -> Option<PhysFrame> {
+        self.frames.allocate(count, align, zone).map(frame_at)
+    }
+
+    unsafe fn deallocate_contiguous(&mut self, _first: PhysFrame, _count: usize) {
+        // NOTE: Frames are never reused, so freed frames are just lost
+        // until a more capable allocator takes over.
+    }
+}
@@ -0,0 +1,51 @@
+//! Provides allocation of frames of physical memory, seeded from the
+//! converted memory map. The bookkeeping itself lives in
+//! `osc_os_frame`, so that it can be tested on the host.
+//!
+//! There are two allocators:
+//!
+//! * `BumpFrameAllocator`, which hands out frames in address order and
+//!   never reuses them. It needs no memory of its own, which makes it
+//!   useful early on, before there's anywhere to keep track of frames.
+//! * `BitmapFrameAllocator`, which tracks every frame with a bit, so
+//!   that frames can be freed and reused.
+//!
+//! Both implement `FrameAllocator`, so that they can supply a `Mapper`
+//! with page tables, and `ContiguousFrameAllocator`, for things that
+//! need physically contiguous memory, such as DMA buffers.
+
+use crate::arch::x86_64::paging::{FrameAllocator, PhysFrame, PhysicalAddress};
+
+pub use osc_os_frame::Zone;
+
+mod bitmap;
+pub use bitmap::*;
+
+mod bump;
+pub use bump::*;
+
+/// Provides runs of physically contiguous frames.
+pub trait ContiguousFrameAllocator: FrameAllocator {
+    /// Allocates `count` contiguous frames, the first of which is
+    /// aligned to `align` bytes, and all of which lie within the zone.
+    /// Returns the first frame, or `None` if there's no suitable run or
+    /// the count is zero.
+    ///
+    /// The alignment must be a power of two, anything less than the
+    /// size of a frame is treated as the size of a frame.
+    fn allocate_contiguous(&mut self, count: usize, align: u64, zone: Zone) -> Option<PhysFrame>;
+
+    /// Returns a run of frames that is no longer in use.
+    ///
+    /// # Safety
+    /// The run must have come from a single call to
+    /// `allocate_contiguous` on this allocator, with the same count,
+    /// and must not be in use any longer.
+    unsafe fn deallocate_contiguous(&mut self, first: PhysFrame, count: usize);
+}
+
+fn frame_at(raw: u64) -> PhysFrame {
+    // NOTE: This is safe because every frame handed out comes from the
+    // memory map.
+    PhysFrame::containing_address(unsafe { PhysicalAddress::from_raw_unchecked(raw) })
+}
@@ -0,0 +1,206 @@
+//! Provides a heap that keeps its free blocks in a linked list.
+
+use core::alloc::Layout;
+use core::mem::{align_of, size_of};
+use core::ptr::NonNull;
+
+use crate::arch::x86_64::paging::{LinearAddress, LinearAddressRange};
+
+/// A free block, which is stored at the start of the memory it
+/// describes.
+struct FreeBlock {
+    size: usize,
+    next: Option<NonNull<FreeBlock>>,
+}
+
+/// The smallest block the heap deals in, since every free block has
+/// to be able to hold a `FreeBlock`.
+const MIN_BLOCK_SIZE: usize = size_of::<FreeBlock>();
+
+/// A first-fit heap over a single range of memory.
+///
+/// Free blocks are kept sorted by address so that neighbouring blocks
+/// can be merged when they're freed. Every block's size and address are
+/// multiples of `MIN_BLOCK_SIZE`, so that whatever is left over around
+/// an allocation can always hold a `FreeBlock`.
+pub struct LinkedListHeap {
+    head: Option<NonNull<FreeBlock>>,
+    range: Option<LinearAddressRange>,
+    free_bytes: usize,
+}
+
+// NOTE: This is safe because the heap owns the memory its pointers
+// refer to.
+unsafe impl Send for LinkedListHeap {}
+
+impl LinkedListHeap {
+    /// Constructs a heap with no memory.
+    pub const fn empty() -> Self {
+        Self {
+            head: None,
+            range: None,
+            free_bytes: 0,
+        }
+    }
+
+    /// Gives the heap the given range of memory to allocate from,
+    /// replacing any memory it had.
+    ///
+    /// # Safety
+    /// The range must be mapped, writable, and owned by the heap from
+    /// now on.
+    pub unsafe fn init(&mut self, range: LinearAddressRange) {
+        let start = align_up(range.start().to_raw() as usize, MIN_BLOCK_SIZE);
+        let end = range.end().to_raw() as usize & !(MIN_BLOCK_SIZE - 1);
+
+        self.head = None;
+        self.range = Some(range);
+        self.free_bytes = 0;
+
+        if start < end {
+            self.free_block(start, end - start);
+        }
+    }
+
+    /// Determines whether the pointer refers to memory owned by the
+    /// heap.
+    pub fn contains(&self, ptr: *mut u8) -> bool {
+        match (self.range, LinearAddress::new(ptr as u64)) {
+            (Some(range), Ok(address)) => range.contains(address),
+            _ => false,
+        }
+    }
+
+    /// Gets the number of bytes that are free, some of which may be
+    /// unusable due to fragmentation.
+    pub fn free_bytes(&self) -> usize {
+        self.free_bytes
+    }
+
+    /// Allocates a block suitable for the layout, returning null if
+    /// there's no free block large enough.
+    pub unsafe fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let size = block_size(layout);
+        let align = layout.align().max(align_of::<FreeBlock>());
+
+        let mut previous: Option<NonNull<FreeBlock>> = None;
+        let mut current = self.head;
+
+        while let Some(block) = current {
+            let block_start = block.as_ptr() as usize;
+            let block_end = block_start + block.as_ref().size;
+            let next = block.as_ref().next;
+
+            let start = align_up(block_start, align);
+
+            // NOTE: Aligning can leave a gap before the allocation that
+            // is too small to be a free block, in which case the next
+            // suitably aligned address has to be tried instead.
+            let start = if start != block_start && start - block_start < MIN_BLOCK_SIZE {
+                align_up(block_start + MIN_BLOCK_SIZE, align)
+            } else {
+                start
+            };
+
+            let end = start + size;
+
+            if end <= block_end {
+                self.unlink(previous, next);
+                self.free_bytes -= block_end - block_start;
+
+                if start != block_start {
+                    self.free_block(block_start, start - block_start);
+                }
+
+                if end != block_end {
+                    self.free_block(end, block_end - end);
+                }
+
+                return start as *mut u8;
+            }
+
+            previous = Some(block);
+            current = next;
+        }
+
+        core::ptr::null_mut()
+    }
+
+    /// Returns a block to the heap.
+    ///
+    /// # Safety
+    /// The block must have come from `allocate` on this heap with the
+    /// same layout, and must not be in use any longer.
+    pub unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        self.free_block(ptr as usize, block_size(layout));
+    }
+
+    /// Inserts a free block into the list, in address order, merging
+    /// it with its neighbours where they're adjacent.
+    unsafe fn free_block(&mut self, start: usize, size: usize) {
+        let mut previous: Option<NonNull<FreeBlock>> = None;
+        let mut current = self.head;
+
+        while let Some(block) = current {
+            if block.as_ptr() as usize > start {
+                break;
+            }
+
+            previous = Some(block);
+            current = block.as_ref().next;
+        }
+
+        self.free_bytes += size;
+
+        let mut size = size;
+
+        if let Some(next) = current {
+            if start + size == next.as_ptr() as usize {
+                size += next.as_ref().size;
+                current = next.as_ref().next;
+            }
+        }
+
+        if let Some(mut previous) = previous {
+            let previous_start = previous.as_ptr() as usize;
+
+            if previous_start + previous.as_ref().size == start {
+                previous.as_mut().size += size;
+                previous.as_mut().next = current;
+                return;
+            }
+        }
+
+        let block = start as *mut FreeBlock;
+        block.write(FreeBlock {
+            size,
+            next: current,
+        });
+
+        self.link(previous, NonNull::new_unchecked(block));
+    }
+
+    fn link(&mut self, previous: Option<NonNull<FreeBlock>>, block: NonNull<FreeBlock>) {
+        match previous {
+            Some(mut previous) => unsafe { previous.as_mut().next = Some(block) },
+            None => self.head = Some(block),
+        }
+    }
+
+    fn unlink(&mut self, previous: Option<NonNull<FreeBlock>>, next: Option<NonNull<FreeBlock>>) {
+        match previous {
+            Some(mut previous) => unsafe { previous.as_mut().next = next },
+            None => self.head = next,
+        }
+    }
+}
+
+/// Gets the size of the block used for an allocation with the given
+/// layout.
+fn block_size(layout: Layout) -> usize {
+    align_up(layout.size().max(MIN_BLOCK_SIZE), MIN_BLOCK_SIZE)
+}
+
+fn align_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
@@ -0,0 +1,249 @@
+//! Provides the global allocator.
+//!
+//! Until boot services are exited, allocations come from the UEFI
+//! pool. The pool disappears along with boot services, so at handoff
+//! the allocator switches to a heap of its own, in pages reserved from
+//! UEFI beforehand. The loader maps those pages into the kernel's
+//! address space as the "boot stub heap" region, at the same addresses
+//! as in UEFI's, so the heap stays usable across the switch of page
+//! tables. The heap can either be a first-fit heap on its own, or have
+//! slab caches in front of it for small allocations.
+//!
+//! A block allocated from the pool can't be freed once the switch has
+//! happened, so any attempt to do so (including reallocating it) is
+//! reported over the serial port, and the block is leaked.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicPtr, AtomicU8, AtomicUsize, Ordering};
+
+use uefi::table::boot::{BootServices, MemoryType};
+use uefi::ResultExt;
+
+use crate::ansi;
+use crate::arch::x86_64::paging::LinearAddressRange;
+use crate::arch::x86_64::serial::{SerialPort, SerialPortDescriptor};
+use crate::memory::slab::SlabAllocator;
+use crate::sync::SpinLock;
+
+mod linked_list;
+pub use linked_list::*;
+
+/// The number of pages reserved for the heap used after handoff.
+pub const HEAP_PAGES: usize = 256;
+
+/// The alignment of every block allocated from the UEFI pool.
+const POOL_ALIGNMENT: usize = 8;
+
+/// The kinds of heap the allocator can switch to at handoff.
+#[derive(Debug, Copy, Clone)]
+pub enum HeapKind {
+    /// A first-fit heap serves every allocation.
+    LinkedList,
+
+    /// Slab caches serve small allocations, with their slabs (and any
+    /// larger allocations) coming from a first-fit heap. When `debug`
+    /// is set, the caches check for corruption.
+    Slab { debug: bool },
+}
+
+const PHASE_UNINITIALISED: u8 = 0;
+const PHASE_FIRMWARE: u8 = 1;
+const PHASE_HEAP: u8 = 2;
+
+#[global_allocator]
+static ALLOCATOR: BootStubAllocator = BootStubAllocator::new();
+
+/// An allocator that starts off using the UEFI pool, and switches to
+/// a heap of its own at handoff.
+pub struct BootStubAllocator {
+    phase: AtomicU8,
+    boot_services: AtomicPtr<BootServices>,
+    heap: SpinLock<Heap>,
+    firmware_uses: AtomicUsize,
+}
+
+impl BootStubAllocator {
+    const fn new() -> Self {
+        Self {
+            phase: AtomicU8::new(PHASE_UNINITIALISED),
+            boot_services: AtomicPtr::new(core::ptr::null_mut()),
+            heap: SpinLock::new(Heap {
+                blocks: LinkedListHeap::empty(),
+                slabs: None,
+            }),
+            firmware_uses: AtomicUsize::new(0),
+        }
+    }
+
+    unsafe fn allocate_from_pool(&self, layout: Layout) -> *mut u8 {
+        let boot_services = &*self.boot_services.load(Ordering::Acquire);
+
+        // NOTE: The pool only guarantees eight byte alignment, so for
+        // anything more, extra space is allocated and the pointer to
+        // the start of the block is stored just before the aligned
+        // part, so that it can be found when freeing.
+        let (size, needs_aligning) = if layout.align() > POOL_ALIGNMENT {
+            (layout.size() + layout.align(), true)
+        } else {
+            (layout.size(), false)
+        };
+
+        let ptr = match boot_services
+            .allocate_pool(MemoryType::LOADER_DATA, size)
+            .warning_as_error()
+        {
+            Ok(ptr) => ptr,
+            Err(_) => return core::ptr::null_mut(),
+        };
+
+        if needs_aligning {
+            let aligned = ptr.add(layout.align() - (ptr as usize & (layout.align() - 1)));
+            (aligned as *mut *mut u8).sub(1).write(ptr);
+            aligned
+        } else {
+            ptr
+        }
+    }
+
+    unsafe fn deallocate_to_pool(&self, ptr: *mut u8, layout: Layout) {
+        let boot_services = &*self.boot_services.load(Ordering::Acquire);
+
+        let ptr = if layout.align() > POOL_ALIGNMENT {
+            (ptr as *mut *mut u8).sub(1).read()
+        } else {
+            ptr
+        };
+
+        // NOTE: There's nothing useful to do if this fails.
+        let _ = boot_services.free_pool(ptr);
+    }
+
+    fn report_firmware_use(&self, ptr: *mut u8, layout: Layout) {
+        self.firmware_uses.fetch_add(1, Ordering::Relaxed);
+
+        let mut com1 = unsafe { SerialPort::new(SerialPortDescriptor::StandardCom1) };
+
+        let _ = writeln!(
+            com1,
+            "{}Freed a {} byte firmware allocation at {:p} after exiting boot services, leaking it{}",
+            ansi::Color::from_fg_and_bg(ansi::StandardColor::Red, ansi::StandardColor::Black),
+            layout.size(),
+            ptr,
+            ansi::Reset
+        );
+    }
+}
+
+/// The heap used after handoff.
+struct Heap {
+    blocks: LinkedListHeap,
+    slabs: Option<SlabAllocator>,
+}
+
+impl Heap {
+    fn contains(&self, ptr: *mut u8) -> bool {
+        self.blocks.contains(ptr)
+    }
+
+    unsafe fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        match &mut self.slabs {
+            Some(slabs) if slabs.handles(layout) => slabs.allocate(layout, &mut self.blocks),
+            _ => self.blocks.allocate(layout),
+        }
+    }
+
+    unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        match &mut self.slabs {
+            Some(slabs) if slabs.handles(layout) => slabs.deallocate(ptr, layout, &mut self.blocks),
+
+            _ => self.blocks.deallocate(ptr, layout),
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for BootStubAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        match self.phase.load(Ordering::Acquire) {
+            PHASE_FIRMWARE => self.allocate_from_pool(layout),
+            PHASE_HEAP => self.heap.lock().allocate(layout),
+            _ => core::ptr::null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        match self.phase.load(Ordering::Acquire) {
+            PHASE_FIRMWARE => self.deallocate_to_pool(ptr, layout),
+
+            PHASE_HEAP => {
+                let mut heap = self.heap.lock();
+
+                if heap.contains(ptr) {
+                    heap.deallocate(ptr, layout);
+                } else {
+                    drop(heap);
+                    self.report_firmware_use(ptr, layout);
+                }
+            }
+
+            _ => (),
+        }
+    }
+}
+
+/// Starts allocating from the UEFI pool.
+///
+/// # Safety
+/// Boot services must remain available until `switch_to_heap` is
+/// called.
+pub unsafe fn init(boot_services: &BootServices) {
+    ALLOCATOR.boot_services.store(
+        boot_services as *const BootServices as *mut BootServices,
+        Ordering::Release,
+    );
+
+    ALLOCATOR.phase.store(PHASE_FIRMWARE, Ordering::Release);
+}
+
+/// Switches from the UEFI pool to a heap of the given kind in the
+/// given memory, which should be done as soon as boot services have
+/// been exited.
+///
+/// # Safety
+/// The range must be mapped and writable, and must be owned by the
+/// heap from now on.
+pub unsafe fn switch_to_heap(range: LinearAddressRange, kind: HeapKind) {
+    {
+        let mut heap = ALLOCATOR.heap.lock();
+
+        heap.blocks.init(range);
+        heap.slabs = match kind {
+            HeapKind::LinkedList => None,
+            HeapKind::Slab { debug } => Some(SlabAllocator::new(debug)),
+        };
+    }
+
+    ALLOCATOR.phase.store(PHASE_HEAP, Ordering::Release);
+    ALLOCATOR
+        .boot_services
+        .store(core::ptr::null_mut(), Ordering::Release);
+}
+
+/// Gets the number of times a firmware allocation has been freed
+/// since the switch to the heap.
+pub fn firmware_uses_after_switch() -> usize {
+    ALLOCATOR.firmware_uses.load(Ordering::Relaxed)
+}
+
+/// Writes the state of the heap used after handoff to the output,
+/// including the statistics for each slab cache if there are any.
+pub fn write_stats(output: &mut impl fmt::Write) -> fmt::Result {
+    let heap = ALLOCATOR.heap.lock();
+
+    writeln!(output, "Heap: {} bytes free", heap.blocks.free_bytes())?;
+
+    match &heap.slabs {
+        Some(slabs) => slabs.write_stats(output),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,6 @@
+//! Provides management of memory that doesn't depend on the firmware,
+//! so that it keeps working once boot services have been exited.
+pub mod frame;
+pub mod heap;
+pub mod slab;
+pub mod stack;
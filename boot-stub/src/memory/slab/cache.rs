@@ -0,0 +1,295 @@
+//! Provides a cache of objects of a single size.
+//!
+//! Each slab is a single page, starting with a `SlabHeader`, followed
+//! by the slots the objects live in:
+//!
+//! | Bytes              | Purpose                                  |
+//! | -------------------| -----------------------------------------|
+//! | 0 - header size    | `SlabHeader`                             |
+//! | padding            | Aligns the first slot                    |
+//! | first slot onwards | One slot per object                      |
+//!
+//! In debug mode, each slot surrounds its object with red zones, which
+//! are checked whenever the object is allocated or freed, and freed
+//! objects are filled with a poison pattern, which is checked when the
+//! object is allocated again.
+
+use core::fmt::Write;
+use core::mem::size_of;
+use core::ptr::NonNull;
+
+use crate::arch::x86_64::paging::{LinearAddress, PAGE_SIZE};
+use crate::arch::x86_64::serial::{SerialPort, SerialPortDescriptor};
+
+use super::page_source::PageSource;
+use super::CacheStats;
+
+/// The size of the red zones either side of an object in debug mode.
+pub const RED_ZONE_SIZE: usize = 16;
+
+/// The byte red zones are filled with.
+const RED_ZONE_BYTE: u8 = 0xBB;
+
+/// The byte freed objects are filled with.
+const POISON_BYTE: u8 = 0x6B;
+
+/// Sits at the start of every slab.
+struct SlabHeader {
+    next: Option<NonNull<SlabHeader>>,
+    free: Option<NonNull<FreeObject>>,
+    in_use: usize,
+}
+
+/// Sits at the start of every free object.
+struct FreeObject {
+    next: Option<NonNull<FreeObject>>,
+}
+
+pub struct SlabCache {
+    object_size: usize,
+    slot_size: usize,
+    first_slot_offset: usize,
+    slots_per_slab: usize,
+    debug: bool,
+    slabs: Option<NonNull<SlabHeader>>,
+    live_objects: usize,
+    pages: usize,
+}
+
+// NOTE: This is safe because the cache owns the slabs its pointers
+// refer to.
+unsafe impl Send for SlabCache {}
+
+impl SlabCache {
+    /// Constructs an empty cache of objects of the given size, which
+    /// must be a power of two no smaller than a pointer.
+    pub fn new(object_size: usize, debug: bool) -> Self {
+        let header_size = size_of::<SlabHeader>();
+
+        // NOTE: Outside of debug mode, slots are aligned to their size,
+        // so objects are too. In debug mode, the red zones get in the
+        // way of that, so objects are only aligned to the red zone size.
+        let (slot_size, first_slot_offset) = if debug {
+            (
+                object_size + 2 * RED_ZONE_SIZE,
+                align_up(header_size, RED_ZONE_SIZE),
+            )
+        } else {
+            (object_size, align_up(header_size, object_size))
+        };
+
+        Self {
+            object_size,
+            slot_size,
+            first_slot_offset,
+            slots_per_slab: (PAGE_SIZE as usize - first_slot_offset) / slot_size,
+            debug,
+            slabs: None,
+            live_objects: 0,
+            pages: 0,
+        }
+    }
+
+    /// Gets the size of the objects in the cache.
+    pub fn object_size(&self) -> usize {
+        self.object_size
+    }
+
+    /// Gets the alignment that objects in the cache are guaranteed.
+    pub fn object_align(&self) -> usize {
+        if self.debug {
+            RED_ZONE_SIZE.min(self.object_size)
+        } else {
+            self.object_size
+        }
+    }
+
+    /// Gets the statistics for the cache.
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            object_size: self.object_size,
+            live_objects: self.live_objects,
+            free_objects: self.pages * self.slots_per_slab - self.live_objects,
+            pages: self.pages,
+        }
+    }
+
+    /// Allocates an object, returning null if a new slab was needed
+    /// and the source had no pages left.
+    pub unsafe fn allocate(&mut self, source: &mut impl PageSource) -> *mut u8 {
+        let mut slab = match self.find_slab_with_free_object() {
+            Some(slab) => slab,
+
+            None => match self.add_slab(source) {
+                Some(slab) => slab,
+                None => return core::ptr::null_mut(),
+            },
+        };
+
+        let header = slab.as_mut();
+        let object = header.free.expect("slab with free objects has none");
+
+        header.free = object.as_ref().next;
+        header.in_use += 1;
+        self.live_objects += 1;
+
+        let object = object.as_ptr() as *mut u8;
+
+        if self.debug {
+            self.check_red_zones(object);
+            self.check_poison(object);
+        }
+
+        object
+    }
+
+    /// Returns an object to the cache, giving its slab back to the
+    /// source if the slab becomes empty and it isn't the only one.
+    ///
+    /// # Safety
+    /// The object must have come from `allocate` on this cache, and
+    /// must not be in use any longer.
+    pub unsafe fn deallocate(&mut self, object: *mut u8, source: &mut impl PageSource) {
+        let slab_address = object as usize & !(PAGE_SIZE as usize - 1);
+        let mut slab = NonNull::new_unchecked(slab_address as *mut SlabHeader);
+
+        if self.debug {
+            // NOTE: A pointer into the slab's header comes before the
+            // first object, so it's reported the same way as one that
+            // doesn't line up with a slot.
+            let offset = (object as usize - slab_address)
+                .checked_sub(self.first_slot_offset + RED_ZONE_SIZE);
+
+            if offset.map_or(true, |offset| offset % self.slot_size != 0) {
+                report_corruption("freed a pointer that isn't an object", object);
+            }
+
+            self.check_red_zones(object);
+
+            if self.is_poisoned(object) {
+                report_corruption("freed an object that looks to be free already", object);
+            }
+
+            core::ptr::write_bytes(object, POISON_BYTE, self.object_size);
+        }
+
+        let free = object as *mut FreeObject;
+        let header = slab.as_mut();
+
+        free.write(FreeObject { next: header.free });
+        header.free = NonNull::new(free);
+        header.in_use -= 1;
+        self.live_objects -= 1;
+
+        if header.in_use == 0 && self.pages > 1 {
+            self.remove_slab(slab);
+            source.deallocate_page(LinearAddress::from_raw_unchecked(slab_address as u64));
+        }
+    }
+
+    unsafe fn find_slab_with_free_object(&self) -> Option<NonNull<SlabHeader>> {
+        let mut current = self.slabs;
+
+        while let Some(slab) = current {
+            if slab.as_ref().free.is_some() {
+                return Some(slab);
+            }
+
+            current = slab.as_ref().next;
+        }
+
+        None
+    }
+
+    unsafe fn add_slab(&mut self, source: &mut impl PageSource) -> Option<NonNull<SlabHeader>> {
+        let page = source.allocate_page()?.to_raw() as usize;
+        let mut free = None;
+
+        // NOTE: The free list is built backwards so that objects are
+        // handed out in address order.
+        for slot in (0..self.slots_per_slab).rev() {
+            let slot_start = page + self.first_slot_offset + slot * self.slot_size;
+
+            let object = if self.debug {
+                let object = (slot_start + RED_ZONE_SIZE) as *mut u8;
+
+                core::ptr::write_bytes(slot_start as *mut u8, RED_ZONE_BYTE, RED_ZONE_SIZE);
+                core::ptr::write_bytes(object, POISON_BYTE, self.object_size);
+                core::ptr::write_bytes(object.add(self.object_size), RED_ZONE_BYTE, RED_ZONE_SIZE);
+
+                object as *mut FreeObject
+            } else {
+                slot_start as *mut FreeObject
+            };
+
+            object.write(FreeObject { next: free });
+            free = NonNull::new(object);
+        }
+
+        let header = page as *mut SlabHeader;
+
+        header.write(SlabHeader {
+            next: self.slabs,
+            free,
+            in_use: 0,
+        });
+
+        self.slabs = NonNull::new(header);
+        self.pages += 1;
+
+        self.slabs
+    }
+
+    unsafe fn remove_slab(&mut self, slab: NonNull<SlabHeader>) {
+        let mut link = &mut self.slabs;
+
+        while let Some(current) = *link {
+            if current == slab {
+                *link = current.as_ref().next;
+                self.pages -= 1;
+                return;
+            }
+
+            link = &mut (*current.as_ptr()).next;
+        }
+    }
+
+    unsafe fn check_red_zones(&self, object: *mut u8) {
+        let before = core::slice::from_raw_parts(object.sub(RED_ZONE_SIZE), RED_ZONE_SIZE);
+        let after = core::slice::from_raw_parts(object.add(self.object_size), RED_ZONE_SIZE);
+
+        if before
+            .iter()
+            .chain(after)
+            .any(|byte| *byte != RED_ZONE_BYTE)
+        {
+            report_corruption("red zone overwritten", object);
+        }
+    }
+
+    /// Determines whether the object is filled with poison, other than
+    /// the free list link at its start.
+    unsafe fn is_poisoned(&self, object: *mut u8) -> bool {
+        let link_size = size_of::<FreeObject>();
+        let rest = core::slice::from_raw_parts(object.add(link_size), self.object_size - link_size);
+
+        rest.iter().all(|byte| *byte == POISON_BYTE)
+    }
+
+    unsafe fn check_poison(&self, object: *mut u8) {
+        if !self.is_poisoned(object) {
+            report_corruption("free object written to", object);
+        }
+    }
+}
+
+fn report_corruption(problem: &str, object: *mut u8) -> ! {
+    let mut com1 = unsafe { SerialPort::new(SerialPortDescriptor::StandardCom1) };
+    let _ = writeln!(com1, "Slab corruption: {} at {:p}", problem, object);
+
+    panic!("slab corruption: {} at {:p}", problem, object);
+}
+
+fn align_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
@@ -0,0 +1,117 @@
+//! Provides a slab allocator, which serves small allocations from
+//! caches of fixed-size objects, one cache per size class.
+//!
+//! The caches get their memory a page at a time from a `PageSource`,
+//! which can either be a heap (as it is when the slab allocator backs
+//! the global allocator) or frames mapped through a `Mapper`.
+
+use core::alloc::Layout;
+use core::fmt;
+
+mod cache;
+pub use cache::*;
+
+mod page_source;
+pub use page_source::*;
+
+/// The sizes of object the slab allocator has caches for.
+pub const SIZE_CLASSES: [usize; 8] = [16, 32, 64, 128, 256, 512, 1024, 2048];
+
+/// The statistics for a single cache.
+#[derive(Debug, Copy, Clone)]
+pub struct CacheStats {
+    /// The size of the objects in the cache.
+    pub object_size: usize,
+
+    /// The number of objects that are allocated.
+    pub live_objects: usize,
+
+    /// The number of objects that are free in the cache's slabs.
+    pub free_objects: usize,
+
+    /// The number of pages used by the cache's slabs.
+    pub pages: usize,
+}
+
+/// Allocates objects from the cache for the smallest size class that
+/// fits them.
+pub struct SlabAllocator {
+    caches: [SlabCache; SIZE_CLASSES.len()],
+}
+
+impl SlabAllocator {
+    /// Constructs an allocator with empty caches, which checks for
+    /// corruption when `debug` is set.
+    pub fn new(debug: bool) -> Self {
+        let cache = |index: usize| SlabCache::new(SIZE_CLASSES[index], debug);
+
+        Self {
+            caches: [
+                cache(0),
+                cache(1),
+                cache(2),
+                cache(3),
+                cache(4),
+                cache(5),
+                cache(6),
+                cache(7),
+            ],
+        }
+    }
+
+    /// Determines whether an allocation with the given layout can be
+    /// served by one of the caches.
+    pub fn handles(&self, layout: Layout) -> bool {
+        self.cache_index(layout).is_some()
+    }
+
+    /// Allocates an object suitable for the layout, returning null if
+    /// there's no suitable cache, or the source has run out of pages.
+    pub unsafe fn allocate(&mut self, layout: Layout, source: &mut impl PageSource) -> *mut u8 {
+        match self.cache_index(layout) {
+            Some(index) => self.caches[index].allocate(source),
+            None => core::ptr::null_mut(),
+        }
+    }
+
+    /// Returns an object to its cache.
+    ///
+    /// # Safety
+    /// The object must have come from `allocate` on this allocator with
+    /// the same layout and source, and must not be in use any longer.
+    pub unsafe fn deallocate(
+        &mut self,
+        object: *mut u8,
+        layout: Layout,
+        source: &mut impl PageSource,
+    ) {
+        if let Some(index) = self.cache_index(layout) {
+            self.caches[index].deallocate(object, source);
+        }
+    }
+
+    /// Gets the statistics for each cache.
+    pub fn stats(&self) -> impl Iterator<Item = CacheStats> + '_ {
+        self.caches.iter().map(SlabCache::stats)
+    }
+
+    /// Writes the statistics for each cache to the output, a line per
+    /// cache.
+    pub fn write_stats(&self, output: &mut impl fmt::Write) -> fmt::Result {
+        for stats in self.stats() {
+            writeln!(
+                output,
+                "{:>5} byte objects: {} live, {} free, {} pages",
+                stats.object_size, stats.live_objects, stats.free_objects, stats.pages
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn cache_index(&self, layout: Layout) -> Option<usize> {
+        self.caches.iter().position(|cache| {
+            layout.size() <= cache.object_size() && layout.align() <= cache.object_align()
+        })
+    }
+}
@@ -0,0 +1,109 @@
+//! Provides the pages that slabs are carved out of.
+
+use core::alloc::Layout;
+
+use crate::arch::x86_64::paging::{
+    FrameAllocator, LinearAddress, LinearAddressRange, Mapper, Page, PageTableEntryFlags,
+    PhysicalToLinear, PAGE_SIZE,
+};
+
+use super::super::heap::LinkedListHeap;
+
+/// Provides pages of memory, which must be mapped, writable, and
+/// aligned to the size of a page.
+pub trait PageSource {
+    /// Allocates a page, returning `None` if there are none left.
+    fn allocate_page(&mut self) -> Option<LinearAddress>;
+
+    /// Returns a page that is no longer in use.
+    ///
+    /// # Safety
+    /// The page must have come from this source, and must not be in
+    /// use any longer.
+    unsafe fn deallocate_page(&mut self, page: LinearAddress);
+}
+
+impl PageSource for LinkedListHeap {
+    fn allocate_page(&mut self) -> Option<LinearAddress> {
+        let ptr = unsafe { self.allocate(page_layout()) };
+        LinearAddress::new(ptr as u64)
+            .ok()
+            .filter(|_| !ptr.is_null())
+    }
+
+    unsafe fn deallocate_page(&mut self, page: LinearAddress) {
+        self.deallocate(page.to_raw() as *mut u8, page_layout());
+    }
+}
+
+/// Provides pages by allocating frames and mapping them into a window
+/// of the linear address space.
+///
+/// Linear addresses aren't reused, so the window needs to be large
+/// enough for every page that will ever be allocated.
+pub struct MappedPageSource<'a, P: PhysicalToLinear, A: FrameAllocator> {
+    mapper: Mapper<'a, P>,
+    frames: A,
+    window: LinearAddressRange,
+    next: LinearAddress,
+}
+
+impl<'a, P: PhysicalToLinear, A: FrameAllocator> MappedPageSource<'a, P, A> {
+    /// Constructs a source that maps pages into the given window of
+    /// the tables being modified by the mapper, which must be active.
+    ///
+    /// # Safety
+    /// Nothing else may be mapped in the window.
+    pub unsafe fn new(mapper: Mapper<'a, P>, frames: A, window: LinearAddressRange) -> Self {
+        let next = window.start().align_up(PAGE_SIZE);
+
+        Self {
+            mapper,
+            frames,
+            window,
+            next,
+        }
+    }
+}
+
+impl<P: PhysicalToLinear, A: FrameAllocator> PageSource for MappedPageSource<'_, P, A> {
+    fn allocate_page(&mut self) -> Option<LinearAddress> {
+        if self.window.end() - self.next < PAGE_SIZE {
+            return None;
+        }
+
+        let page = Page::containing_address(self.next);
+        let frame = self.frames.allocate_frame()?;
+
+        let flags = PageTableEntryFlags::WRITABLE | PageTableEntryFlags::NO_EXECUTE;
+
+        match self.mapper.map(page, frame, flags, &mut self.frames) {
+            Ok(flush) => flush.flush(),
+
+            Err(_) => {
+                // NOTE: This is safe because the frame was never mapped.
+                unsafe { self.frames.deallocate_frame(frame) };
+                return None;
+            }
+        }
+
+        self.next += PAGE_SIZE;
+
+        Some(page.start_address())
+    }
+
+    unsafe fn deallocate_page(&mut self, page: LinearAddress) {
+        let page = Page::containing_address(page);
+
+        if let Ok((frame, flush)) = self.mapper.unmap(page, &mut self.frames) {
+            flush.flush();
+            self.frames.deallocate_frame(frame);
+        }
+    }
+}
+
+fn page_layout() -> Layout {
+    // NOTE: This is safe because the page size is a non-zero power of
+    // two.
+    unsafe { Layout::from_size_align_unchecked(PAGE_SIZE as usize, PAGE_SIZE as usize) }
+}
@@ -0,0 +1,150 @@
+//! Provides stacks with unmapped guard pages below them, so that
+//! overflowing a stack causes a page fault instead of silently
+//! corrupting whatever lies below it.
+//!
+//! Stacks are given their own frames, which don't need to be
+//! contiguous, and are mapped in the first gap in a window of the
+//! linear address space that's large enough for them and their guard.
+
+use crate::arch::x86_64::paging::{
+    FrameAllocator, LinearAddress, LinearAddressRange, Mapper, MapperError, PageTableEntryFlags,
+    PhysicalToLinear, RegionError, RegionManager, Size4KiB, PAGE_SIZE,
+};
+
+/// The number of unmapped pages below every stack.
+pub const GUARD_PAGES: u64 = 1;
+
+/// The name of the region reserved for each guard.
+const GUARD_REGION_NAME: &str = "stack guard";
+
+/// The ways allocating a stack can fail.
+#[derive(Debug)]
+pub enum StackError {
+    /// Reserving the linear addresses for the stack failed.
+    ReserveFailed(RegionError),
+
+    /// There weren't enough frames for the stack.
+    OutOfFrames,
+
+    /// Mapping the page at the given linear address failed.
+    MapFailed(u64, MapperError),
+}
+
+/// A stack, along with the guard below it.
+#[derive(Debug, Copy, Clone)]
+pub struct Stack {
+    guard: LinearAddressRange,
+    range: LinearAddressRange,
+}
+
+impl Stack {
+    /// Gets the address just beyond the top of the stack, which is
+    /// what the stack pointer starts off as.
+    pub fn top(&self) -> LinearAddress {
+        self.range.end()
+    }
+
+    /// Gets the lowest usable address of the stack.
+    pub fn bottom(&self) -> LinearAddress {
+        self.range.start()
+    }
+
+    /// Gets the usable size of the stack in bytes.
+    pub fn size(&self) -> u64 {
+        self.range.len()
+    }
+
+    /// Gets the unmapped range below the stack.
+    pub fn guard(&self) -> LinearAddressRange {
+        self.guard
+    }
+}
+
+/// Allocates a stack of `pages` pages, with a guard below it, in the
+/// first gap in `window` that's large enough, reserving both as
+/// regions.
+///
+/// Frames for the stack come from `stack_frames`, and frames for any
+/// page tables needed to map it come from `table_frames`. If anything
+/// fails, everything allocated so far is given back.
+///
+/// The mapper's page tables must not be the active ones, since nothing
+/// is flushed from the TLB.
+pub fn allocate_stack<P: PhysicalToLinear>(
+    mapper: &mut Mapper<'_, P>,
+    regions: &mut RegionManager,
+    stack_frames: &mut impl FrameAllocator,
+    table_frames: &mut impl FrameAllocator,
+    name: &'static str,
+    pages: u64,
+    window: LinearAddressRange,
+) -> Result<Stack, StackError> {
+    let guard_size = GUARD_PAGES * PAGE_SIZE;
+    let size = pages * PAGE_SIZE;
+
+    let start = regions
+        .find_gap(guard_size + size, PAGE_SIZE, window)
+        .ok_or(StackError::ReserveFailed(RegionError::NoGap(
+            name,
+            guard_size + size,
+        )))?;
+
+    let stack = Stack {
+        guard: LinearAddressRange::from_start_and_len(start, guard_size),
+        range: LinearAddressRange::from_start_and_len(start + guard_size, size),
+    };
+
+    let flags = PageTableEntryFlags::WRITABLE | PageTableEntryFlags::NO_EXECUTE;
+
+    // NOTE: The guard is reserved so that nothing else gets mapped
+    // there, but it's never mapped itself.
+    regions
+        .reserve(GUARD_REGION_NAME, stack.guard, PageTableEntryFlags::empty())
+        .map_err(StackError::ReserveFailed)?;
+
+    if let Err(err) = regions.reserve(name, stack.range, flags) {
+        regions.release(stack.guard.start());
+        return Err(StackError::ReserveFailed(err));
+    }
+
+    let mut mapped = 0;
+
+    let result = stack.range.pages::<Size4KiB>().try_for_each(|page| {
+        let frame = stack_frames
+            .allocate_frame()
+            .ok_or(StackError::OutOfFrames)?;
+
+        match mapper.map(page, frame, flags, table_frames) {
+            Ok(flush) => {
+                flush.ignore();
+                mapped += 1;
+                Ok(())
+            }
+
+            Err(err) => {
+                // NOTE: This is safe because the frame was never mapped.
+                unsafe { stack_frames.deallocate_frame(frame) };
+                Err(StackError::MapFailed(page.start_address().to_raw(), err))
+            }
+        }
+    });
+
+    if let Err(err) = result {
+        for page in stack.range.pages::<Size4KiB>().take(mapped) {
+            if let Ok((frame, flush)) = mapper.unmap(page, table_frames) {
+                flush.ignore();
+
+                // NOTE: This is safe because the frame is no longer
+                // mapped, and nothing could have used it yet.
+                unsafe { stack_frames.deallocate_frame(frame) };
+            }
+        }
+
+        regions.release(stack.range.start());
+        regions.release(stack.guard.start());
+
+        return Err(err);
+    }
+
+    Ok(stack)
+}
@@ -0,0 +1,658 @@
+//! Provides just enough ELF64 parsing to load a kernel image.
+//!
+//! Everything here works on an image that has already been read into
+//! memory, so that it can be built and tested on the host. Copying the
+//! segments into place is left to the boot stub.
+//!
+//! For more details about the format see the System V ABI (Generic
+//! ABI, chapters 4 and 5) and its AMD64 supplement.
+#![cfg_attr(not(test), no_std)]
+
+use bitflags::bitflags;
+use core::mem::size_of;
+
+/// The reasons an image might be rejected as an ELF64 executable.
+#[derive(Debug)]
+pub enum ElfError {
+    /// The image is too short to contain an ELF header.
+    TooSmall,
+
+    /// The image doesn't start with the ELF magic number.
+    BadMagic,
+
+    /// The image isn't a 64-bit ELF file.
+    UnsupportedClass(u8),
+
+    /// The image isn't little-endian.
+    UnsupportedEncoding(u8),
+
+    /// The image targets a machine other than x86-64.
+    UnsupportedMachine(u16),
+
+    /// The image is neither an executable nor a shared object.
+    UnsupportedType(u16),
+
+    /// The program header entries are an unexpected size.
+    BadProgramHeaderSize(u16),
+
+    /// The program header table extends beyond the end of the image.
+    ProgramHeadersOutOfBounds,
+
+    /// The contents of the segment at the given index extend beyond
+    /// the end of the image.
+    SegmentOutOfBounds(usize),
+
+    /// The segment at the given index has a larger size in the file
+    /// than in memory.
+    SegmentFileSizeTooLarge(usize),
+
+    /// The segment at the given index extends beyond the end of the
+    /// address space.
+    SegmentAddressOverflow(usize),
+
+    /// The image is position-independent, and the segment at the given
+    /// index has no physical address, so it can't be loaded without
+    /// being relocated.
+    RelocationRequired(usize),
+}
+
+/// The type of an ELF image.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ElfType {
+    /// A fixed-address executable (`ET_EXEC`).
+    Executable,
+
+    /// A position-independent image (`ET_DYN`).
+    SharedObject,
+}
+
+/// The ELF file header.
+///
+/// | Bytes    | Length | Purpose                                  |
+/// | ---------| -------| -----------------------------------------|
+/// |  0 - 15  | 16     | Identification (magic, class, encoding)  |
+/// | 16 - 17  | 2      | Type                                     |
+/// | 18 - 19  | 2      | Machine                                  |
+/// | 20 - 23  | 4      | Version                                  |
+/// | 24 - 31  | 8      | Entry point                              |
+/// | 32 - 39  | 8      | Program header table offset              |
+/// | 40 - 47  | 8      | Section header table offset              |
+/// | 48 - 51  | 4      | Flags                                    |
+/// | 52 - 53  | 2      | Header size                              |
+/// | 54 - 55  | 2      | Program header entry size                |
+/// | 56 - 57  | 2      | Program header entry count               |
+/// | 58 - 59  | 2      | Section header entry size                |
+/// | 60 - 61  | 2      | Section header entry count               |
+/// | 62 - 63  | 2      | Section name string table index          |
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct FileHeader {
+    ident: [u8; 16],
+    elf_type: u16,
+    machine: u16,
+    version: u32,
+    entry: u64,
+    program_header_offset: u64,
+    section_header_offset: u64,
+    flags: u32,
+    header_size: u16,
+    program_header_entry_size: u16,
+    program_header_count: u16,
+    section_header_entry_size: u16,
+    section_header_count: u16,
+    section_name_index: u16,
+}
+
+impl FileHeader {
+    const MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+    const CLASS_64: u8 = 2;
+    const ENCODING_LITTLE_ENDIAN: u8 = 1;
+    const TYPE_EXEC: u16 = 2;
+    const TYPE_DYN: u16 = 3;
+    const MACHINE_X86_64: u16 = 62;
+}
+
+/// The types of segment described by a program header.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SegmentType {
+    Null,
+    Load,
+    Dynamic,
+    Interpreter,
+    Note,
+    ProgramHeaders,
+    ThreadLocalStorage,
+    Other(u32),
+}
+
+impl From<u32> for SegmentType {
+    fn from(raw: u32) -> Self {
+        match raw {
+            0 => Self::Null,
+            1 => Self::Load,
+            2 => Self::Dynamic,
+            3 => Self::Interpreter,
+            4 => Self::Note,
+            6 => Self::ProgramHeaders,
+            7 => Self::ThreadLocalStorage,
+            other => Self::Other(other),
+        }
+    }
+}
+
+bitflags! {
+    pub struct SegmentFlags: u32 {
+        const EXECUTABLE = 0b001;
+        const WRITABLE = 0b010;
+        const READABLE = 0b100;
+    }
+}
+
+/// An entry in the program header table.
+///
+/// | Bytes    | Length | Purpose                                  |
+/// | ---------| -------| -----------------------------------------|
+/// |  0 - 3   | 4      | Segment type                             |
+/// |  4 - 7   | 4      | Segment flags                            |
+/// |  8 - 15  | 8      | Offset of the contents in the file       |
+/// | 16 - 23  | 8      | Virtual address                          |
+/// | 24 - 31  | 8      | Physical address                         |
+/// | 32 - 39  | 8      | Size in the file                         |
+/// | 40 - 47  | 8      | Size in memory                           |
+/// | 48 - 55  | 8      | Alignment                                |
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct ProgramHeader {
+    segment_type: u32,
+    flags: u32,
+    offset: u64,
+    virtual_address: u64,
+    physical_address: u64,
+    file_size: u64,
+    memory_size: u64,
+    alignment: u64,
+}
+
+impl ProgramHeader {
+    pub fn segment_type(&self) -> SegmentType {
+        SegmentType::from(self.segment_type)
+    }
+
+    pub fn flags(&self) -> SegmentFlags {
+        SegmentFlags::from_bits_truncate(self.flags)
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn virtual_address(&self) -> u64 {
+        self.virtual_address
+    }
+
+    pub fn physical_address(&self) -> u64 {
+        self.physical_address
+    }
+
+    pub fn file_size(&self) -> u64 {
+        self.file_size
+    }
+
+    pub fn memory_size(&self) -> u64 {
+        self.memory_size
+    }
+
+    pub fn alignment(&self) -> u64 {
+        self.alignment
+    }
+}
+
+impl core::fmt::Debug for ProgramHeader {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ProgramHeader")
+            .field("segment_type", &self.segment_type())
+            .field("flags", &self.flags())
+            .field("offset", &format_args!("{:#X}", self.offset))
+            .field(
+                "virtual_address",
+                &format_args!("{:#018X}", self.virtual_address),
+            )
+            .field(
+                "physical_address",
+                &format_args!("{:#018X}", self.physical_address),
+            )
+            .field("file_size", &format_args!("{:#X}", self.file_size))
+            .field("memory_size", &format_args!("{:#X}", self.memory_size))
+            .finish()
+    }
+}
+
+/// Provides validated access to an ELF64 image held in memory.
+pub struct ElfFile<'a> {
+    data: &'a [u8],
+    header: FileHeader,
+}
+
+impl<'a> ElfFile<'a> {
+    /// Parses and validates the header of the given image, along with
+    /// the bounds of its program headers and their segments.
+    pub fn parse(data: &'a [u8]) -> Result<Self, ElfError> {
+        if data.len() < size_of::<FileHeader>() {
+            return Err(ElfError::TooSmall);
+        }
+
+        // NOTE: This is safe because we've checked the length, and the
+        // read is unaligned because the buffer is just bytes.
+        let header = unsafe { core::ptr::read_unaligned(data.as_ptr() as *const FileHeader) };
+
+        if header.ident[0..4] != FileHeader::MAGIC {
+            return Err(ElfError::BadMagic);
+        }
+
+        if header.ident[4] != FileHeader::CLASS_64 {
+            return Err(ElfError::UnsupportedClass(header.ident[4]));
+        }
+
+        if header.ident[5] != FileHeader::ENCODING_LITTLE_ENDIAN {
+            return Err(ElfError::UnsupportedEncoding(header.ident[5]));
+        }
+
+        if header.machine != FileHeader::MACHINE_X86_64 {
+            return Err(ElfError::UnsupportedMachine(header.machine));
+        }
+
+        if header.elf_type != FileHeader::TYPE_EXEC && header.elf_type != FileHeader::TYPE_DYN {
+            return Err(ElfError::UnsupportedType(header.elf_type));
+        }
+
+        if usize::from(header.program_header_entry_size) != size_of::<ProgramHeader>() {
+            return Err(ElfError::BadProgramHeaderSize(
+                header.program_header_entry_size,
+            ));
+        }
+
+        let table_size = u64::from(header.program_header_count) * size_of::<ProgramHeader>() as u64;
+
+        match header.program_header_offset.checked_add(table_size) {
+            Some(table_end) if table_end <= data.len() as u64 => (),
+            _ => return Err(ElfError::ProgramHeadersOutOfBounds),
+        }
+
+        let file = Self { data, header };
+
+        for (index, program_header) in file.program_headers().enumerate() {
+            if program_header.file_size > program_header.memory_size {
+                return Err(ElfError::SegmentFileSizeTooLarge(index));
+            }
+
+            match program_header.offset.checked_add(program_header.file_size) {
+                Some(end) if end <= data.len() as u64 => (),
+                _ => return Err(ElfError::SegmentOutOfBounds(index)),
+            }
+
+            let virtual_end = program_header
+                .virtual_address
+                .checked_add(program_header.memory_size);
+            let physical_end = program_header
+                .physical_address
+                .checked_add(program_header.memory_size);
+
+            if virtual_end.is_none() || physical_end.is_none() {
+                return Err(ElfError::SegmentAddressOverflow(index));
+            }
+        }
+
+        Ok(file)
+    }
+
+    /// Gets the type of the image.
+    pub fn elf_type(&self) -> ElfType {
+        match self.header.elf_type {
+            FileHeader::TYPE_EXEC => ElfType::Executable,
+            _ => ElfType::SharedObject,
+        }
+    }
+
+    /// Finds the first loadable segment that can't be loaded without
+    /// relocating the image, returning its index in the program header
+    /// table.
+    ///
+    /// Executables always have fixed addresses. Position-independent
+    /// images are only loadable where they were linked if every segment
+    /// has been given a physical address, rather than zero.
+    pub fn segment_requiring_relocation(&self) -> Option<usize> {
+        if self.elf_type() == ElfType::Executable {
+            return None;
+        }
+
+        self.loadable_segments()
+            .find(|(_, ph)| ph.memory_size() != 0 && ph.physical_address() == 0)
+            .map(|(index, _)| index)
+    }
+
+    /// Gets the virtual address of the entry point.
+    pub fn entry_point(&self) -> u64 {
+        self.header.entry
+    }
+
+    /// Iterates over the entries in the program header table.
+    pub fn program_headers(&self) -> impl Iterator<Item = ProgramHeader> + 'a {
+        let data = self.data;
+        let table_offset = self.header.program_header_offset as usize;
+
+        (0..usize::from(self.header.program_header_count)).map(move |index| {
+            let offset = table_offset + index * size_of::<ProgramHeader>();
+
+            // NOTE: This is safe because parse checked that the table
+            // lies within the image.
+            unsafe { core::ptr::read_unaligned(data[offset..].as_ptr() as *const ProgramHeader) }
+        })
+    }
+
+    /// Iterates over the segments that need to be loaded into memory,
+    /// along with their indices in the program header table.
+    pub fn loadable_segments(&self) -> impl Iterator<Item = (usize, ProgramHeader)> + 'a {
+        self.program_headers()
+            .enumerate()
+            .filter(|(_, ph)| ph.segment_type() == SegmentType::Load)
+    }
+
+    /// Gets the contents of the given segment from the file. This will
+    /// be shorter than the segment's memory size when it has a BSS part.
+    pub fn segment_data(&self, program_header: &ProgramHeader) -> &'a [u8] {
+        let start = program_header.offset as usize;
+        let end = start + program_header.file_size as usize;
+        &self.data[start..end]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER_SIZE: usize = size_of::<FileHeader>();
+    const PROGRAM_HEADER_SIZE: usize = size_of::<ProgramHeader>();
+
+    struct Segment {
+        segment_type: u32,
+        offset: u64,
+        virtual_address: u64,
+        physical_address: u64,
+        file_size: u64,
+        memory_size: u64,
+    }
+
+    fn load(offset: u64, address: u64, file_size: u64, memory_size: u64) -> Segment {
+        Segment {
+            segment_type: 1,
+            offset,
+            virtual_address: 0xFFFF_FFFF_8000_0000 + address,
+            physical_address: address,
+            file_size,
+            memory_size,
+        }
+    }
+
+    /// Builds an x86-64 executable with the given segments, whose
+    /// program header table directly follows the file header, padded
+    /// out to the given total size.
+    fn image(segments: &[Segment], size: usize) -> Vec<u8> {
+        let mut data = vec![0u8; size];
+
+        data[0..4].copy_from_slice(&FileHeader::MAGIC);
+        data[4] = FileHeader::CLASS_64;
+        data[5] = FileHeader::ENCODING_LITTLE_ENDIAN;
+        data[6] = 1;
+        data[16..18].copy_from_slice(&FileHeader::TYPE_EXEC.to_le_bytes());
+        data[18..20].copy_from_slice(&FileHeader::MACHINE_X86_64.to_le_bytes());
+        data[20..24].copy_from_slice(&1u32.to_le_bytes());
+        data[24..32].copy_from_slice(&0xFFFF_FFFF_8010_0000u64.to_le_bytes());
+        data[32..40].copy_from_slice(&(HEADER_SIZE as u64).to_le_bytes());
+        data[52..54].copy_from_slice(&(HEADER_SIZE as u16).to_le_bytes());
+        data[54..56].copy_from_slice(&(PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+        data[56..58].copy_from_slice(&(segments.len() as u16).to_le_bytes());
+
+        for (index, segment) in segments.iter().enumerate() {
+            let entry = &mut data[HEADER_SIZE + index * PROGRAM_HEADER_SIZE..];
+            entry[0..4].copy_from_slice(&segment.segment_type.to_le_bytes());
+            entry[4..8].copy_from_slice(&0b101u32.to_le_bytes());
+            entry[8..16].copy_from_slice(&segment.offset.to_le_bytes());
+            entry[16..24].copy_from_slice(&segment.virtual_address.to_le_bytes());
+            entry[24..32].copy_from_slice(&segment.physical_address.to_le_bytes());
+            entry[32..40].copy_from_slice(&segment.file_size.to_le_bytes());
+            entry[40..48].copy_from_slice(&segment.memory_size.to_le_bytes());
+            entry[48..56].copy_from_slice(&0x1000u64.to_le_bytes());
+        }
+
+        data
+    }
+
+    fn parse_error(data: &[u8]) -> ElfError {
+        match ElfFile::parse(data) {
+            Ok(_) => panic!("image parsed successfully"),
+            Err(err) => err,
+        }
+    }
+
+    #[test]
+    fn parses_loadable_segments() {
+        let mut data = image(
+            &[
+                load(0x100, 0x10_0000, 0x20, 0x20),
+                Segment {
+                    segment_type: 4,
+                    ..load(0, 0, 0, 0)
+                },
+                load(0x120, 0x10_1000, 0x10, 0x800),
+            ],
+            0x130,
+        );
+        data[0x100] = 0xAB;
+        data[0x12F] = 0xCD;
+
+        let elf = ElfFile::parse(&data).unwrap();
+
+        assert_eq!(elf.elf_type(), ElfType::Executable);
+        assert_eq!(elf.entry_point(), 0xFFFF_FFFF_8010_0000);
+        assert_eq!(elf.program_headers().count(), 3);
+
+        let segments: Vec<_> = elf.loadable_segments().collect();
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].0, 0);
+        assert_eq!(segments[1].0, 2);
+
+        let (_, first) = &segments[0];
+        assert_eq!(first.physical_address(), 0x10_0000);
+        assert_eq!(first.virtual_address(), 0xFFFF_FFFF_8010_0000);
+        assert_eq!(
+            first.flags(),
+            SegmentFlags::EXECUTABLE | SegmentFlags::READABLE
+        );
+        assert_eq!(elf.segment_data(first)[0], 0xAB);
+
+        let (_, second) = &segments[1];
+        assert_eq!(second.memory_size(), 0x800);
+        assert_eq!(elf.segment_data(second).len(), 0x10);
+        assert_eq!(elf.segment_data(second)[0xF], 0xCD);
+    }
+
+    #[test]
+    fn rejects_truncated_headers() {
+        let data = image(&[], HEADER_SIZE);
+        assert!(matches!(
+            parse_error(&data[..HEADER_SIZE - 1]),
+            ElfError::TooSmall
+        ));
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let mut data = image(&[], HEADER_SIZE);
+        data[1] = b'X';
+        assert!(matches!(parse_error(&data), ElfError::BadMagic));
+    }
+
+    #[test]
+    fn rejects_32_bit_images() {
+        let mut data = image(&[], HEADER_SIZE);
+        data[4] = 1;
+        assert!(matches!(parse_error(&data), ElfError::UnsupportedClass(1)));
+    }
+
+    #[test]
+    fn rejects_big_endian_images() {
+        let mut data = image(&[], HEADER_SIZE);
+        data[5] = 2;
+        assert!(matches!(
+            parse_error(&data),
+            ElfError::UnsupportedEncoding(2)
+        ));
+    }
+
+    #[test]
+    fn rejects_other_machines() {
+        let mut data = image(&[], HEADER_SIZE);
+        data[18..20].copy_from_slice(&183u16.to_le_bytes());
+        assert!(matches!(
+            parse_error(&data),
+            ElfError::UnsupportedMachine(183)
+        ));
+    }
+
+    #[test]
+    fn accepts_executables_and_shared_objects_only() {
+        let mut data = image(&[], HEADER_SIZE);
+
+        data[16..18].copy_from_slice(&FileHeader::TYPE_DYN.to_le_bytes());
+        assert_eq!(
+            ElfFile::parse(&data).unwrap().elf_type(),
+            ElfType::SharedObject
+        );
+
+        data[16..18].copy_from_slice(&1u16.to_le_bytes());
+        assert!(matches!(parse_error(&data), ElfError::UnsupportedType(1)));
+    }
+
+    #[test]
+    fn rejects_unexpected_program_header_sizes() {
+        let mut data = image(&[], HEADER_SIZE);
+        data[54..56].copy_from_slice(&32u16.to_le_bytes());
+        assert!(matches!(
+            parse_error(&data),
+            ElfError::BadProgramHeaderSize(32)
+        ));
+    }
+
+    #[test]
+    fn rejects_program_headers_beyond_the_image() {
+        let segments = [load(0, 0x10_0000, 0, 0x1000)];
+        let size = HEADER_SIZE + PROGRAM_HEADER_SIZE;
+
+        assert!(ElfFile::parse(&image(&segments, size)).is_ok());
+
+        assert!(matches!(
+            parse_error(&image(&segments, size)[..size - 1]),
+            ElfError::ProgramHeadersOutOfBounds
+        ));
+
+        let mut data = image(&segments, size);
+        data[32..40].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(
+            parse_error(&data),
+            ElfError::ProgramHeadersOutOfBounds
+        ));
+    }
+
+    #[test]
+    fn rejects_segments_larger_in_the_file_than_in_memory() {
+        let data = image(&[load(0, 0x10_0000, 0x20, 0x10)], 0x100);
+        assert!(matches!(
+            parse_error(&data),
+            ElfError::SegmentFileSizeTooLarge(0)
+        ));
+    }
+
+    #[test]
+    fn rejects_segments_beyond_the_end_of_the_image() {
+        assert!(ElfFile::parse(&image(&[load(0xF0, 0x10_0000, 0x10, 0x10)], 0x100)).is_ok());
+
+        let data = image(
+            &[
+                load(0, 0x10_0000, 0x10, 0x10),
+                load(0xF0, 0x10_1000, 0x11, 0x11),
+            ],
+            0x100,
+        );
+        assert!(matches!(
+            parse_error(&data),
+            ElfError::SegmentOutOfBounds(1)
+        ));
+
+        let data = image(&[load(u64::MAX, 0x10_0000, 1, 1)], 0x100);
+        assert!(matches!(
+            parse_error(&data),
+            ElfError::SegmentOutOfBounds(0)
+        ));
+    }
+
+    #[test]
+    fn rejects_segments_wrapping_the_address_space() {
+        let data = image(
+            &[Segment {
+                virtual_address: u64::MAX - 0xFFF,
+                ..load(0, 0x10_0000, 0, 0x1001)
+            }],
+            0x100,
+        );
+        assert!(matches!(
+            parse_error(&data),
+            ElfError::SegmentAddressOverflow(0)
+        ));
+
+        let data = image(
+            &[Segment {
+                physical_address: u64::MAX - 0xFFF,
+                ..load(0, 0x10_0000, 0, 0x1001)
+            }],
+            0x100,
+        );
+        assert!(matches!(
+            parse_error(&data),
+            ElfError::SegmentAddressOverflow(0)
+        ));
+    }
+
+    #[test]
+    fn finds_segments_requiring_relocation() {
+        let segments = [
+            load(0, 0x10_0000, 0, 0x1000),
+            load(0, 0, 0, 0),
+            load(0, 0, 0, 0x1000),
+        ];
+        let mut data = image(&segments, 0x200);
+
+        assert_eq!(
+            ElfFile::parse(&data)
+                .unwrap()
+                .segment_requiring_relocation(),
+            None
+        );
+
+        data[16..18].copy_from_slice(&FileHeader::TYPE_DYN.to_le_bytes());
+        assert_eq!(
+            ElfFile::parse(&data)
+                .unwrap()
+                .segment_requiring_relocation(),
+            Some(2)
+        );
+
+        let mut data = image(&segments[..2], 0x200);
+        data[16..18].copy_from_slice(&FileHeader::TYPE_DYN.to_le_bytes());
+        assert_eq!(
+            ElfFile::parse(&data)
+                .unwrap()
+                .segment_requiring_relocation(),
+            None
+        );
+    }
+}
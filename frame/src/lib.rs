@@ -0,0 +1,517 @@
+//! Provides the bookkeeping behind allocating frames of physical memory
+//! from the usable regions of the memory map handed over in the boot
+//! information.
+//!
+//! This works on raw physical addresses rather than the boot stub's
+//! paging types, so that it can be built and tested on the host, and so
+//! that a kernel can seed its own allocators from the memory map in the
+//! same way.
+#![cfg_attr(not(test), no_std)]
+
+use osc_os_boot_info::{MemoryRegion, MemoryRegionKind};
+
+/// The size of a frame of physical memory in bytes.
+pub const FRAME_SIZE: u64 = 4096;
+
+const BITS_PER_WORD: usize = 64;
+
+/// Constrains where in physical memory allocated frames may live.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Zone {
+    /// Below 1MiB, e.g. for the real mode code that starts other
+    /// processors.
+    Low,
+
+    /// Below 4GiB, for devices that can only address 32 bits.
+    Dma32,
+
+    /// Anywhere at all.
+    Any,
+}
+
+impl Zone {
+    /// Gets the physical address that allocations from the zone must
+    /// end at or below.
+    pub fn limit(&self) -> u64 {
+        match self {
+            Self::Low => 0x10_0000,
+            Self::Dma32 => 0x1_0000_0000,
+            Self::Any => u64::MAX,
+        }
+    }
+}
+
+/// Iterates over the usable regions of the memory map as ranges of
+/// whole frames, as `(start, end)` physical addresses.
+///
+/// NOTE: Frame zero is always left out, so that a physical address of
+/// zero can never refer to an allocated frame.
+pub fn usable_frame_ranges(regions: &[MemoryRegion]) -> impl Iterator<Item = (u64, u64)> + '_ {
+    regions
+        .iter()
+        .filter(|region| region.kind == MemoryRegionKind::USABLE)
+        .filter_map(|region| {
+            let start = align_up(region.start.max(FRAME_SIZE), FRAME_SIZE)?;
+            let end = region.end & !(FRAME_SIZE - 1);
+            Some((start, end))
+        })
+        .filter(|(start, end)| start < end)
+}
+
+/// Tracks the frames handed out from the usable regions of the memory
+/// map in address order, never reusing them.
+///
+/// Skipping forward to satisfy an alignment or zone wastes the frames
+/// skipped, and once the allocator has moved beyond a zone, it can't
+/// allocate from it again, so allocations below 1MiB should be made
+/// first.
+pub struct BumpFrames<'a> {
+    regions: &'a [MemoryRegion],
+    start: u64,
+    next: u64,
+}
+
+impl<'a> BumpFrames<'a> {
+    /// Constructs an allocator over the usable regions of the given
+    /// memory map, which must be sorted by address.
+    pub fn new(regions: &'a [MemoryRegion]) -> Self {
+        let start = usable_frame_ranges(regions)
+            .next()
+            .map(|(start, _)| start)
+            .unwrap_or(0);
+
+        Self {
+            regions,
+            start,
+            next: start,
+        }
+    }
+
+    /// Gets the range of physical memory that the allocator has moved
+    /// through, as `(start, end)` physical addresses.
+    pub fn allocated_range(&self) -> (u64, u64) {
+        (self.start, self.next)
+    }
+
+    /// Allocates `count` contiguous frames, the first of which is
+    /// aligned to `align` bytes, and all of which lie within the zone.
+    /// Returns the physical address of the first frame, or `None` if
+    /// there's no suitable run or the count is zero.
+    pub fn allocate(&mut self, count: usize, align: u64, zone: Zone) -> Option<u64> {
+        if count == 0 {
+            return None;
+        }
+
+        let align = frame_alignment(align);
+        let size = (count as u64).checked_mul(FRAME_SIZE)?;
+
+        for (start, end) in usable_frame_ranges(self.regions) {
+            if end <= self.next {
+                continue;
+            }
+
+            let candidate = align_up(start.max(self.next), align)?;
+            let candidate_end = candidate.checked_add(size)?;
+
+            if candidate_end > zone.limit() {
+                return None;
+            }
+
+            if candidate_end <= end {
+                self.next = candidate_end;
+                return Some(candidate);
+            }
+        }
+
+        None
+    }
+}
+
+/// Tracks frames using a bitmap with a bit per frame, set when the
+/// frame is in use (or isn't usable memory at all), so that frames can
+/// be freed and reused.
+pub struct BitmapFrames<'a> {
+    bitmap: &'a mut [u64],
+    frame_count: usize,
+    free_count: usize,
+    next_hint: usize,
+}
+
+impl<'a> BitmapFrames<'a> {
+    /// Gets the number of words of bitmap needed to track every usable
+    /// frame in the given memory map.
+    // NOTE: `usize::div_ceil` is newer than the toolchain the boot stub
+    // is built with.
+    #[allow(clippy::manual_div_ceil)]
+    pub fn required_words(regions: &[MemoryRegion]) -> usize {
+        let frame_count = usable_frame_ranges(regions)
+            .map(|(_, end)| (end / FRAME_SIZE) as usize)
+            .max()
+            .unwrap_or(0);
+
+        (frame_count + BITS_PER_WORD - 1) / BITS_PER_WORD
+    }
+
+    /// Constructs an allocator over the usable regions of the given
+    /// memory map, keeping track of them in the given bitmap. Usable
+    /// frames beyond the end of the bitmap are ignored.
+    pub fn new(regions: &[MemoryRegion], bitmap: &'a mut [u64]) -> Self {
+        for word in bitmap.iter_mut() {
+            *word = !0;
+        }
+
+        let mut frames = Self {
+            frame_count: bitmap.len() * BITS_PER_WORD,
+            bitmap,
+            free_count: 0,
+            next_hint: 0,
+        };
+
+        for (start, end) in usable_frame_ranges(regions) {
+            let first = (start / FRAME_SIZE) as usize;
+            let last = ((end / FRAME_SIZE) as usize).min(frames.frame_count);
+
+            for index in first..last {
+                frames.set_free(index);
+            }
+        }
+
+        frames
+    }
+
+    /// Marks the frames overlapping the given range of physical
+    /// addresses as in use, e.g. those handed out by an earlier
+    /// allocator.
+    pub fn reserve(&mut self, start: u64, end: u64) {
+        if start >= end {
+            return;
+        }
+
+        let first = (start / FRAME_SIZE) as usize;
+        let last = (((end - 1) / FRAME_SIZE) as usize + 1).min(self.frame_count);
+
+        for index in first..last {
+            if !self.is_used(index) {
+                self.set_used(index);
+            }
+        }
+    }
+
+    /// Gets the number of frames that are free.
+    pub fn free_frames(&self) -> usize {
+        self.free_count
+    }
+
+    /// Allocates a single frame, returning its physical address.
+    pub fn allocate_frame(&mut self) -> Option<u64> {
+        let index = self
+            .find_free_from(self.next_hint)
+            .or_else(|| self.find_free_from(0))?;
+
+        self.set_used(index);
+        self.next_hint = index + 1;
+
+        Some(index as u64 * FRAME_SIZE)
+    }
+
+    /// Allocates `count` contiguous frames, the first of which is
+    /// aligned to `align` bytes, and all of which lie within the zone.
+    /// Returns the physical address of the first frame, or `None` if
+    /// there's no suitable run or the count is zero.
+    pub fn allocate(&mut self, count: usize, align: u64, zone: Zone) -> Option<u64> {
+        if count == 0 {
+            return None;
+        }
+
+        let align = (frame_alignment(align) / FRAME_SIZE) as usize;
+        let limit = ((zone.limit() / FRAME_SIZE) as usize).min(self.frame_count);
+
+        let mut candidate: usize = 0;
+
+        while let Some(end) = candidate.checked_add(count).filter(|end| *end <= limit) {
+            match (candidate..end).find(|index| self.is_used(*index)) {
+                // NOTE: No run containing the used frame can work, so
+                // skip straight past it.
+                Some(used) => candidate = (used + align) & !(align - 1),
+
+                None => {
+                    for index in candidate..end {
+                        self.set_used(index);
+                    }
+
+                    return Some(candidate as u64 * FRAME_SIZE);
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Frees `count` frames starting at the given physical address.
+    ///
+    /// # Panics
+    /// Panics if any of the frames is already free.
+    pub fn deallocate(&mut self, start: u64, count: usize) {
+        let first = (start / FRAME_SIZE) as usize;
+
+        for index in first..first + count {
+            self.set_free(index);
+        }
+    }
+
+    fn is_used(&self, index: usize) -> bool {
+        self.bitmap[index / BITS_PER_WORD] & (1 << (index % BITS_PER_WORD)) != 0
+    }
+
+    fn set_used(&mut self, index: usize) {
+        debug_assert!(!self.is_used(index), "frame {:#X} already in use", index);
+
+        self.bitmap[index / BITS_PER_WORD] |= 1 << (index % BITS_PER_WORD);
+        self.free_count -= 1;
+    }
+
+    fn set_free(&mut self, index: usize) {
+        assert!(self.is_used(index), "frame {:#X} freed twice", index);
+
+        self.bitmap[index / BITS_PER_WORD] &= !(1 << (index % BITS_PER_WORD));
+        self.free_count += 1;
+    }
+
+    /// Finds a free frame at or after the given index.
+    fn find_free_from(&self, start: usize) -> Option<usize> {
+        let first_word = start / BITS_PER_WORD;
+
+        for (word_index, word) in self.bitmap.iter().enumerate().skip(first_word) {
+            // NOTE: Frames before the start in the first word are
+            // treated as used.
+            let word = if word_index == first_word {
+                word | ((1u64 << (start % BITS_PER_WORD)) - 1)
+            } else {
+                *word
+            };
+
+            if word != !0 {
+                return Some(word_index * BITS_PER_WORD + (!word).trailing_zeros() as usize);
+            }
+        }
+
+        None
+    }
+}
+
+/// Gets the alignment to use for an allocation, in bytes.
+fn frame_alignment(align: u64) -> u64 {
+    assert!(align.is_power_of_two(), "alignment must be a power of two");
+    align.max(FRAME_SIZE)
+}
+
+fn align_up(value: u64, align: u64) -> Option<u64> {
+    Some(value.checked_add(align - 1)? & !(align - 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIB: u64 = 0x10_0000;
+
+    fn region(start: u64, end: u64, kind: MemoryRegionKind) -> MemoryRegion {
+        MemoryRegion { start, end, kind }
+    }
+
+    fn usable(start: u64, end: u64) -> MemoryRegion {
+        region(start, end, MemoryRegionKind::USABLE)
+    }
+
+    #[test]
+    fn usable_ranges_are_whole_frames_above_frame_zero() {
+        let regions = [
+            usable(0, 0x3000),
+            region(0x3000, 0x8000, MemoryRegionKind::RESERVED),
+            usable(0x8800, 0xA800),
+            usable(0xB000, 0xB800),
+            usable(0x10_0000, 0x20_0000),
+        ];
+
+        let ranges: Vec<_> = usable_frame_ranges(&regions).collect();
+
+        assert_eq!(
+            ranges,
+            vec![(0x1000, 0x3000), (0x9000, 0xA000), (0x10_0000, 0x20_0000)]
+        );
+    }
+
+    #[test]
+    fn bump_allocates_in_address_order_across_regions() {
+        let regions = [usable(0x1000, 0x3000), usable(0x8000, 0xA000)];
+        let mut frames = BumpFrames::new(&regions);
+
+        assert_eq!(frames.allocate(1, FRAME_SIZE, Zone::Any), Some(0x1000));
+        assert_eq!(frames.allocate(1, FRAME_SIZE, Zone::Any), Some(0x2000));
+        assert_eq!(frames.allocate(2, FRAME_SIZE, Zone::Any), Some(0x8000));
+        assert_eq!(frames.allocate(1, FRAME_SIZE, Zone::Any), None);
+        assert_eq!(frames.allocated_range(), (0x1000, 0xA000));
+    }
+
+    #[test]
+    fn bump_skips_forward_to_satisfy_alignment() {
+        let regions = [usable(0x1000, 0x20_0000)];
+        let mut frames = BumpFrames::new(&regions);
+
+        assert_eq!(frames.allocate(1, FRAME_SIZE, Zone::Any), Some(0x1000));
+        assert_eq!(frames.allocate(2, 0x10_0000, Zone::Any), Some(0x10_0000));
+        assert_eq!(frames.allocate(1, 0x800, Zone::Any), Some(0x10_2000));
+    }
+
+    #[test]
+    fn bump_keeps_allocations_within_zones() {
+        let regions = [usable(0xF_E000, 0x10_2000)];
+        let mut frames = BumpFrames::new(&regions);
+
+        assert_eq!(frames.allocate(3, FRAME_SIZE, Zone::Low), None);
+        assert_eq!(frames.allocate(2, FRAME_SIZE, Zone::Low), Some(0xF_E000));
+        assert_eq!(frames.allocate(1, FRAME_SIZE, Zone::Low), None);
+        assert_eq!(frames.allocate(1, FRAME_SIZE, Zone::Dma32), Some(MIB));
+    }
+
+    #[test]
+    fn bump_refuses_empty_and_oversized_runs() {
+        let regions = [usable(0x1000, 0x3000)];
+        let mut frames = BumpFrames::new(&regions);
+
+        assert_eq!(frames.allocate(0, FRAME_SIZE, Zone::Any), None);
+        assert_eq!(frames.allocate(usize::MAX, FRAME_SIZE, Zone::Any), None);
+        assert_eq!(frames.allocated_range(), (0x1000, 0x1000));
+    }
+
+    #[test]
+    fn bitmap_words_cover_the_highest_usable_frame() {
+        assert_eq!(BitmapFrames::required_words(&[]), 0);
+        assert_eq!(
+            BitmapFrames::required_words(&[usable(0x1000, 64 * FRAME_SIZE)]),
+            1
+        );
+        assert_eq!(
+            BitmapFrames::required_words(&[
+                usable(0x1000, 0x2000),
+                region(0x2000, 0x100_0000, MemoryRegionKind::RESERVED),
+                usable(0x100_0000, 0x100_0000 + 65 * FRAME_SIZE),
+            ]),
+            66
+        );
+    }
+
+    #[test]
+    fn bitmap_tracks_only_usable_frames() {
+        let regions = [
+            usable(0, 0x4000),
+            region(0x4000, 0x6000, MemoryRegionKind::RESERVED),
+            usable(0x6000, 0x8000),
+        ];
+        let mut bitmap = [0; 1];
+        let mut frames = BitmapFrames::new(&regions, &mut bitmap);
+
+        assert_eq!(frames.free_frames(), 5);
+
+        let mut allocated = Vec::new();
+        while let Some(address) = frames.allocate_frame() {
+            allocated.push(address);
+        }
+
+        assert_eq!(allocated, vec![0x1000, 0x2000, 0x3000, 0x6000, 0x7000]);
+        assert_eq!(frames.free_frames(), 0);
+        assert_eq!(bitmap, [!0]);
+    }
+
+    #[test]
+    fn bitmap_ignores_frames_beyond_its_end() {
+        let regions = [usable(0x1000, 100 * FRAME_SIZE)];
+        let mut bitmap = [0; 1];
+        let mut frames = BitmapFrames::new(&regions, &mut bitmap);
+
+        assert_eq!(frames.free_frames(), 63);
+
+        frames.reserve(60 * FRAME_SIZE, 100 * FRAME_SIZE);
+        assert_eq!(frames.free_frames(), 59);
+    }
+
+    #[test]
+    fn bitmap_reserves_frames_overlapping_a_range() {
+        let regions = [usable(0x1000, 0x10000)];
+        let mut bitmap = [0; 1];
+        let mut frames = BitmapFrames::new(&regions, &mut bitmap);
+
+        frames.reserve(0x1800, 0x3001);
+        frames.reserve(0x2000, 0x3000);
+        frames.reserve(0x5000, 0x5000);
+
+        assert_eq!(frames.free_frames(), 12);
+        assert_eq!(frames.allocate_frame(), Some(0x4000));
+    }
+
+    #[test]
+    fn bitmap_reuses_freed_frames() {
+        let regions = [usable(0x1000, 0x4000)];
+        let mut bitmap = [0; 1];
+        let mut frames = BitmapFrames::new(&regions, &mut bitmap);
+
+        assert_eq!(frames.allocate(3, FRAME_SIZE, Zone::Any), Some(0x1000));
+        assert_eq!(frames.allocate_frame(), None);
+
+        frames.deallocate(0x2000, 1);
+        assert_eq!(frames.free_frames(), 1);
+        assert_eq!(frames.allocate_frame(), Some(0x2000));
+    }
+
+    #[test]
+    #[should_panic(expected = "freed twice")]
+    fn bitmap_catches_double_frees() {
+        let regions = [usable(0x1000, 0x4000)];
+        let mut bitmap = [0; 1];
+        let mut frames = BitmapFrames::new(&regions, &mut bitmap);
+
+        let address = frames.allocate_frame().unwrap();
+        frames.deallocate(address, 1);
+        frames.deallocate(address, 1);
+    }
+
+    #[test]
+    fn bitmap_skips_past_used_frames_to_the_next_aligned_run() {
+        let regions = [usable(0x1000, 0x40_0000)];
+        let mut bitmap = [0; 16];
+        let mut frames = BitmapFrames::new(&regions, &mut bitmap);
+
+        // NOTE: Frame zero is never usable, so the first run of four
+        // aligned frames starts at frame four, unless that's in use too.
+        assert_eq!(frames.allocate(2, 4 * FRAME_SIZE, Zone::Any), Some(0x4000));
+
+        frames.reserve(0x9000, 0xA000);
+        assert_eq!(frames.allocate(4, 4 * FRAME_SIZE, Zone::Any), Some(0xC000));
+        assert_eq!(frames.allocate(1, 4 * FRAME_SIZE, Zone::Any), Some(0x8000));
+
+        assert_eq!(frames.allocate(1, 0x20_0000, Zone::Any), Some(0x20_0000));
+    }
+
+    #[test]
+    fn bitmap_keeps_allocations_within_zones() {
+        let regions = [usable(0xF_E000, 0x10_2000)];
+        let mut bitmap = [0; 5];
+        let mut frames = BitmapFrames::new(&regions, &mut bitmap);
+
+        assert_eq!(frames.allocate(3, FRAME_SIZE, Zone::Low), None);
+        assert_eq!(frames.allocate(2, FRAME_SIZE, Zone::Low), Some(0xF_E000));
+        assert_eq!(frames.allocate(1, FRAME_SIZE, Zone::Low), None);
+        assert_eq!(frames.allocate(2, FRAME_SIZE, Zone::Dma32), Some(MIB));
+    }
+
+    #[test]
+    fn bitmap_refuses_empty_and_oversized_runs() {
+        let regions = [usable(0x1000, 0x4000)];
+        let mut bitmap = [0; 1];
+        let mut frames = BitmapFrames::new(&regions, &mut bitmap);
+
+        assert_eq!(frames.allocate(0, FRAME_SIZE, Zone::Any), None);
+        assert_eq!(frames.allocate(usize::MAX, FRAME_SIZE, Zone::Any), None);
+        assert_eq!(frames.free_frames(), 3);
+    }
+}
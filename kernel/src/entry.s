@@ -0,0 +1,56 @@
+# A minimal kernel, which reports over COM1 that it was entered, and
+# whether it was handed boot information it recognises, and then halts.
+#
+# The boot stub has already set up COM1, and calls the entry point with
+# the address of the boot information in rdi.
+
+    .intel_syntax noprefix
+
+    .set COM1_DATA, 0x3F8
+    .set COM1_LINE_STATUS, 0x3FD
+    .set LINE_STATUS_TRANSMIT_EMPTY, 0x20
+
+    .section .text
+    .global _start
+_start:
+    movabs rax, 0x004942534F43534F      # "OSCOSBI\0", see BOOT_INFO_MAGIC
+    lea rsi, [rip + entered_message]
+    cmp [rdi], rax
+    je 1f
+    lea rsi, [rip + bad_boot_info_message]
+
+1:
+    call write_string
+
+2:
+    cli
+    hlt
+    jmp 2b
+
+# Writes the null-terminated string at rsi to COM1.
+write_string:
+    mov cl, [rsi]
+    test cl, cl
+    jz 2f
+
+1:
+    mov dx, COM1_LINE_STATUS
+    in al, dx
+    test al, LINE_STATUS_TRANSMIT_EMPTY
+    jz 1b
+
+    mov al, cl
+    mov dx, COM1_DATA
+    out dx, al
+    inc rsi
+    jmp write_string
+
+2:
+    ret
+
+    .section .rodata
+entered_message:
+    .asciz "Kernel entered.\r\n"
+
+bad_boot_info_message:
+    .asciz "Kernel entered without recognisable boot information.\r\n"